@@ -0,0 +1,59 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::load::event_fraction;
+
+// A stretch of driving, once or repeating at an interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drive {
+    pub start: NaiveDateTime,
+    pub duration: Duration,
+    pub every: Option<Duration>,
+}
+
+// Alternator charging, usually through a DC-DC charger, at a fixed power
+// while the vehicle or boat's engine runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternator {
+    pub power: f32, // watts into the battery
+    pub drives: Vec<Drive>,
+}
+
+impl Alternator {
+    pub fn new(power: f32) -> Alternator {
+        Alternator { power, drives: Vec::new() }
+    }
+
+    // Chargers are rated in amps at the battery's voltage
+    pub fn from_amps(amps: f32, voltage: f32) -> Alternator {
+        Alternator::new(amps * voltage)
+    }
+
+    pub fn with_drive(mut self, start: NaiveDateTime, duration: Duration) -> Alternator {
+        self.drives.push(Drive { start, duration, every: None });
+        self
+    }
+
+    // Repeats every week from the first start, e.g. 2 hours on Saturdays
+    pub fn with_weekly_drive(mut self, first: NaiveDateTime, duration: Duration) -> Alternator {
+        self.drives.push(Drive { start: first, duration, every: Some(Duration::weeks(1)) });
+        self
+    }
+
+    // Average watts over the step
+    pub fn power(&self, now: NaiveDateTime, step: Duration) -> f32 {
+        let driving = self.drives.iter()
+            .map(|d| event_fraction(now, step, d.start, d.duration, d.every))
+            .sum::<f32>();
+        self.power * driving.min(1.)
+    }
+}
+
+#[test]
+fn test_weekend_drives() {
+    // 2023-01-07 is a Saturday
+    let saturday = chrono::NaiveDate::from_ymd_opt(2023, 1, 7).unwrap().and_hms_opt(10, 0, 0).unwrap();
+    let alternator = Alternator::from_amps(40., 12.).with_weekly_drive(saturday, Duration::hours(2));
+    assert_eq!(alternator.power(saturday + Duration::weeks(3), Duration::hours(1)), 480.);
+    assert_eq!(alternator.power(saturday + Duration::days(1), Duration::hours(1)), 0.);
+    assert_eq!(alternator.power(saturday + Duration::hours(1), Duration::hours(2)), 240.)
+}
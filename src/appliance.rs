@@ -0,0 +1,58 @@
+use chrono::Duration;
+
+use crate::load::Load;
+
+// Typical draw of a common appliance for building a load budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Appliance {
+    pub name: &'static str,
+    pub on_power: f32, // watts while running
+    pub off_power: f32, // watts between cycles
+    pub duty: f32, // fraction of each cycle spent running, 1 for steady loads
+    pub cycle_minutes: i64,
+    pub dc: bool, // made for the DC bus rather than mains power
+}
+
+// Rough figures, not any one model's
+pub const APPLIANCES: [Appliance; 8] = [
+    Appliance { name: "LED Light", on_power: 10., off_power: 0., duty: 1., cycle_minutes: 60, dc: false },
+    Appliance { name: "Laptop", on_power: 45., off_power: 0., duty: 1., cycle_minutes: 60, dc: false },
+    Appliance { name: "Phone Charger", on_power: 10., off_power: 0., duty: 1., cycle_minutes: 60, dc: false },
+    Appliance { name: "12 V Fridge", on_power: 45., off_power: 1., duty: 0.35, cycle_minutes: 30, dc: true },
+    Appliance { name: "Chest Freezer", on_power: 80., off_power: 0., duty: 0.4, cycle_minutes: 45, dc: false },
+    Appliance { name: "Starlink", on_power: 50., off_power: 0., duty: 1., cycle_minutes: 60, dc: false },
+    Appliance { name: "Wi-Fi Router", on_power: 8., off_power: 0., duty: 1., cycle_minutes: 60, dc: false },
+    Appliance { name: "Water Pump", on_power: 100., off_power: 0., duty: 0.1, cycle_minutes: 60, dc: false },
+];
+
+pub fn preset(name: &str) -> Option<Appliance> {
+    APPLIANCES.iter().find(|a| a.name == name).copied()
+}
+
+impl Appliance {
+    pub fn average_power(&self) -> f32 {
+        self.on_power * self.duty + self.off_power * (1. - self.duty)
+    }
+
+    pub fn load(&self) -> Load {
+        let load = if self.duty >= 1. {
+            Load::constant(self.name, self.on_power)
+        } else {
+            Load::duty_cycle(self.name, self.on_power, self.off_power, self.duty, Duration::minutes(self.cycle_minutes))
+        };
+        if self.dc { load.dc() } else { load }
+    }
+}
+
+#[test]
+fn test_appliances() {
+    let fridge = preset("12 V Fridge").unwrap();
+    assert!((fridge.average_power() - 16.4).abs() < 0.01);
+    // A step spanning whole compressor cycles sees the average draw
+    let mut state = crate::SimState::new();
+    state.step_size = Duration::hours(1);
+    assert!((fridge.load().power(&state) - fridge.average_power()).abs() < 0.01);
+    assert!(fridge.load().dc);
+    assert_eq!(preset("Starlink").unwrap().load(), Load::constant("Starlink", 50.));
+    assert_eq!(preset("Toaster"), None)
+}
@@ -0,0 +1,138 @@
+// Battery banks sharing one bus, e.g. an old bank kept alongside a new one
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryBank {
+    pub name: String,
+    pub capacity: f32, // Wh
+    pub stored: f32, // Wh
+    pub max_power: f32, // watts in or out, infinite when unlimited
+    pub minimum_soc: f32, // fraction of capacity held back from the loads
+}
+
+impl BatteryBank {
+    pub fn new(name: &str, capacity: f32) -> BatteryBank {
+        BatteryBank {
+            name: name.to_string(),
+            capacity,
+            stored: 0.,
+            max_power: f32::INFINITY,
+            minimum_soc: 0.,
+        }
+    }
+
+    pub fn with_max_power(mut self, watts: f32) -> BatteryBank {
+        self.max_power = watts;
+        self
+    }
+
+    pub fn with_minimum_soc(mut self, fraction: f32) -> BatteryBank {
+        self.minimum_soc = fraction;
+        self
+    }
+
+    // Most this bank can take (positive) or give (negative) over a step
+    fn limit(&self, charging: bool, hours: f32) -> f32 {
+        let rate = self.max_power * hours;
+        if charging {
+            (self.capacity - self.stored).max(0.).min(rate)
+        } else {
+            -(self.stored - self.minimum_soc * self.capacity).max(0.).min(rate)
+        }
+    }
+
+    fn soc(&self) -> f32 {
+        if self.capacity > 0. { self.stored / self.capacity } else { 1. }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchPolicy {
+    #[default]
+    PriorityOrder, // the first bank listed charges and discharges first
+    FillSmallestFirst, // smallest capacity first, both ways
+    Proportional, // keeps every bank at a similar state of charge
+}
+
+// Shares the step's net energy (Wh, negative when discharging) among the
+// banks, returning what none of them could take or give: positive for
+// surplus, negative for unmet demand
+pub fn dispatch(banks: &mut [BatteryBank], policy: DispatchPolicy, energy: f32, hours: f32) -> f32 {
+    let charging = energy >= 0.;
+    let mut remaining = energy;
+    match policy {
+        DispatchPolicy::PriorityOrder | DispatchPolicy::FillSmallestFirst => {
+            let mut order: Vec<usize> = (0..banks.len()).collect();
+            if policy == DispatchPolicy::FillSmallestFirst {
+                order.sort_by(|a, b| banks[*a].capacity.total_cmp(&banks[*b].capacity));
+            }
+            for i in order {
+                let share = if charging {
+                    remaining.min(banks[i].limit(true, hours))
+                } else {
+                    remaining.max(banks[i].limit(false, hours))
+                };
+                banks[i].stored += share;
+                remaining -= share;
+            }
+        },
+        DispatchPolicy::Proportional => {
+            // Banks that hit a limit drop out and the rest share what's left
+            let mut open: Vec<usize> = (0..banks.len()).collect();
+            for _ in 0..=banks.len() {
+                if remaining.abs() < 1e-6 || open.is_empty() {
+                    break;
+                }
+                let total: f32 = open.iter().map(|i| banks[*i].capacity).sum();
+                if total <= 0. {
+                    break;
+                }
+                let round = remaining;
+                open.retain(|i| {
+                    let bank = &mut banks[*i];
+                    let wanted = round * bank.capacity / total;
+                    let limit = bank.limit(charging, hours);
+                    let share = if charging { wanted.min(limit) } else { wanted.max(limit) };
+                    bank.stored += share;
+                    remaining -= share;
+                    share == wanted
+                });
+            }
+        },
+    }
+    remaining
+}
+
+pub fn state_of_charge_spread(banks: &[BatteryBank]) -> f32 {
+    let socs = banks.iter().map(|b| b.soc());
+    socs.clone().fold(f32::MIN, f32::max) - socs.fold(f32::MAX, f32::min)
+}
+
+#[test]
+fn test_priority_and_smallest_first() {
+    let mut banks = vec![BatteryBank::new("New", 1000.), BatteryBank::new("Old", 200.)];
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::PriorityOrder, 300., 1.), 0.);
+    assert_eq!((banks[0].stored, banks[1].stored), (300., 0.));
+
+    let mut banks = vec![BatteryBank::new("New", 1000.), BatteryBank::new("Old", 200.)];
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::FillSmallestFirst, 300., 1.), 0.);
+    assert_eq!((banks[0].stored, banks[1].stored), (100., 200.));
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::FillSmallestFirst, -250., 1.), 0.);
+    assert_eq!((banks[0].stored, banks[1].stored), (50., 0.));
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::FillSmallestFirst, -80., 1.), -30.)
+}
+
+#[test]
+fn test_proportional_with_power_limit() {
+    let mut banks = vec![
+        BatteryBank::new("New", 1000.),
+        BatteryBank::new("Old", 1000.).with_max_power(50.),
+    ];
+    // The old bank takes its 50 W and the new one the rest
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::Proportional, 300., 1.), 0.);
+    assert!((banks[0].stored - 250.).abs() < 0.001);
+    assert_eq!(banks[1].stored, 50.);
+
+    let mut banks = vec![BatteryBank::new("A", 100.), BatteryBank::new("B", 300.)];
+    assert_eq!(dispatch(&mut banks, DispatchPolicy::Proportional, 200., 1.), 0.);
+    assert!(state_of_charge_spread(&banks) < 0.001);
+    assert!((dispatch(&mut banks, DispatchPolicy::Proportional, 500., 1.) - 300.).abs() < 0.001)
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+// Battery types with typical datasheet behavior. Applying one sets the
+// matching SimState fields, which can still be changed afterward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chemistry {
+    LiFePO4,
+    Agm,
+    FloodedLeadAcid,
+    Nmc,
+}
+
+impl Chemistry {
+    pub const ALL: [Chemistry; 4] = [Chemistry::LiFePO4, Chemistry::Agm, Chemistry::FloodedLeadAcid, Chemistry::Nmc];
+
+    pub fn charge_efficiency(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.97,
+            Chemistry::Agm => 0.9,
+            Chemistry::FloodedLeadAcid => 0.85,
+            Chemistry::Nmc => 0.96,
+        }
+    }
+
+    pub fn discharge_efficiency(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.98,
+            Chemistry::Agm => 0.95,
+            Chemistry::FloodedLeadAcid => 0.95,
+            Chemistry::Nmc => 0.97,
+        }
+    }
+
+    // Lowest state of charge worth designing to for a reasonable cycle life
+    pub fn minimum_soc(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.2,
+            Chemistry::Agm | Chemistry::FloodedLeadAcid => 0.5,
+            Chemistry::Nmc => 0.1,
+        }
+    }
+
+    // Fraction of the stored energy lost per month at rest
+    pub fn self_discharge(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.03,
+            Chemistry::Agm => 0.03,
+            Chemistry::FloodedLeadAcid => 0.1,
+            Chemistry::Nmc => 0.02,
+        }
+    }
+
+    // State of charge where the charger moves from bulk to absorption and
+    // the current starts to taper
+    pub fn taper_soc(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.95,
+            Chemistry::Nmc => 0.9,
+            Chemistry::Agm => 0.85,
+            Chemistry::FloodedLeadAcid => 0.8,
+        }
+    }
+
+    // Lithium cells plate when charged below freezing, so their battery
+    // management systems refuse charge
+    pub fn minimum_charge_temperature(&self) -> Option<f32> {
+        match self {
+            Chemistry::LiFePO4 | Chemistry::Nmc => Some(0.),
+            Chemistry::Agm | Chemistry::FloodedLeadAcid => None,
+        }
+    }
+
+    pub fn can_charge(&self, temperature: f32) -> bool {
+        self.minimum_charge_temperature().is_none_or(|t| temperature >= t)
+    }
+
+    // Equivalent full cycles until the capacity fades to 80%
+    pub fn cycle_life(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 4000.,
+            Chemistry::Agm => 500.,
+            Chemistry::FloodedLeadAcid => 1200.,
+            Chemistry::Nmc => 1500.,
+        }
+    }
+
+    // Peukert exponent, 1 for cells whose capacity doesn't depend on the
+    // discharge rate. Lithium cells come close enough.
+    pub fn peukert_exponent(&self) -> f32 {
+        match self {
+            Chemistry::Agm => 1.12,
+            Chemistry::FloodedLeadAcid => 1.25,
+            Chemistry::LiFePO4 | Chemistry::Nmc => 1.,
+        }
+    }
+
+    // Share of the capacity, rated at the 20-hour rate, that a discharge at
+    // some multiple of that rate can deliver
+    pub fn rate_capacity(&self, rate: f32) -> f32 {
+        if rate <= 1. {
+            return 1.;
+        }
+        rate.powf(1. - self.peukert_exponent())
+    }
+
+    // Share of the rated capacity available at -20 C
+    fn cold_capacity(&self) -> f32 {
+        match self {
+            Chemistry::LiFePO4 => 0.7,
+            Chemistry::Agm | Chemistry::FloodedLeadAcid => 0.6,
+            Chemistry::Nmc => 0.75,
+        }
+    }
+
+    // Share of the rated capacity the cells can deliver at a temperature,
+    // falling linearly from the full rating at 25 C. The rest comes back as
+    // the battery warms.
+    pub fn capacity_factor(&self, temperature: f32) -> f32 {
+        if temperature >= 25. {
+            return 1.;
+        }
+        (1. - (1. - self.cold_capacity()) * (25. - temperature) / 45.).max(0.)
+    }
+}
+
+impl fmt::Display for Chemistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Chemistry::LiFePO4 => "LiFePO4",
+            Chemistry::Agm => "AGM",
+            Chemistry::FloodedLeadAcid => "Flooded Lead-Acid",
+            Chemistry::Nmc => "NMC",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[test]
+fn test_capacity_factor() {
+    assert_eq!(Chemistry::Agm.capacity_factor(30.), 1.);
+    assert!((Chemistry::Agm.capacity_factor(-20.) - 0.6).abs() < 0.0001);
+    // Lithium holds up better in the cold
+    assert!(Chemistry::LiFePO4.capacity_factor(0.) > Chemistry::FloodedLeadAcid.capacity_factor(0.));
+    assert_eq!(Chemistry::Agm.capacity_factor(-200.), 0.)
+}
+
+#[test]
+fn test_peukert() {
+    // Flooded lead-acid run flat in 5 hours instead of 20 gives about 70%
+    assert!((Chemistry::FloodedLeadAcid.rate_capacity(4.) - 0.707).abs() < 0.001);
+    assert!(Chemistry::Agm.rate_capacity(4.) > 0.8);
+    assert_eq!(Chemistry::LiFePO4.rate_capacity(4.), 1.);
+    assert_eq!(Chemistry::FloodedLeadAcid.rate_capacity(0.5), 1.)
+}
@@ -0,0 +1,216 @@
+use std::f32::consts::PI;
+
+use crate::sun::{self, Orientation, SolarPosition, TrackingMode};
+
+pub const STANDARD_IRRADIANCE: f32 = 1000.; // W/m2 at which nominal output is rated
+pub const GRASS_ALBEDO: f32 = 0.2; // typical ground cover
+pub const SNOW_ALBEDO: f32 = 0.8; // fresh snow
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SolarModel {
+    #[default]
+    SimpleCurve, // half cosine from sunrise to sunset
+    // Ineichen-Perez clear sky, attenuating with air mass, turbidity, and
+    // the site's elevation
+    Ineichen {
+        linke_turbidity: f32, // about 2 for very clean air, 3 to 4 for typical sites
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Irradiance {
+    pub global: f32, // W/m2 on a horizontal surface
+    pub direct_normal: f32, // W/m2 facing the sun
+    pub diffuse: f32, // W/m2 from the sky on a horizontal surface
+}
+
+// Kasten and Young
+pub fn air_mass(cos_zenith: f32) -> f32 {
+    let zenith = cos_zenith.clamp(-1., 1.).acos().to_degrees();
+    1. / (cos_zenith + 0.50572 * (96.07995 - zenith).powf(-1.6364))
+}
+
+// Beam transmission relative to the sun overhead at sea level, using the
+// Kasten and Young air mass in Meinel's model with Laue's correction for the
+// thinner air at elevation (m)
+pub fn air_mass_factor(cos_zenith: f32, elevation: f32) -> f32 {
+    if cos_zenith <= 0. {
+        0.
+    } else {
+        let above = (0.14 * elevation.max(0.) / 1000.).min(1.);
+        ((1. - above) * 0.7_f32.powf(air_mass(cos_zenith).powf(0.678)) + above) / 0.7
+    }
+}
+
+pub fn ineichen(cos_zenith: f32, day_of_year: u32, solar_constant: f32, linke_turbidity: f32, elevation: f32) -> Irradiance {
+    if cos_zenith <= 0. {
+        return Irradiance { global: 0., direct_normal: 0., diffuse: 0. };
+    }
+    let extraterrestrial = solar_constant * (1. + 0.033 * (2. * PI * day_of_year as f32 / 365.).cos());
+    let am = air_mass(cos_zenith);
+    let fh1 = (-elevation / 8000.).exp();
+    let fh2 = (-elevation / 1250.).exp();
+    let cg1 = 5.09e-5 * elevation + 0.868;
+    let cg2 = 3.92e-5 * elevation + 0.0387;
+    let global = cg1 * extraterrestrial * cos_zenith
+        * (-cg2 * am * (fh1 + fh2 * (linke_turbidity - 1.))).exp()
+        * (0.01 * am.powf(1.8)).exp();
+    let b = 0.664 + 0.163 / fh1;
+    let direct_normal = (b * extraterrestrial * (-0.09 * am * (linke_turbidity - 1.)).exp())
+        .min(global / cos_zenith);
+    Irradiance {
+        global,
+        direct_normal,
+        diffuse: global - direct_normal * cos_zenith,
+    }
+}
+
+impl Irradiance {
+    // All direct light, as the idealized curve assumes
+    pub fn beam(direct_normal: f32, cos_zenith: f32) -> Irradiance {
+        Irradiance { global: direct_normal * cos_zenith.max(0.), direct_normal, diffuse: 0. }
+    }
+}
+
+// Clearness index, global over extraterrestrial horizontal, of a clear day
+const CLEAR_INDEX: f32 = 0.75;
+
+// Share of global horizontal that arrives diffuse (Erbs)
+pub fn diffuse_fraction(clearness_index: f32) -> f32 {
+    let kt = clearness_index;
+    if kt <= 0.22 {
+        1. - 0.09 * kt
+    } else if kt <= 0.8 {
+        0.9511 - 0.1604 * kt + 4.388 * kt.powi(2) - 16.638 * kt.powi(3) + 12.336 * kt.powi(4)
+    } else {
+        0.165
+    }
+}
+
+// Clear sky dimmed to `clearness`. Clouds scatter the beam into the sky, so
+// an overcast day is almost entirely diffuse.
+pub fn cloudy(sky: Irradiance, clearness: f32, cos_zenith: f32) -> Irradiance {
+    if clearness >= 1. {
+        return sky;
+    }
+    let clear = diffuse_fraction(CLEAR_INDEX);
+    let scattered = ((diffuse_fraction(CLEAR_INDEX * clearness) - clear) / (1. - clear)).clamp(0., 1.);
+    let direct_normal = sky.direct_normal * clearness * (1. - scattered);
+    let global = sky.global * clearness;
+    Irradiance {
+        global,
+        direct_normal,
+        diffuse: (global - direct_normal * cos_zenith.max(0.)).max(0.),
+    }
+}
+
+// W/m2 on the panel: the beam, the share of isotropic sky diffuse it can see,
+// and light reflected off the ground in front of it. A panel without an
+// orientation always faces the sun and sees no ground.
+pub fn plane_of_array(
+    position: SolarPosition,
+    orientation: Option<Orientation>,
+    tracking: TrackingMode,
+    sky: Irradiance,
+    albedo: f32,
+) -> f32 {
+    let cos_zenith = position.cos_zenith();
+    if cos_zenith <= 0. {
+        return 0.;
+    }
+    let (beam, cos_tilt, ground) = match orientation {
+        Some(orientation) => (
+            sun::tracking_factor(position, orientation, tracking),
+            cos_tilt(orientation, tracking, cos_zenith),
+            albedo,
+        ),
+        // The idealized panel only collects from the sky
+        None => (1., cos_zenith, 0.),
+    };
+    sky.direct_normal * beam
+        + sky.diffuse * (1. + cos_tilt) / 2.
+        + sky.global * ground * (1. - cos_tilt) / 2.
+}
+
+// W/m2 on the back of a panel, which faces mostly the ground and the sky
+// behind it. Ignores the shade of the array itself on the ground.
+pub fn rear_irradiance(
+    position: SolarPosition,
+    orientation: Option<Orientation>,
+    tracking: TrackingMode,
+    sky: Irradiance,
+    albedo: f32,
+) -> f32 {
+    let cos_zenith = position.cos_zenith();
+    match orientation {
+        Some(orientation) if cos_zenith > 0. => {
+            let cos_tilt = cos_tilt(orientation, tracking, cos_zenith);
+            sky.global * albedo * (1. + cos_tilt) / 2. + sky.diffuse * (1. - cos_tilt) / 2.
+        }
+        _ => 0.,
+    }
+}
+
+fn cos_tilt(orientation: Orientation, tracking: TrackingMode, cos_zenith: f32) -> f32 {
+    match tracking {
+        TrackingMode::Fixed => orientation.tilt.to_radians().cos(),
+        // A tracker tilts over about as far as the sun is from overhead
+        _ => cos_zenith,
+    }
+}
+
+#[test]
+fn test_air_mass() {
+    assert!((air_mass(1.) - 1.).abs() < 0.001);
+    assert!((air_mass(0.5) - 2.).abs() < 0.01);
+    // Close to the horizon the curved atmosphere caps the air mass near 38
+    assert!(air_mass(0.) > 35. && air_mass(0.) < 40.);
+    assert!((air_mass_factor(1., 0.) - 1.).abs() < 0.001);
+    assert!(air_mass_factor(0.1, 0.) < 0.5 && air_mass_factor(0.5, 0.) > 0.8);
+    // At 3000 m the overhead sun is almost a fifth stronger
+    assert!((air_mass_factor(1., 3000.) - 1.18).abs() < 0.01);
+    assert!(air_mass_factor(0.3, 3000.) > 1.15 * air_mass_factor(0.3, 0.))
+}
+
+#[test]
+fn test_ineichen() {
+    // Equatorial noon at sea level in average air
+    let noon = ineichen(1., 80, 1361., 3., 0.);
+    assert!(noon.global > 950. && noon.global < 1100.);
+    assert!(noon.direct_normal > 800. && noon.diffuse > 50.);
+    // The beam from a low sun crosses far more air
+    let low = ineichen(0.1, 80, 1361., 3., 0.);
+    assert!(low.direct_normal < 0.3 * noon.direct_normal);
+    // Cleaner, thinner air lets more through
+    assert!(ineichen(0.5, 80, 1361., 2., 2000.).global > ineichen(0.5, 80, 1361., 4., 0.).global);
+    assert_eq!(ineichen(-0.1, 80, 1361., 3., 0.).global, 0.)
+}
+
+#[test]
+fn test_cloudy() {
+    let clear = ineichen(0.8, 172, 1361., 3., 0.);
+    assert_eq!(cloudy(clear, 1., 0.8), clear);
+    let overcast = cloudy(clear, 0.25, 0.8);
+    assert!((overcast.global - 0.25 * clear.global).abs() < 0.01);
+    assert!(overcast.diffuse > 0.95 * overcast.global);
+    let partly = cloudy(clear, 0.6, 0.8);
+    assert!(partly.diffuse > 0.5 * partly.global && partly.direct_normal > 0.)
+}
+
+#[test]
+fn test_ground_reflection() {
+    let sky = Irradiance { global: 400., direct_normal: 500., diffuse: 150. };
+    let now = chrono::NaiveDate::from_ymd_opt(2023, 1, 15).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let sun = sun::solar_position(now, 60., 0.);
+    let wall = Some(Orientation::new(90., 180.));
+    let grass = plane_of_array(sun, wall, TrackingMode::Fixed, sky, GRASS_ALBEDO);
+    let snow = plane_of_array(sun, wall, TrackingMode::Fixed, sky, SNOW_ALBEDO);
+    // A vertical panel sees half the ground
+    assert!((snow - grass - 400. * 0.6 / 2.).abs() < 0.01);
+    // A flat one sees none of it
+    let flat = Some(Orientation::new(0., 180.));
+    assert_eq!(
+        plane_of_array(sun, flat, TrackingMode::Fixed, sky, GRASS_ALBEDO),
+        plane_of_array(sun, flat, TrackingMode::Fixed, sky, SNOW_ALBEDO),
+    )
+}
@@ -0,0 +1,102 @@
+use chrono::NaiveDateTime;
+
+use crate::{SimState, chart};
+use crate::series::interpolate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    StateOfCharge, // Wh, compared against charge_history
+    SolarPower, // watts, compared against solar_history
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub dates: Vec<NaiveDateTime>,
+    pub simulated: Vec<f32>,
+    pub measured: Vec<f32>,
+    pub rmse: f32,
+    pub mae: f32,
+    pub bias: f32, // mean of simulated minus measured
+}
+
+// Aligns a measured series with a finished run by interpolating the
+// measurements at each simulated step. Steps outside the measured range are
+// left out.
+pub fn compare(state: &SimState, quantity: Quantity, measured: &[(NaiveDateTime, f32)]) -> Comparison {
+    let simulated_history = match quantity {
+        Quantity::StateOfCharge => &state.charge_history,
+        Quantity::SolarPower => &state.solar_history,
+    };
+    let mut comparison = Comparison {
+        dates: Vec::new(),
+        simulated: Vec::new(),
+        measured: Vec::new(),
+        rmse: 0.,
+        mae: 0.,
+        bias: 0.,
+    };
+    for (date, simulated) in state.history_dates.iter().zip(simulated_history) {
+        if let Some(value) = interpolate(measured, *date) {
+            comparison.dates.push(*date);
+            comparison.simulated.push(*simulated);
+            comparison.measured.push(value);
+        }
+    }
+
+    let n = comparison.dates.len() as f32;
+    if n > 0. {
+        let errors: Vec<f32> = comparison.simulated.iter().zip(&comparison.measured)
+            .map(|(s, m)| s - m)
+            .collect();
+        comparison.bias = errors.iter().sum::<f32>() / n;
+        comparison.mae = errors.iter().map(|e| e.abs()).sum::<f32>() / n;
+        comparison.rmse = (errors.iter().map(|e| e * e).sum::<f32>() / n).sqrt();
+    }
+    comparison
+}
+
+impl Comparison {
+    // Plots the aligned series, skipped when none of the run overlaps the
+    // measurements
+    pub fn chart(&self, title: Option<String>) {
+        if self.dates.is_empty() {
+            return;
+        }
+        chart(
+            self.dates.clone(),
+            vec![self.simulated.clone(), self.measured.clone()],
+            Vec::new(),
+            vec!["Simulated".to_string(), "Measured".to_string()],
+            Some(title.unwrap_or_else(|| "Simulated vs Measured".to_string())),
+            true);
+    }
+}
+
+#[test]
+fn test_compare_metrics() {
+    let mut state = SimState::new();
+    let start = state.now;
+    for (i, soc) in [10., 20., 30., 40.].iter().enumerate() {
+        state.history_dates.push(start + chrono::Duration::hours(i as i64));
+        state.charge_history.push(*soc);
+    }
+    // Measured is 2 Wh low, then 2 Wh high, and stops before the last step
+    let measured = vec![
+        (start, 8.),
+        (start + chrono::Duration::hours(1), 22.),
+        (start + chrono::Duration::hours(2), 28.),
+    ];
+    let comparison = compare(&state, Quantity::StateOfCharge, &measured);
+    assert_eq!(comparison.dates.len(), 3);
+    assert!((comparison.rmse - 2.).abs() < 0.0001);
+    assert!((comparison.mae - 2.).abs() < 0.0001);
+    assert!((comparison.bias - 2. / 3.).abs() < 0.0001)
+}
+
+#[test]
+fn test_chart_without_overlap() {
+    let state = SimState::new();
+    let comparison = compare(&state, Quantity::SolarPower, &[]);
+    assert!(comparison.dates.is_empty());
+    comparison.chart(None)
+}
@@ -0,0 +1,49 @@
+use crate::generator::Generator;
+
+// How a backup generator shares the work with the battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dispatcher {
+    // Once started the generator runs near full output, charging the
+    // battery with whatever the loads don't take, until the battery reaches
+    // the generator's stop state of charge. Fewer, longer, efficient runs.
+    #[default]
+    CycleCharging,
+    // The generator only covers the loads the battery can't, running while
+    // the battery is below the start state of charge. Solar does all the
+    // charging, but the generator idles along at part load.
+    LoadFollowing,
+}
+
+impl Dispatcher {
+    pub const ALL: [Dispatcher; 2] = [Dispatcher::CycleCharging, Dispatcher::LoadFollowing];
+
+    // Wh the generator delivers over the step, given the deficit the other
+    // sources leave and the room left in the battery, both at the bus
+    pub fn generator_output(&self, generator: &Generator, deficit: f32, room: f32, hours: f32) -> f32 {
+        let wanted = match self {
+            Dispatcher::CycleCharging => deficit.max(0.) + room.max(0.),
+            Dispatcher::LoadFollowing => deficit.max(0.),
+        };
+        (generator.power * hours).min(wanted)
+    }
+
+    // Whether the generator runs over the next step, from the state of
+    // charge (as a fraction) at its end
+    pub fn generator_running(&self, generator: &Generator, was_running: bool, soc: f32) -> bool {
+        match self {
+            Dispatcher::CycleCharging if was_running => soc < generator.stop_soc,
+            Dispatcher::CycleCharging | Dispatcher::LoadFollowing => soc < generator.start_soc,
+        }
+    }
+}
+
+#[test]
+fn test_dispatch_output() {
+    let generator = Generator::new(1000., 0.3, 0.9, 1.);
+    assert_eq!(Dispatcher::CycleCharging.generator_output(&generator, 200., 500., 1.), 700.);
+    assert_eq!(Dispatcher::LoadFollowing.generator_output(&generator, 200., 500., 1.), 200.);
+    assert_eq!(Dispatcher::CycleCharging.generator_output(&generator, 800., 500., 1.), 1000.);
+    // Load following stops as soon as the battery is back over the start level
+    assert!(Dispatcher::CycleCharging.generator_running(&generator, true, 0.5));
+    assert!(!Dispatcher::LoadFollowing.generator_running(&generator, true, 0.5))
+}
@@ -0,0 +1,81 @@
+use chrono::Datelike;
+
+use crate::{SimState, step_hours};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmissionFactors {
+    pub grid_intensity: f32, // kg CO2 per kWh imported, or displaced by solar
+}
+
+impl Default for EmissionFactors {
+    // Roughly the US grid average
+    fn default() -> Self {
+        EmissionFactors { grid_intensity: 0.39 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Emissions {
+    pub solar_offset: f32, // kg CO2 avoided by solar energy that reached the loads
+    pub grid: f32, // kg CO2 from grid imports
+}
+
+impl Emissions {
+    pub fn net(&self) -> f32 {
+        self.grid - self.solar_offset
+    }
+
+    fn add(&mut self, other: Emissions) {
+        self.solar_offset += other.solar_offset;
+        self.grid += other.grid;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmissionsReport {
+    pub monthly: Vec<(i32, u32, Emissions)>, // year, month, totals
+    pub annual: Vec<(i32, Emissions)>,
+}
+
+// Load served by anything but the grid is counted as displaced grid energy
+pub fn emissions(result: &SimState, factors: EmissionFactors) -> EmissionsReport {
+    let hours = step_hours(result);
+    let mut monthly: Vec<(i32, u32, Emissions)> = Vec::new();
+    for (i, date) in result.history_dates.iter().enumerate() {
+        let imported = result.grid_import_history.get(i).cloned().unwrap_or(0.);
+        let served = result.served_load_history[i] * hours - result.unmet_history[i];
+        let step = Emissions {
+            solar_offset: (served - imported).max(0.) / 1000. * factors.grid_intensity,
+            grid: imported / 1000. * factors.grid_intensity,
+        };
+        match monthly.last_mut() {
+            Some((year, month, total)) if *year == date.year() && *month == date.month() => total.add(step),
+            _ => monthly.push((date.year(), date.month(), step)),
+        }
+    }
+    let mut annual: Vec<(i32, Emissions)> = Vec::new();
+    for (year, _, month) in &monthly {
+        match annual.last_mut() {
+            Some((y, total)) if y == year => total.add(*month),
+            _ => annual.push((*year, *month)),
+        }
+    }
+    EmissionsReport { monthly, annual }
+}
+
+#[test]
+fn test_emissions() {
+    let mut state = SimState::new();
+    state.step_size = chrono::Duration::hours(1);
+    let start = crate::simulation_start(&state);
+    state.history_dates = vec![start, start + chrono::Duration::days(40)];
+    state.served_load_history = vec![1000., 1000.];
+    state.unmet_history = vec![0., 0.];
+    state.grid_import_history = vec![0., 500.];
+    let report = emissions(&state, EmissionFactors { grid_intensity: 0.5 });
+    assert_eq!(report.monthly.len(), 2);
+    assert_eq!(report.monthly[0].2, Emissions { solar_offset: 0.5, grid: 0. });
+    assert_eq!(report.monthly[1].2, Emissions { solar_offset: 0.25, grid: 0.25 });
+    assert_eq!(report.annual, vec![(2023, Emissions { solar_offset: 0.75, grid: 0.25 })]);
+    assert_eq!(report.annual[0].1.net(), -0.5)
+}
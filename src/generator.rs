@@ -0,0 +1,97 @@
+use chrono::Datelike;
+
+use crate::{SimState, step_hours};
+
+// Backup generator that starts when the battery runs low and charges it
+// back up, as in a hybrid generator/solar system
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Generator {
+    pub power: f32, // watts, the most it delivers to the loads and battery
+    pub start_soc: f32, // starts below this state of charge
+    pub stop_soc: f32, // runs until the battery is charged to this state of charge
+    pub fuel_rate: f32, // liters per hour at full output
+    pub fuel_price: f32, // per liter
+}
+
+impl Generator {
+    pub fn new(power: f32, start_soc: f32, stop_soc: f32, fuel_rate: f32) -> Generator {
+        Generator { power, start_soc, stop_soc, fuel_rate, fuel_price: 0. }
+    }
+
+    pub fn with_fuel_price(mut self, price: f32) -> Generator {
+        self.fuel_price = price;
+        self
+    }
+
+    // Liters burned running for some hours. A generator still burns about a
+    // quarter of its full rate with no load, and the rest in proportion to
+    // its output.
+    pub fn fuel(&self, output: f32, hours: f32) -> f32 {
+        if self.power <= 0. {
+            return 0.;
+        }
+        let load_fraction = (output / self.power).clamp(0., 1.);
+        self.fuel_rate * (0.25 + 0.75 * load_fraction) * hours
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GeneratorUse {
+    pub starts: u32,
+    pub runtime: f32, // hours
+    pub fuel: f32, // liters
+    pub cost: f32, // at the generator's fuel price
+}
+
+// Generator use in each month of a finished run, as (year, month, use)
+pub fn monthly_use(result: &SimState) -> Vec<(i32, u32, GeneratorUse)> {
+    let hours = step_hours(result);
+    let price = result.generator.map_or(0., |g| g.fuel_price);
+    let mut monthly: Vec<(i32, u32, GeneratorUse)> = Vec::new();
+    for (i, date) in result.history_dates.iter().enumerate() {
+        let fuel = result.fuel_history.get(i).cloned().unwrap_or(0.);
+        let month = match monthly.last_mut() {
+            Some((year, month, total)) if *year == date.year() && *month == date.month() => total,
+            _ => {
+                monthly.push((date.year(), date.month(), GeneratorUse::default()));
+                &mut monthly.last_mut().unwrap().2
+            },
+        };
+        if fuel > 0. {
+            month.runtime += hours;
+        }
+        month.fuel += fuel;
+        month.cost += fuel * price;
+    }
+    for start in &result.generator_starts {
+        if let Some((_, _, month)) = monthly.iter_mut()
+            .find(|(year, month, _)| *year == start.year() && *month == start.month()) {
+            month.starts += 1;
+        }
+    }
+    monthly
+}
+
+#[test]
+fn test_fuel() {
+    let generator = Generator::new(2000., 0.3, 0.9, 1.2);
+    assert!((generator.fuel(2000., 2.) - 2.4).abs() < 0.0001);
+    assert!((generator.fuel(0., 1.) - 0.3).abs() < 0.0001);
+    assert!((generator.fuel(1000., 1.) - 0.75).abs() < 0.0001)
+}
+
+#[test]
+fn test_monthly_use() {
+    let mut state = SimState::new();
+    state.step_size = chrono::Duration::hours(1);
+    state.generator = Some(Generator::new(2000., 0.3, 0.9, 1.).with_fuel_price(1.5));
+    let start = crate::simulation_start(&state);
+    let february = start + chrono::Duration::days(40);
+    state.history_dates = vec![start, start + chrono::Duration::hours(1), february];
+    state.fuel_history = vec![1., 0.5, 2.];
+    state.generator_starts = vec![start, february];
+    let report = monthly_use(&state);
+    assert_eq!(report.len(), 2);
+    assert_eq!(report[0], (2023, 1, GeneratorUse { starts: 1, runtime: 2., fuel: 1.5, cost: 2.25 }));
+    assert_eq!(report[1].2.cost, 3.)
+}
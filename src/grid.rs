@@ -0,0 +1,301 @@
+use chrono::{Datelike, Duration, NaiveDateTime};
+
+use crate::SimState;
+use crate::outage::{Component, FailureGenerator, FailureMode, Outage};
+use crate::tariff::{self, Tariff};
+
+// Random outages, e.g. one 8 hour outage a month in winter
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutagePattern {
+    pub per_month: f32, // average outages per active month
+    pub duration: Duration,
+    pub months: Vec<u32>, // 1 to 12, every month when empty
+}
+
+impl OutagePattern {
+    pub fn new(per_month: f32, duration: Duration, months: Vec<u32>) -> OutagePattern {
+        OutagePattern { per_month, duration, months }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GridMode {
+    // The grid carries any deficit, leaving the battery full for outages
+    #[default]
+    Backup,
+    // The battery serves the loads first and the grid only what it can't,
+    // as for a grid-tied battery bought to use more of its own solar
+    SelfConsumption,
+    // Under a time-of-use tariff, charges the battery from the grid while
+    // the rate is at or below charge_below, serves the loads from it while
+    // the rate is at or above discharge_above, and holds it in between
+    Arbitrage { charge_below: f32, discharge_above: f32 },
+}
+
+// Utility connection. While the grid is up it takes any surplus the battery
+// can't, up to the export limit, and covers deficits as its mode decides.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Grid {
+    pub scheduled: Vec<(NaiveDateTime, Duration)>, // known outage windows
+    pub random: Vec<OutagePattern>,
+    pub seed: u64,
+    pub mode: GridMode,
+    pub export_limit: Option<f32>, // watts, unlimited when None
+    pub tariff: Option<Tariff>, // what the arbitrage mode trades on
+    pub backup_reserve: f32, // fraction of the battery kept for outages while the grid is up
+}
+
+impl Grid {
+    pub fn new() -> Grid {
+        Grid::default()
+    }
+
+    pub fn self_consumption() -> Grid {
+        Grid { mode: GridMode::SelfConsumption, ..Grid::default() }
+    }
+
+    pub fn with_export_limit(mut self, watts: f32) -> Grid {
+        self.export_limit = Some(watts);
+        self
+    }
+
+    // Outage or demand-response window in which the house is islanded
+    pub fn with_outage(mut self, start: NaiveDateTime, duration: Duration) -> Grid {
+        self.scheduled.push((start, duration));
+        self
+    }
+
+    pub fn with_backup_reserve(mut self, reserve: f32) -> Grid {
+        self.backup_reserve = reserve;
+        self
+    }
+
+    pub fn arbitrage(tariff: Tariff, charge_below: f32, discharge_above: f32) -> Grid {
+        Grid {
+            mode: GridMode::Arbitrage { charge_below, discharge_above },
+            tariff: Some(tariff),
+            ..Grid::default()
+        }
+    }
+
+    // Whether the grid covers deficits instead of the battery at a time.
+    // Arbitrage without a tariff works as self-consumption.
+    pub fn holds_battery(&self, now: NaiveDateTime) -> bool {
+        match (self.mode, &self.tariff) {
+            (GridMode::Backup, _) => true,
+            (GridMode::SelfConsumption, _) | (GridMode::Arbitrage { .. }, None) => false,
+            (GridMode::Arbitrage { discharge_above, .. }, Some(tariff)) => tariff.rate_at(now) < discharge_above,
+        }
+    }
+
+    pub fn charges_battery(&self, now: NaiveDateTime) -> bool {
+        match (self.mode, &self.tariff) {
+            (GridMode::Arbitrage { charge_below, .. }, Some(tariff)) => tariff.rate_at(now) <= charge_below,
+            _ => false,
+        }
+    }
+
+    pub fn outages(&self, start: NaiveDateTime, end: NaiveDateTime) -> Vec<Outage> {
+        let mut outages: Vec<Outage> = self.scheduled.iter()
+            .map(|(start, duration)| Outage::new(Component::Grid, *start, *duration))
+            .collect();
+        for (i, pattern) in self.random.iter().enumerate() {
+            // Drawn all year at the monthly rate, keeping the active months
+            let mode = FailureMode::new(Component::Grid, 12. * pattern.per_month, pattern.duration);
+            let generator = FailureGenerator::new(vec![mode], self.seed.wrapping_add(i as u64));
+            outages.extend(generator.generate(start, end).into_iter()
+                .filter(|o| pattern.months.is_empty() || pattern.months.contains(&o.start.month())));
+        }
+        outages.sort_by_key(|o| o.start);
+        outages
+    }
+}
+
+// Yearly savings of the configured system over the same site with no
+// battery, both priced under the grid's tariff
+pub fn arbitrage_savings(state: &SimState) -> Vec<(i32, f32)> {
+    let Some(tariff) = state.grid.as_ref().and_then(|g| g.tariff.clone()) else {
+        return Vec::new();
+    };
+    let mut without = state.clone();
+    without.battery_capacity = 0.;
+    without.banks = Vec::new();
+    let net = |result: &SimState| tariff::energy_costs(result, &tariff).annual.into_iter()
+        .map(|(year, cost, revenue)| (year, cost - revenue))
+        .collect::<Vec<_>>();
+    let (with, without) = (net(&crate::run_simulation(state)), net(&crate::run_simulation(&without)));
+    with.iter().zip(&without)
+        .map(|((year, with), (_, without))| (*year, without - with))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RideThrough {
+    pub outage: Outage,
+    pub unmet_energy: f32, // Wh
+    pub minimum_charge: f32, // Wh
+    pub backup_hours: f32, // from the start of the outage until the first unmet load, all of it if survived
+}
+
+impl RideThrough {
+    pub fn survived(&self) -> bool {
+        self.unmet_energy <= 0.001
+    }
+}
+
+// How the battery and array fared through each grid outage in a finished run
+pub fn ride_through(result: &SimState) -> Vec<RideThrough> {
+    result.outage_events.iter()
+        .filter(|o| o.component == Component::Grid)
+        .filter_map(|outage| {
+            let steps: Vec<usize> = (0..result.history_dates.len())
+                .filter(|i| outage.covers(result.history_dates[*i]))
+                .collect();
+            if steps.is_empty() {
+                return None;
+            }
+            let carried = steps.iter().take_while(|i| result.unmet_history[**i] <= 0.001).count();
+            Some(RideThrough {
+                outage: *outage,
+                unmet_energy: steps.iter().map(|i| result.unmet_history[*i]).sum(),
+                minimum_charge: steps.iter().map(|i| result.charge_history[*i]).fold(f32::MAX, f32::min),
+                backup_hours: carried as f32 * crate::step_hours(result),
+            })
+        })
+        .collect()
+}
+
+// Ride-through of the grid's outages with each backup reserve, for choosing
+// how much of a self-consumption battery to hold back
+pub fn reserve_sweep(state: &SimState, reserves: &[f32]) -> Vec<(f32, Vec<RideThrough>)> {
+    reserves.iter()
+        .map(|reserve| {
+            let mut trial = state.clone();
+            if let Some(grid) = &mut trial.grid {
+                grid.backup_reserve = *reserve;
+            }
+            (*reserve, ride_through(&crate::run_simulation(&trial)))
+        })
+        .collect()
+}
+
+#[test]
+fn test_winter_outages() {
+    let start = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let end = start + Duration::days(365 * 10);
+    let mut grid = Grid::new();
+    grid.random = vec![OutagePattern::new(1., Duration::hours(8), vec![12, 1, 2])];
+    grid.scheduled = vec![(start + Duration::days(200), Duration::hours(4))];
+    let outages = grid.outages(start, end);
+    assert!(outages.iter().all(|o| o.component == Component::Grid));
+    assert!(outages.iter().any(|o| o.start == start + Duration::days(200)));
+    let winter = outages.iter().filter(|o| [12, 1, 2].contains(&o.start.month())).count();
+    assert_eq!(winter, outages.len() - 1);
+    assert!((winter as f32 / 30. - 1.).abs() < 0.35)
+}
+
+#[test]
+fn test_self_consumption() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 1000.;
+    state.battery_capacity = 2000.;
+    state.load = 100.;
+    state.grid = Some(Grid::new());
+    let backup = crate::run_simulation(&state);
+    state.grid = Some(Grid::self_consumption());
+    let own_use = crate::run_simulation(&state);
+    // Drawing the battery overnight instead of holding it full cuts imports
+    let imported = |r: &SimState| r.grid_import_history.iter().sum::<f32>();
+    assert!(imported(&own_use) < imported(&backup));
+    assert!(own_use.charge_history.iter().any(|c| *c < 1000.));
+    assert_eq!(own_use.unmet_history.iter().sum::<f32>(), 0.);
+    // Export beyond the limit is clipped
+    state.grid = Some(Grid::self_consumption().with_export_limit(200.));
+    let limited = crate::run_simulation(&state);
+    assert!(limited.grid_export_history.iter().all(|e| *e <= 200.));
+    assert!(limited.clipped_energy_history.iter().sum::<f32>() > 0.)
+}
+
+#[test]
+fn test_arbitrage() {
+    use chrono::Timelike;
+    use crate::tariff::TouPeriod;
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 3000.;
+    state.max_charge_power = 1000.;
+    state.load = 200.;
+    // Cheap overnight, dear in the evening
+    let tariff = Tariff::flat(0.25, 0.)
+        .with_period(TouPeriod::new(0, 6, 0.1))
+        .with_period(TouPeriod::new(16, 22, 0.5));
+    state.grid = Some(Grid::arbitrage(tariff, 0.1, 0.5));
+    let result = crate::run_simulation(&state);
+    for (i, date) in result.history_dates.iter().enumerate() {
+        let hour = date.hour();
+        if (16..22).contains(&hour) && i > 24 {
+            assert_eq!(result.grid_import_history[i], 0.);
+        }
+        if (6..16).contains(&hour) {
+            // Held for the evening
+            assert_eq!(result.charge_history[i + 1], result.charge_history[i]);
+        }
+    }
+    let savings = arbitrage_savings(&state);
+    assert_eq!(savings.len(), 1);
+    // 1200 Wh a day moved from 0.5 to 0.1, less filling the empty battery
+    // on the first night
+    assert!((savings[0].1 - (3. * 1.2 * 0.4 - 1.8 * 0.1)).abs() < 0.01)
+}
+
+#[test]
+fn test_backup_reserve() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(9);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 400.;
+    state.battery_capacity = 5000.;
+    state.load = 100.;
+    // Two days without power in January, starting the evening of the 7th
+    let outage = crate::simulation_start(&state) + Duration::hours(24 * 6 + 18);
+    state.grid = Some(Grid::self_consumption().with_outage(outage, Duration::days(2)));
+    let sweep = reserve_sweep(&state, &[0., 0.5, 1.]);
+    let hours: Vec<f32> = sweep.iter().map(|(_, r)| r[0].backup_hours).collect();
+    assert!(hours[0] < hours[1] && hours[1] <= hours[2]);
+    assert!(!sweep[0].1[0].survived());
+    // Holding the reserve means importing more before the outage
+    let mut kept = state.clone();
+    kept.grid = Some(kept.grid.unwrap().with_backup_reserve(0.5));
+    let imports = |s: &SimState| crate::run_simulation(s).grid_import_history.iter().sum::<f32>();
+    assert!(imports(&kept) > imports(&state))
+}
+
+#[test]
+fn test_ride_through_sizing() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 200.;
+    state.load = 50.;
+    let evening = crate::simulation_start(&state) + Duration::hours(44);
+    let mut grid = Grid::new();
+    grid.scheduled = vec![(evening, Duration::hours(8))];
+    state.grid = Some(grid);
+
+    state.battery_capacity = 100.;
+    let small = crate::run_simulation(&state);
+    let report = ride_through(&small);
+    assert_eq!(report.len(), 1);
+    assert!(!report[0].survived());
+    // Outside the outage the grid carries the load
+    let unmet: f32 = small.unmet_history.iter().sum();
+    assert!((unmet - report[0].unmet_energy).abs() < 0.001);
+    assert!(small.grid_import_history.iter().sum::<f32>() > 0.);
+
+    state.battery_capacity = 1000.;
+    assert!(ride_through(&crate::run_simulation(&state))[0].survived())
+}
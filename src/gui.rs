@@ -5,7 +5,7 @@ use iced::{
     alignment::{Horizontal, Vertical, Alignment},
     Length,
     Command,
-    widget::{column, container, horizontal_rule, radio, row, scrollable, text} 
+    widget::{button, checkbox, column, container, horizontal_rule, pick_list, radio, row, scrollable, text, text_input} 
 };
 use plotters_iced::{Chart, ChartWidget, DrawingBackend, ChartBuilder};
 use plotters::coord::types::RangedDateTime;
@@ -14,15 +14,85 @@ use plotters::prelude::*;
 use iced_aw::{number_input::NumberInput, style::NumberInputStyles};
 
 use crate::{SimState, run_simulation};
+use crate::appliance::{self, APPLIANCES};
+use crate::chemistry::Chemistry;
+use crate::dispatcher::Dispatcher;
+use crate::generator::Generator;
+use crate::hydro::MicroHydro;
+use crate::clearsky::{SolarModel, GRASS_ALBEDO, SNOW_ALBEDO};
+use crate::controller::ChargeController;
+use crate::inverter::Inverter;
+use crate::irradiance::IrradianceSeries;
+use crate::margin::{self, Margin};
+use crate::panel::{self, PRESETS};
+use crate::scenario;
+use crate::sun::{Orientation, TrackingMode};
+use crate::temperature::AmbientTemperature;
+use crate::trace;
+use crate::weather::WeatherGenerator;
+use crate::wind::{WindGenerator, WindTurbine};
+use crate::wiring::CableRun;
+
+// Panel choice that leaves output independent of cell temperature
+const GENERIC_PANEL: &str = "Generic";
 
 #[derive(Debug, Clone)]
 pub enum Message {
     BatteryCapacityChanged(f32),
+    InitialSocChanged(f32),
+    BatteryAmpHoursChanged(f32),
+    AmpHoursToggled(bool),
+    SocPercentToggled(bool),
+    ChargeEfficiencyChanged(f32),
+    DischargeEfficiencyChanged(f32),
+    MinimumSocChanged(f32),
+    ReconnectSocChanged(f32),
+    ChemistrySelected(Chemistry),
+    BatteryTemperatureRiseChanged(f32),
     SolarCapacityChanged(f32),
     LoadChanged(f32),
+    StandbyLoadChanged(f32),
+    DumpLoadChanged(f32),
     LatitudeChanged(f32),
-    StartDateChanged(f32),
-    EndDateChanged(f32),
+    LongitudeChanged(f32),
+    UtcOffsetChanged(f32),
+    ElevationChanged(f32),
+    DerateChanged(usize, f32),
+    PvgisToggled(bool),
+    StartDateChanged(String),
+    EndDateChanged(String),
+    VoltageChanged(f32),
+    ArrayCableLengthChanged(f32),
+    ArrayCableGaugeChanged(f32),
+    ConnectionLossChanged(f32),
+    ControllerDrawChanged(f32),
+    BmsDrawChanged(f32),
+    PwmToggled(bool),
+    InverterIdleChanged(f32),
+    InverterRatingChanged(f32),
+    GeneratorPowerChanged(f32),
+    WindPowerChanged(f32),
+    WindSpeedChanged(f32),
+    HydroPowerChanged(f32),
+    StandbyThresholdChanged(f32),
+    OrientationToggled(bool),
+    TiltChanged(f32),
+    AzimuthChanged(f32),
+    TrackingChanged(TrackingMode),
+    DispatcherChanged(Dispatcher),
+    ClearSkyToggled(bool),
+    SnowToggled(bool),
+    PanelSelected(&'static str),
+    ApplianceAdded(&'static str),
+    ClearLoads,
+    SnowCoverToggled(bool),
+    BifacialToggled(bool),
+    SeasonalTemperatureToggled(bool),
+    WeatherToggled(bool),
+    WeatherSeedChanged(f32),
+    SetBaseline,
+    ClearBaseline,
+    AnalyzeMargin,
     ChartEvent(ChartMessage),
     AxisChoiceChanged(SecondAxis)
 }
@@ -30,7 +100,29 @@ pub enum Message {
 pub struct AppState {
     pub sim_state: SimState,
     pub plot: DateLineChart,
-    pub second_axis: SecondAxis
+    pub second_axis: SecondAxis,
+    pub array_cable_length: f32, // m
+    pub array_cable_gauge: f32, // AWG
+    pub controller_draw: f32, // W, no controller is modeled at zero
+    pub pwm_controller: bool, // a PWM controller instead of MPPT
+    pub amp_hours: bool, // battery capacity shown in Ah at the system voltage instead of Wh
+    pub soc_percent: bool, // charge plotted in percent instead of Wh
+    pub inverter_idle: f32, // W, no inverter is modeled at zero
+    pub standby_threshold: f32, // W, always on at zero
+    pub inverter_rating: f32, // W, no efficiency curve at zero
+    pub generator_power: f32, // W, no generator at zero
+    pub wind_power: f32, // W, no turbine at zero
+    pub wind_speed: f32, // m/s, mean over the year
+    pub hydro_power: f32, // W, no hydro turbine at zero
+    pub start_date: String, // as typed, applied once it parses
+    pub end_date: String,
+    pub panel_tilt: f32, // degrees, used when the panel is fixed
+    pub panel_azimuth: f32, // degrees, used when the panel is fixed
+    pub baseline: Option<SimState>, // finished run the current scenario is compared against
+    pub weather_seed: f32,
+    pub margin: Option<Margin>, // cleared whenever the scenario changes
+    pub pvgis: bool, // drive the run from PVGIS climatology
+    pub data_error: Option<String>,
 }
 
 impl Application for AppState {
@@ -47,15 +139,37 @@ impl Application for AppState {
         starting_state.latitude = 36.;
         let state = run_simulation(&starting_state); 
         let plot = DateLineChart::new(
-            state.history_dates.clone().into_iter().map(|d| d).collect(),
+            state.history_dates.clone().into_iter().collect(),
             vec![state.charge_history.clone()],
             Vec::new(),
-            vec!["State of Charge".to_string()],
+            vec!["State of Charge [Wh]".to_string()],
             );    
         (AppState { 
             sim_state: state,
             plot,
-            second_axis: SecondAxis::None
+            second_axis: SecondAxis::None,
+            array_cable_length: 0.,
+            array_cable_gauge: 10.,
+            controller_draw: 0.,
+            pwm_controller: false,
+            amp_hours: false,
+            soc_percent: false,
+            inverter_idle: 0.,
+            standby_threshold: 0.,
+            inverter_rating: 0.,
+            generator_power: 0.,
+            wind_power: 0.,
+            wind_speed: 5.,
+            hydro_power: 0.,
+            start_date: starting_state.start_date.to_string(),
+            end_date: starting_state.end_date.to_string(),
+            panel_tilt: 30.,
+            panel_azimuth: 180.,
+            baseline: None,
+            weather_seed: 1.,
+            margin: None,
+            pvgis: false,
+            data_error: None,
             }, 
         Command::none())
     }
@@ -65,18 +179,183 @@ impl Application for AppState {
     }
 
     fn update(&mut self, event: Message) -> Command<Message>{
+        trace::timed!("gui_update", ?event);
+        if !matches!(event, Message::AnalyzeMargin) {
+            self.margin = None;
+        }
         match event {
             Message::BatteryCapacityChanged(capacity) => self.sim_state.battery_capacity = capacity,
+            Message::InitialSocChanged(percent) => self.sim_state.initial_soc = percent / 100.,
+            Message::BatteryAmpHoursChanged(amp_hours) => {
+                self.sim_state = self.sim_state.clone().with_battery_amp_hours(amp_hours, self.sim_state.system_voltage)
+            },
+            Message::AmpHoursToggled(amp_hours) => self.amp_hours = amp_hours,
+            Message::SocPercentToggled(percent) => self.soc_percent = percent,
+            Message::ChargeEfficiencyChanged(efficiency) => self.sim_state.charge_efficiency = efficiency,
+            Message::DischargeEfficiencyChanged(efficiency) => self.sim_state.discharge_efficiency = efficiency,
+            Message::MinimumSocChanged(fraction) => self.sim_state.minimum_soc = fraction,
+            Message::ReconnectSocChanged(fraction) => self.sim_state.reconnect_soc = fraction,
+            Message::ChemistrySelected(chemistry) => self.sim_state = self.sim_state.clone().with_chemistry(chemistry),
+            Message::BatteryTemperatureRiseChanged(rise) => self.sim_state.battery_temperature_rise = rise,
             Message::SolarCapacityChanged(capacity) => self.sim_state.solar_nominal_output = capacity,
+            Message::ApplianceAdded(name) => {
+                if let Some(appliance) = appliance::preset(name) {
+                    self.sim_state.loads.push(appliance.load());
+                }
+            },
+            Message::ClearLoads => self.sim_state.loads.clear(),
+            Message::PanelSelected(name) => {
+                self.sim_state.panel = panel::preset(name);
+                // Round the array to whole panels
+                if let Some(panel) = self.sim_state.panel {
+                    self.sim_state.solar_nominal_output =
+                        panel.count_for(self.sim_state.solar_nominal_output) as f32 * panel.rated_power;
+                }
+            }
             Message::LoadChanged(load) => self.sim_state.load = load,
+            Message::StandbyLoadChanged(load) => self.sim_state.standby_load = load,
+            Message::DumpLoadChanged(load) => self.sim_state.dump_load = load,
             Message::LatitudeChanged(lat) => self.sim_state.latitude = lat,
-            Message::StartDateChanged(day) => self.sim_state.start_day = day as u32,
-            Message::EndDateChanged(day) => self.sim_state.end_day = day as u32,
+            Message::LongitudeChanged(lon) => self.sim_state.longitude = lon,
+            Message::UtcOffsetChanged(offset) => self.sim_state.utc_offset = offset,
+            Message::ElevationChanged(elevation) => self.sim_state.elevation = elevation,
+            Message::DerateChanged(month, factor) => self.sim_state.monthly_derate[month] = factor,
+            Message::PvgisToggled(enabled) => self.pvgis = enabled,
+            Message::StartDateChanged(date) => self.start_date = date,
+            Message::EndDateChanged(date) => self.end_date = date,
+            Message::VoltageChanged(voltage) => self.sim_state.system_voltage = voltage,
+            Message::ArrayCableLengthChanged(length) => self.array_cable_length = length,
+            Message::ArrayCableGaugeChanged(gauge) => self.array_cable_gauge = gauge,
+            Message::ConnectionLossChanged(percent) => self.sim_state.wiring.connection_loss = percent / 100.,
+            Message::ControllerDrawChanged(draw) => self.controller_draw = draw,
+            Message::BmsDrawChanged(draw) => self.sim_state.bms_draw = draw,
+            Message::PwmToggled(pwm) => self.pwm_controller = pwm,
+            Message::InverterIdleChanged(draw) => self.inverter_idle = draw,
+            Message::StandbyThresholdChanged(threshold) => self.standby_threshold = threshold,
+            Message::InverterRatingChanged(rating) => self.inverter_rating = rating,
+            Message::GeneratorPowerChanged(power) => self.generator_power = power,
+            Message::WindPowerChanged(power) => self.wind_power = power,
+            Message::WindSpeedChanged(speed) => self.wind_speed = speed,
+            Message::HydroPowerChanged(power) => self.hydro_power = power,
+            Message::OrientationToggled(fixed) => self.sim_state.orientation = if fixed {
+                Some(Orientation::new(self.panel_tilt, self.panel_azimuth))
+            } else {
+                None
+            },
+            Message::TiltChanged(tilt) => self.panel_tilt = tilt,
+            Message::AzimuthChanged(azimuth) => self.panel_azimuth = azimuth,
+            Message::TrackingChanged(tracking) => self.sim_state.tracking = tracking,
+            Message::DispatcherChanged(dispatcher) => self.sim_state.dispatcher = dispatcher,
+            Message::ClearSkyToggled(enabled) => self.sim_state.solar_model = if enabled {
+                SolarModel::Ineichen { linke_turbidity: 3. }
+            } else {
+                SolarModel::SimpleCurve
+            },
+            Message::SnowToggled(snow) => self.sim_state.albedo = if snow {
+                SNOW_ALBEDO
+            } else {
+                GRASS_ALBEDO
+            },
+            Message::BifacialToggled(bifacial) => self.sim_state.bifaciality = if bifacial { 0.7 } else { 0. },
+            Message::SnowCoverToggled(enabled) => self.sim_state.snow = if enabled {
+                Some(crate::snow::SnowModel::northern_winter(self.weather_seed as u64))
+            } else {
+                None
+            },
+            Message::SeasonalTemperatureToggled(enabled) => self.sim_state.ambient_temperature = if enabled {
+                AmbientTemperature::seasonal(self.sim_state.latitude)
+            } else {
+                AmbientTemperature::default()
+            },
+            Message::WeatherToggled(enabled) => self.sim_state.weather = if enabled {
+                Some(WeatherGenerator::temperate(self.weather_seed as u64))
+            } else {
+                None
+            },
+            Message::WeatherSeedChanged(seed) => {
+                self.weather_seed = seed;
+                if let Some(weather) = &mut self.sim_state.weather {
+                    weather.seed = seed as u64;
+                }
+            },
+            Message::SetBaseline => self.baseline = Some(self.sim_state.clone()),
+            Message::ClearBaseline => self.baseline = None,
+            Message::AnalyzeMargin => self.margin = Some(margin::design_margin(&self.sim_state)),
             Message::ChartEvent(_) => (),
             Message::AxisChoiceChanged(axis) => self.second_axis = axis,
         }
+        self.sim_state.wiring.array = if self.array_cable_length > 0. {
+            Some(CableRun::from_awg(self.array_cable_length, self.array_cable_gauge as u32))
+        } else {
+            None
+        };
+        if matches!(self.sim_state.ambient_temperature, AmbientTemperature::Seasonal { .. }) {
+            self.sim_state.ambient_temperature = AmbientTemperature::seasonal(self.sim_state.latitude);
+        }
+        if let Some(orientation) = &mut self.sim_state.orientation {
+            *orientation = Orientation::new(self.panel_tilt, self.panel_azimuth);
+        }
+        self.sim_state.charge_controller = if self.controller_draw > 0. || self.pwm_controller {
+            let mut controller = if self.pwm_controller {
+                ChargeController::pwm(self.sim_state.solar_nominal_output)
+            } else {
+                ChargeController::new(self.sim_state.solar_nominal_output)
+            };
+            controller.quiescent_draw = self.controller_draw;
+            Some(controller)
+        } else {
+            None
+        };
+        self.sim_state.inverter = if self.inverter_idle > 0. {
+            let mut inverter = Inverter::new(self.inverter_idle);
+            if self.inverter_rating > 0. {
+                inverter = inverter.with_rating(self.inverter_rating, 0.93);
+            }
+            if self.standby_threshold > 0. {
+                Some(inverter.with_standby(self.standby_threshold, self.inverter_idle / 10.))
+            } else {
+                Some(inverter)
+            }
+        } else {
+            None
+        };
+        // Starts at 30%, stops at 90%, and burns about 0.5 L per kWh at full output
+        self.sim_state.generator = if self.generator_power > 0. {
+            Some(Generator::new(self.generator_power, 0.3, 0.9, self.generator_power / 2000.))
+        } else {
+            None
+        };
+        (self.sim_state.wind_turbine, self.sim_state.wind) = if self.wind_power > 0. {
+            (Some(WindTurbine::small(self.wind_power)),
+                Some(WindGenerator::for_latitude(self.wind_speed, self.sim_state.latitude, self.weather_seed as u64)))
+        } else {
+            (None, None)
+        };
+        self.sim_state.hydro = if self.hydro_power > 0. { Some(MicroHydro::constant(self.hydro_power)) } else { None };
+        // Keeps the last good dates while one is half typed
+        if let (Ok(start), Ok(end)) = (NaiveDate::parse_from_str(&self.start_date, "%Y-%m-%d"),
+            NaiveDate::parse_from_str(&self.end_date, "%Y-%m-%d")) {
+            (self.sim_state.start_date, self.sim_state.end_date) = (start, end);
+        }
+        self.data_error = None;
+        self.sim_state.irradiance = if self.pvgis {
+            match pvgis_series(&self.sim_state) {
+                Ok(series) => Some(series),
+                Err(error) => {
+                    self.data_error = Some(format!("PVGIS: {}", error));
+                    None
+                },
+            }
+        } else {
+            None
+        };
         self.sim_state = run_simulation(&self.sim_state);
-        let mut labels = vec!["State of Charge".to_string()];
+        let (charge, charge_label) = if self.soc_percent {
+            (self.sim_state.soc_history.clone(), "State of Charge [%]")
+        } else {
+            (self.sim_state.charge_history.clone(), "State of Charge [Wh]")
+        };
+        let mut labels = vec![charge_label.to_string()];
         let mut secondary_data = Vec::new();
         match self.second_axis {
             SecondAxis::None => (),
@@ -84,24 +363,88 @@ impl Application for AppState {
                 labels.push("Solar Output".to_string());
                 secondary_data.push(self.sim_state.solar_history.clone());
             },
+            SecondAxis::WindPower => {
+                labels.push("Wind Output".to_string());
+                secondary_data.push(self.sim_state.wind_history.clone());
+            },
             SecondAxis::SunlightHours => {
                 labels.push("Daylight Hours".to_string());
                 secondary_data.push(self.sim_state.daylight_history.clone());
+            },
+            SecondAxis::WiringLoss => {
+                labels.push("Wiring Loss [Wh]".to_string());
+                secondary_data.push(self.sim_state.wiring_loss_history.clone());
+            },
+            SecondAxis::AmbientTemperature => {
+                labels.push("Ambient Temperature [C]".to_string());
+                secondary_data.push(self.sim_state.temperature_history.clone());
+            },
+            SecondAxis::BatteryTemperature => {
+                labels.push("Battery Temperature [C]".to_string());
+                secondary_data.push(self.sim_state.battery_temperature_history.clone());
+            },
+            SecondAxis::StateOfHealth => {
+                labels.push("State of Health".to_string());
+                secondary_data.push(self.sim_state.soh_history.clone());
+            },
+            SecondAxis::Currents => {
+                labels.push("Battery Current [A]".to_string());
+                labels.push("Controller Current [A]".to_string());
+                labels.push("Load Current [A]".to_string());
+                secondary_data.push(self.sim_state.battery_current_history.clone());
+                secondary_data.push(self.sim_state.controller_current_history.clone());
+                secondary_data.push(self.sim_state.load_current_history.clone());
             }
         }
         self.plot = DateLineChart::new(
-            self.sim_state.history_dates.clone().into_iter().map(|d| d).collect(),
-            vec![self.sim_state.charge_history.clone()],
+            self.sim_state.history_dates.clone().into_iter().collect(),
+            vec![charge],
             secondary_data,
             labels,
         ); 
         Command::none()
     }
     
-    fn view(&self) -> Element<Message> {
-        let battery_input = NumberInput::new(self.sim_state.battery_capacity, 1000000000000000000., Message::BatteryCapacityChanged)
+    fn view(&self) -> Element<'_, Message> {
+        let charge_efficiency_input = NumberInput::new(self.sim_state.charge_efficiency, 1., Message::ChargeEfficiencyChanged)
+            .min(0.5)
+            .style(NumberInputStyles::Default)
+            .step(0.01).width(Length::Fixed(80.));
+
+        let discharge_efficiency_input = NumberInput::new(self.sim_state.discharge_efficiency, 1., Message::DischargeEfficiencyChanged)
+            .min(0.5)
+            .style(NumberInputStyles::Default)
+            .step(0.01).width(Length::Fixed(80.));
+
+        let minimum_soc_input = NumberInput::new(self.sim_state.minimum_soc, 0.9, Message::MinimumSocChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.05).width(Length::Fixed(80.));
+
+        let reconnect_soc_input = NumberInput::new(self.sim_state.reconnect_soc, 1., Message::ReconnectSocChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.05).width(Length::Fixed(80.));
+
+        let choose_chemistry = pick_list(
+            &Chemistry::ALL[..],
+            self.sim_state.chemistry,
+            Message::ChemistrySelected,
+        );
+
+        let battery_rise_input = NumberInput::new(self.sim_state.battery_temperature_rise, 40., Message::BatteryTemperatureRiseChanged)
+            .style(NumberInputStyles::Default)
+            .step(1.).width(Length::Fixed(80.));
+
+        let (battery_label, battery_input) = if self.amp_hours {
+            ("Battery Capacity [Ah]", NumberInput::new(self.sim_state.battery_amp_hours(), 1000000000000000000., Message::BatteryAmpHoursChanged))
+        } else {
+            ("Battery Capacity [Wh]", NumberInput::new(self.sim_state.battery_capacity, 1000000000000000000., Message::BatteryCapacityChanged))
+        };
+        let battery_input = battery_input
             .style(NumberInputStyles::Default)
             .step(1.).width(Length::Fixed(80.));
+        let initial_soc_input = NumberInput::new(self.sim_state.initial_soc * 100., 100., Message::InitialSocChanged)
+            .style(NumberInputStyles::Default)
+            .step(5.).width(Length::Fixed(80.));
         let solar_input = NumberInput::new(self.sim_state.solar_nominal_output, 1000000000000000000., Message::SolarCapacityChanged)
             .style(NumberInputStyles::Default)
             .step(1.).width(Length::Fixed(80.));
@@ -110,20 +453,177 @@ impl Application for AppState {
             .style(NumberInputStyles::Default)
             .step(1.).width(Length::Fixed(80.));
 
-        let lat_input = NumberInput::new(self.sim_state.latitude, 1000000000000000000., Message::LatitudeChanged)
+        // Southern latitudes are negative
+        let lat_input = NumberInput::new(self.sim_state.latitude, 90., Message::LatitudeChanged)
+            .min(-90.)
+            .style(NumberInputStyles::Default)
+            .step(0.1).width(Length::Fixed(80.));
+
+        let lon_input = NumberInput::new(self.sim_state.longitude, 180., Message::LongitudeChanged)
+            .min(-180.)
             .style(NumberInputStyles::Default)
             .step(0.1).width(Length::Fixed(80.));
 
-        let start_input = NumberInput::new(self.sim_state.start_day as f32, 365., Message::StartDateChanged)
+        let elevation_input = NumberInput::new(self.sim_state.elevation, 9000., Message::ElevationChanged)
+            .style(NumberInputStyles::Default)
+            .step(100.).width(Length::Fixed(80.));
+
+        let utc_input = NumberInput::new(self.sim_state.utc_offset, 14., Message::UtcOffsetChanged)
+            .min(-12.)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let start_input = text_input("YYYY-MM-DD", &self.start_date)
+            .on_input(Message::StartDateChanged)
+            .width(Length::Fixed(110.));
+
+        let end_input = text_input("YYYY-MM-DD", &self.end_date)
+            .on_input(Message::EndDateChanged)
+            .width(Length::Fixed(110.));
+
+        let voltage_input = NumberInput::new(self.sim_state.system_voltage, 1000., Message::VoltageChanged)
+            .style(NumberInputStyles::Default)
+            .step(12.).width(Length::Fixed(80.));
+
+        let cable_length_input = NumberInput::new(self.array_cable_length, 1000., Message::ArrayCableLengthChanged)
             .style(NumberInputStyles::Default)
             .step(1.).width(Length::Fixed(80.));
 
-        let end_input = NumberInput::new(self.sim_state.end_day as f32, 365., Message::EndDateChanged)
+        let cable_gauge_input = NumberInput::new(self.array_cable_gauge, 40., Message::ArrayCableGaugeChanged)
             .style(NumberInputStyles::Default)
             .step(1.).width(Length::Fixed(80.));
 
+        let standby_load_input = NumberInput::new(self.sim_state.standby_load, 1000., Message::StandbyLoadChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let connection_loss_input = NumberInput::new(
+            self.sim_state.wiring.connection_loss * 100., 50., Message::ConnectionLossChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let dump_load_input = NumberInput::new(self.sim_state.dump_load, 10000., Message::DumpLoadChanged)
+            .style(NumberInputStyles::Default)
+            .step(100.).width(Length::Fixed(80.));
+
+        let bms_input = NumberInput::new(self.sim_state.bms_draw, 100., Message::BmsDrawChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let controller_input = NumberInput::new(self.controller_draw, 100., Message::ControllerDrawChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let inverter_input = NumberInput::new(self.inverter_idle, 1000., Message::InverterIdleChanged)
+            .style(NumberInputStyles::Default)
+            .step(1.).width(Length::Fixed(80.));
+
+        let standby_input = NumberInput::new(self.standby_threshold, 10000., Message::StandbyThresholdChanged)
+            .style(NumberInputStyles::Default)
+            .step(1.).width(Length::Fixed(80.));
+
+        let generator_input = NumberInput::new(self.generator_power, 20000., Message::GeneratorPowerChanged)
+            .style(NumberInputStyles::Default)
+            .step(100.).width(Length::Fixed(80.));
+
+        let wind_power_input = NumberInput::new(self.wind_power, 20000., Message::WindPowerChanged)
+            .style(NumberInputStyles::Default)
+            .step(100.).width(Length::Fixed(80.));
+
+        let wind_speed_input = NumberInput::new(self.wind_speed, 30., Message::WindSpeedChanged)
+            .style(NumberInputStyles::Default)
+            .step(0.5).width(Length::Fixed(80.));
+
+        let hydro_input = NumberInput::new(self.hydro_power, 20000., Message::HydroPowerChanged)
+            .style(NumberInputStyles::Default)
+            .step(50.).width(Length::Fixed(80.));
+
+        let rating_input = NumberInput::new(self.inverter_rating, 10000., Message::InverterRatingChanged)
+            .style(NumberInputStyles::Default)
+            .step(50.).width(Length::Fixed(80.));
+
+        let tilt_input = NumberInput::new(self.panel_tilt, 90., Message::TiltChanged)
+            .style(NumberInputStyles::Default)
+            .step(5.).width(Length::Fixed(80.));
+
+        let azimuth_input = NumberInput::new(self.panel_azimuth, 360., Message::AzimuthChanged)
+            .style(NumberInputStyles::Default)
+            .step(5.).width(Length::Fixed(80.));
+
+        let seed_input = NumberInput::new(self.weather_seed, 1000000., Message::WeatherSeedChanged)
+            .style(NumberInputStyles::Default)
+            .step(1.).width(Length::Fixed(80.));
+
+        let panel_names: Vec<&'static str> = std::iter::once(GENERIC_PANEL)
+            .chain(PRESETS.iter().map(|p| p.name))
+            .collect();
+        let choose_panel = pick_list(
+            panel_names,
+            Some(self.sim_state.panel.map_or(GENERIC_PANEL, |p| p.name)),
+            Message::PanelSelected,
+        );
+        let panel_summary = match self.sim_state.panel {
+            Some(panel) => {
+                let count = panel.count_for(self.sim_state.solar_nominal_output);
+                format!("{} panels, {:.1} m2", count, count as f32 * panel.area())
+            }
+            None => String::new(),
+        };
+
+        let add_appliance = pick_list(
+            APPLIANCES.iter().map(|a| a.name).collect::<Vec<_>>(),
+            None,
+            Message::ApplianceAdded,
+        ).placeholder("Add Appliance");
+        let loads_summary = self.sim_state.loads.iter()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+        let derate_inputs = (0..3).fold(column![text("Monthly solar derating:")].spacing(5), |column, quarter| {
+            column.push((0..4).fold(row![].spacing(5), |row, i| {
+                let month = quarter * 4 + i;
+                row.push(text(MONTHS[month]).width(Length::Fixed(30.)))
+                    .push(NumberInput::new(self.sim_state.monthly_derate[month], 1., move |f| Message::DerateChanged(month, f))
+                        .style(NumberInputStyles::Default)
+                        .step(0.05).width(Length::Fixed(60.)))
+            }))
+        });
+
+        let choose_tracking =
+        [TrackingMode::Fixed, TrackingMode::SingleAxis, TrackingMode::DualAxis]
+            .iter()
+            .fold(
+                column![text("Panel mount:")].spacing(10),
+                |column, tracking| {
+                    column.push(radio(
+                        format!("{tracking:?}"),
+                        *tracking,
+                        Some(self.sim_state.tracking),
+                        Message::TrackingChanged,
+                    ))
+                },
+            );
+
+        let choose_dispatcher = Dispatcher::ALL
+            .iter()
+            .fold(
+                column![text("Generator dispatch:")].spacing(10),
+                |column, dispatcher| {
+                    column.push(radio(
+                        format!("{dispatcher:?}"),
+                        *dispatcher,
+                        Some(self.sim_state.dispatcher),
+                        Message::DispatcherChanged,
+                    ))
+                },
+            );
+
         let choose_axis =
-        [SecondAxis::None, SecondAxis::SolarPower, SecondAxis::SunlightHours]
+        [SecondAxis::None, SecondAxis::SolarPower, SecondAxis::WindPower, SecondAxis::SunlightHours, SecondAxis::WiringLoss,
+            SecondAxis::AmbientTemperature, SecondAxis::Currents, SecondAxis::StateOfHealth,
+            SecondAxis::BatteryTemperature]
             .iter()
             .fold(
                 column![text("Choose the secondary axis:")].spacing(10),
@@ -140,14 +640,87 @@ impl Application for AppState {
         let inputs = scrollable(
             column![
                 text("Settings").width(Length::Fill).horizontal_alignment(Horizontal::Center),
-                row![text("Battery Capacity [Wh]").width(Length::Fill), battery_input,],
+                row![text(battery_label).width(Length::Fill), battery_input,],
+                checkbox("Capacity in Ah", self.amp_hours, Message::AmpHoursToggled),
+                checkbox("Charge in %", self.soc_percent, Message::SocPercentToggled),
+                row![text("Initial Charge [%]").width(Length::Fill), initial_soc_input,],
+                row![text("Charge Efficiency").width(Length::Fill), charge_efficiency_input,],
+                row![text("Discharge Efficiency").width(Length::Fill), discharge_efficiency_input,],
+                row![text("Minimum SOC").width(Length::Fill), minimum_soc_input,],
+                row![text("Reconnect SOC").width(Length::Fill), reconnect_soc_input,],
+                row![text("Chemistry").width(Length::Fill), choose_chemistry,],
+                row![text("Battery Heating [C]").width(Length::Fill), battery_rise_input,],
                 row![text("Solar Power Nominal [W]").width(Length::Fill), solar_input,],
+                row![text("Panel").width(Length::Fill), choose_panel,],
+                text(panel_summary).size(14),
                 row![text("Load [W]").width(Length::Fill), load_input,],
+                row![text("Standby Load [W]").width(Length::Fill), standby_load_input,],
+                row![text("Dump Load [W]").width(Length::Fill), dump_load_input,],
+                row![add_appliance, button("Clear Loads").on_press(Message::ClearLoads),].spacing(10),
+                text(loads_summary).size(14),
                 row![text("Latitude [degrees]").width(Length::Fill), lat_input,],
+                row![text("Longitude [degrees]").width(Length::Fill), lon_input,],
+                row![text("UTC Offset [hours]").width(Length::Fill), utc_input,],
+                row![text("Elevation [m]").width(Length::Fill), elevation_input,],
+                checkbox("PVGIS Data", self.pvgis, Message::PvgisToggled),
+                text(self.data_error.clone().unwrap_or_default()).size(14),
+                row![text("System Voltage [V]").width(Length::Fill), voltage_input,],
+                row![text("Array Cable Length [m]").width(Length::Fill), cable_length_input,],
+                row![text("Array Cable Gauge [AWG]").width(Length::Fill), cable_gauge_input,],
+                row![text("Connection Loss [%]").width(Length::Fill), connection_loss_input,],
+                row![text("Controller Draw [W]").width(Length::Fill), controller_input,],
+                row![text("BMS Draw [W]").width(Length::Fill), bms_input,],
+                checkbox("PWM Controller", self.pwm_controller, Message::PwmToggled),
+                row![text("Inverter Idle [W]").width(Length::Fill), inverter_input,],
+                row![text("Inverter Standby Below [W]").width(Length::Fill), standby_input,],
+                row![text("Inverter Rating [W]").width(Length::Fill), rating_input,],
+                row![text("Generator [W]").width(Length::Fill), generator_input,],
+                choose_dispatcher,
+                row![text("Wind Turbine [W]").width(Length::Fill), wind_power_input,],
+                row![text("Mean Wind Speed [m/s]").width(Length::Fill), wind_speed_input,],
+                row![text("Micro-Hydro [W]").width(Length::Fill), hydro_input,],
+                checkbox("Fixed Panel", self.sim_state.orientation.is_some(), Message::OrientationToggled),
+                row![text("Panel Tilt [degrees]").width(Length::Fill), tilt_input,],
+                row![text("Panel Azimuth [degrees]").width(Length::Fill), azimuth_input,],
+                choose_tracking,
+                checkbox(
+                    "Snow on the Ground",
+                    self.sim_state.albedo == SNOW_ALBEDO,
+                    Message::SnowToggled),
+                checkbox("Bifacial Panels", self.sim_state.bifaciality > 0., Message::BifacialToggled),
+                checkbox("Snow Covers Panels", self.sim_state.snow.is_some(), Message::SnowCoverToggled),
+                checkbox(
+                    "Clear-Sky Irradiance",
+                    matches!(self.sim_state.solar_model, SolarModel::Ineichen { .. }),
+                    Message::ClearSkyToggled),
+                checkbox(
+                    "Seasonal Temperature",
+                    matches!(self.sim_state.ambient_temperature, AmbientTemperature::Seasonal { .. }),
+                    Message::SeasonalTemperatureToggled),
+                checkbox("Synthetic Weather", self.sim_state.weather.is_some(), Message::WeatherToggled),
+                row![text("Weather Seed").width(Length::Fill), seed_input,],
+                derate_inputs,
                 horizontal_rule(1),
-                row![text("Start Day").width(Length::Fill), start_input,],
-                row![text("End Day").width(Length::Fill), end_input,],
+                row![text("Start Date").width(Length::Fill), start_input,],
+                row![text("End Date").width(Length::Fill), end_input,],
                 choose_axis,
+                horizontal_rule(1),
+                row![
+                    button("Set Baseline").on_press(Message::SetBaseline),
+                    button("Clear").on_press(Message::ClearBaseline),
+                    ].spacing(10),
+                text(match &self.baseline {
+                    Some(baseline) => scenario::diff(baseline, &self.sim_state).report(),
+                    None => "Set a baseline to compare scenarios".to_string(),
+                }).size(14),
+                button("Analyze Margin").on_press(Message::AnalyzeMargin),
+                text(match &self.margin {
+                    Some(margin) => format!(
+                        "Load margin: {}\nSolar margin: {}",
+                        margin.load_margin_percent().map_or("over 9900%".to_string(), |p| format!("{:.0}%", p)),
+                        margin.solar_margin_percent().map_or("never fails".to_string(), |p| format!("{:.0}%", p))),
+                    None => String::new(),
+                }).size(14),
                 ].padding(10)
                 .spacing(10)
             .align_items(Alignment::Start)
@@ -167,6 +740,25 @@ impl Application for AppState {
     }
 }
 
+// Hourly data for the site and mount, cached after the first download
+#[cfg(feature = "pvgis")]
+fn pvgis_series(state: &SimState) -> std::io::Result<IrradianceSeries> {
+    let request = crate::pvgis::PvgisRequest {
+        latitude: state.latitude,
+        longitude: state.longitude,
+        orientation: state.orientation.unwrap_or(Orientation::equator_facing(state.latitude)),
+        tracking: if state.orientation.is_some() { state.tracking } else { TrackingMode::DualAxis },
+        year: 2019,
+    };
+    let start = crate::simulation_start(state);
+    crate::irradiance::fetch(&request).map(|series| crate::irradiance::shift_to_year(&series, start.year()))
+}
+
+#[cfg(not(feature = "pvgis"))]
+fn pvgis_series(_state: &SimState) -> std::io::Result<IrradianceSeries> {
+    Err(std::io::Error::other("built without the pvgis feature"))
+}
+
 #[derive(Debug, Clone)]
 pub enum ChartMessage {
     Updated
@@ -176,7 +768,13 @@ pub enum ChartMessage {
 pub enum SecondAxis {
     None,
     SolarPower,
+    WindPower,
     SunlightHours,
+    WiringLoss,
+    AmbientTemperature,
+    Currents,
+    StateOfHealth,
+    BatteryTemperature,
 }
 
 pub struct DateLineChart {
@@ -197,7 +795,7 @@ impl Chart<ChartMessage> for DateLineChart {
 
         //const PLOT_LINE_COLOR: RGBColor = RGBColor(0, 175, 255);
         
-        let from_date = *self.xs.first().clone().unwrap_or(
+        let from_date = *self.xs.first().unwrap_or(
             &NaiveDateTime::new(
             NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), 
             NaiveTime::from_hms_opt(1,0,0).unwrap()));
@@ -206,22 +804,21 @@ impl Chart<ChartMessage> for DateLineChart {
             NaiveDate::from_ymd_opt(2023, 1, 2).unwrap(), 
             NaiveTime::from_hms_opt(1,0,0).unwrap()));
     
-        let mut y_max: f32 = self.ys.iter().map(|y| 
-            y.clone().into_iter().reduce(f32::max))
-            .filter(|i| i.is_some())
-            .map(|i| i.unwrap()).reduce(f32::max).unwrap_or(1.);
+        let mut y_max: f32 = self.ys.iter().filter_map(|y| 
+            y.clone().into_iter().reduce(f32::max)).reduce(f32::max).unwrap_or(1.);
         if y_max == 0. {
             y_max = 1.
         }
 
-        let y_secondary_max: f32 = if self.ys_secondary.len() == 0 {
+        let y_secondary_max: f32 = if self.ys_secondary.is_empty() {
             1.
         } else {
-            self.ys_secondary.iter().map(|y| 
-            y.clone().into_iter().reduce(f32::max))
-            .filter(|i| i.is_some())
-            .map(|i| i.unwrap()).reduce(f32::max).unwrap()
+            self.ys_secondary.iter().filter_map(|y| 
+            y.clone().into_iter().reduce(f32::max)).reduce(f32::max).unwrap()
         };
+        // Room below zero for series like a discharging battery's current
+        let y_secondary_min: f32 = self.ys_secondary.iter().filter_map(|y|
+            y.clone().into_iter().reduce(f32::min)).fold(0., f32::min);
 
         let mut chart = builder
             .x_label_area_size(28_i32)
@@ -233,7 +830,7 @@ impl Chart<ChartMessage> for DateLineChart {
                 0_f32..y_max*1.05).unwrap()
             .set_secondary_coord(
                 RangedDateTime::from(from_date..to_date), 
-                0_f32..y_secondary_max*1.05);
+                y_secondary_min*1.05..y_secondary_max*1.05);
 
         chart
             .configure_mesh()
@@ -255,14 +852,14 @@ impl Chart<ChartMessage> for DateLineChart {
             .draw()
             .expect("failed to draw chart mesh");
     
-        if self.ys_secondary.len() > 0 {
+        if !self.ys_secondary.is_empty() {
         chart
             .configure_secondary_axes()
             .y_desc(self.labels.last().unwrap())
             .draw().unwrap();
         }
     
-        let colors = vec![
+        let colors = [
             &BLUE, 
             &RED, 
             &BLACK, 
@@ -273,11 +870,11 @@ impl Chart<ChartMessage> for DateLineChart {
             &RGBColor(255, 150, 150), // pink
         ];
         let mut color_index = 0;
-        let n = vec![self.ys.len(), colors.len(), self.labels.len()].iter().min().unwrap_or(&1).clone() as usize;
+        let n = *[self.ys.len(), colors.len(), self.labels.len()].iter().min().unwrap_or(&1);
     
         for i in 0..n {
             let this_data: Vec<(NaiveDateTime,f32)> = self.xs.clone().into_iter()
-                .zip(self.ys[i.clone()].clone().into_iter()).collect();
+                .zip(self.ys[i].clone()).collect();
             let this_color = colors[color_index];
             let this_label = self.labels[i].clone();
             chart
@@ -291,16 +888,16 @@ impl Chart<ChartMessage> for DateLineChart {
             )
             .expect("failed to draw chart data")
             .label(this_label)
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], this_color.clone()));
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], *this_color));
             color_index += 1;
         }
     
     
-        let n = vec![self.ys_secondary.len(), colors.len(), self.labels.len()].iter().min().unwrap_or(&1).clone() as usize;
+        let n = *[self.ys_secondary.len(), colors.len(), self.labels.len()].iter().min().unwrap_or(&1);
     
         for i in 0..n {
             let this_data: Vec<(NaiveDateTime,f32)> = self.xs.clone().into_iter()
-                .zip(self.ys_secondary[i.clone()].clone().into_iter()).collect();
+                .zip(self.ys_secondary[i].clone()).collect();
             let this_color = colors[color_index];
             let this_label = self.labels[color_index].clone();
             chart
@@ -314,15 +911,15 @@ impl Chart<ChartMessage> for DateLineChart {
             )
             .expect("failed to draw chart data")
             .label(this_label)
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], this_color.clone()));
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], *this_color));
             color_index += 1;
         }
     
-        if self.ys_secondary.len() > 0 {
+        if !self.ys_secondary.is_empty() {
             chart.configure_series_labels()
             .label_font(("sans-serif", 16))
-            .background_style(&WHITE)
-            .border_style(&BLACK)
+            .background_style(WHITE)
+            .border_style(BLACK)
             .draw().expect("Failed to draw legend")    
         }
     }
@@ -338,7 +935,7 @@ impl DateLineChart {
             labels,
         }
     }
-    pub fn view(&self)->Element<ChartMessage> {
+    pub fn view(&self)->Element<'_, ChartMessage> {
         ChartWidget::new(self)
             //.width(Length::Fixed(200.))
             //.height(Length::Fixed(200.))
@@ -0,0 +1,43 @@
+use chrono::NaiveDateTime;
+use std::f32::consts::PI;
+
+use crate::temperature;
+
+// Stream-fed turbine running day and night, its output following the
+// stream's flow through the year
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MicroHydro {
+    pub monthly_power: [f32; 12], // watts delivered, January first
+}
+
+impl MicroHydro {
+    pub fn constant(watts: f32) -> MicroHydro {
+        MicroHydro { monthly_power: [watts; 12] }
+    }
+
+    // Flow peaking in one month, e.g. with the spring snowmelt, and lowest
+    // six months later
+    pub fn seasonal(peak_power: f32, low_power: f32, peak_month: u32) -> MicroHydro {
+        let mut monthly_power = [0.; 12];
+        for (i, power) in monthly_power.iter_mut().enumerate() {
+            let phase = 2. * PI * (i as f32 + 1. - peak_month as f32) / 12.;
+            *power = low_power + (peak_power - low_power) * (1. + phase.cos()) / 2.;
+        }
+        MicroHydro { monthly_power }
+    }
+
+    // Interpolated between the middles of the months
+    pub fn power_at(&self, now: NaiveDateTime) -> f32 {
+        temperature::monthly(&self.monthly_power, now).max(0.)
+    }
+}
+
+#[test]
+fn test_seasonal_hydro() {
+    let hydro = MicroHydro::seasonal(500., 100., 5);
+    assert!((hydro.monthly_power[4] - 500.).abs() < 0.001);
+    assert!((hydro.monthly_power[10] - 100.).abs() < 0.001);
+    let mid_may = chrono::NaiveDate::from_ymd_opt(2023, 5, 15).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    assert!((hydro.power_at(mid_may) - 500.).abs() < 0.01);
+    assert_eq!(MicroHydro::constant(200.).power_at(mid_may), 200.)
+}
@@ -0,0 +1,114 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Standby {
+    pub threshold: f32, // watts, the inverter sleeps while the AC load is below this
+    pub draw: f32, // watts, consumption while asleep
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub power: f32, // watts AC
+    pub efficiency: f32, // fraction, at rated power
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inverter {
+    pub idle_draw: f32, // watts, consumed whenever the inverter is on
+    pub standby: Option<Standby>, // always on when None
+    pub rating: Option<Rating>, // no conversion loss beyond the idle draw when None
+}
+
+impl Inverter {
+    pub fn new(idle_draw: f32) -> Inverter {
+        Inverter {
+            idle_draw,
+            standby: None,
+            rating: None,
+        }
+    }
+
+    pub fn with_rating(mut self, power: f32, efficiency: f32) -> Inverter {
+        self.rating = Some(Rating { power, efficiency });
+        self
+    }
+
+    pub fn with_standby(mut self, threshold: f32, draw: f32) -> Inverter {
+        self.standby = Some(Standby { threshold, draw });
+        self
+    }
+
+    // A sleeping inverter leaves loads below the search threshold unpowered
+    pub fn is_sleeping(&self, ac_load: f32) -> bool {
+        match self.standby {
+            Some(standby) => ac_load < standby.threshold,
+            None => false,
+        }
+    }
+
+    // Watts the inverter itself takes from the battery
+    pub fn self_consumption(&self, ac_load: f32) -> f32 {
+        match self.standby {
+            Some(standby) if self.is_sleeping(ac_load) => standby.draw,
+            _ => self.idle_draw,
+        }
+    }
+
+    // Watts lost converting the load, on top of the idle draw. The loss is
+    // resistive, growing with the square of the load, sized to give the
+    // rated efficiency at rated power, so with the fixed idle draw light
+    // loads convert poorly.
+    pub fn conversion_loss(&self, ac_load: f32) -> f32 {
+        let served = self.served_load(ac_load);
+        match self.rating {
+            Some(rating) if rating.power > 0. && served > 0. => {
+                let at_rated = rating.power / rating.efficiency - rating.power - self.idle_draw;
+                at_rated.max(0.) * (served / rating.power).powi(2)
+            }
+            _ => 0.,
+        }
+    }
+
+    // Fraction of the battery draw that reaches the load
+    pub fn efficiency(&self, ac_load: f32) -> f32 {
+        let served = self.served_load(ac_load);
+        if served <= 0. {
+            0.
+        } else {
+            served / (served + self.self_consumption(ac_load) + self.conversion_loss(ac_load))
+        }
+    }
+
+    // Watts of AC load actually powered
+    pub fn served_load(&self, ac_load: f32) -> f32 {
+        if self.is_sleeping(ac_load) {
+            0.
+        } else {
+            ac_load
+        }
+    }
+}
+
+#[test]
+fn test_always_on() {
+    let inverter = Inverter::new(8.);
+    assert_eq!(inverter.self_consumption(0.), 8.);
+    assert_eq!(inverter.served_load(3.), 3.)
+}
+
+#[test]
+fn test_standby() {
+    let inverter = Inverter::new(8.).with_standby(5., 0.5);
+    assert!(inverter.is_sleeping(3.));
+    assert_eq!(inverter.self_consumption(3.), 0.5);
+    assert_eq!(inverter.served_load(3.), 0.);
+    assert_eq!(inverter.self_consumption(50.), 8.);
+    assert_eq!(inverter.served_load(50.), 50.)
+}
+
+#[test]
+fn test_efficiency_curve() {
+    let inverter = Inverter::new(8.).with_rating(1000., 0.93);
+    assert!((inverter.efficiency(1000.) - 0.93).abs() < 0.001);
+    assert!(inverter.efficiency(20.) < 0.75);
+    assert!(inverter.efficiency(300.) > 0.9);
+    assert_eq!(Inverter::new(8.).conversion_loss(500.), 0.)
+}
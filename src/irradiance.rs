@@ -0,0 +1,85 @@
+use chrono::{Datelike, NaiveDateTime};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::series;
+
+// Hourly plane-of-array irradiance, W/m2, from a climatology source. It
+// replaces the idealized curve and the weather model, as real data already
+// includes its clouds.
+pub type IrradianceSeries = Vec<(NaiveDateTime, f32)>;
+
+// Moves a year of data onto the simulated year, dropping the leap day
+pub fn shift_to_year(series: &[(NaiveDateTime, f32)], year: i32) -> IrradianceSeries {
+    let mut shifted: IrradianceSeries = series.iter()
+        .filter_map(|(time, value)| time.with_year(year).map(|t| (t, *value)))
+        .collect();
+    shifted.sort_by_key(|(time, _)| *time);
+    shifted
+}
+
+// Fraction of nominal output, which is rated at 1000 W/m2
+pub fn coefficient_at(series: &[(NaiveDateTime, f32)], now: NaiveDateTime) -> f32 {
+    series::interpolate_yearly(series, now).unwrap_or(0.).max(0.) / 1000.
+}
+
+// An online source of hourly irradiance for a site
+pub trait WeatherProvider {
+    fn name(&self) -> &str;
+    fn url(&self) -> String;
+    // File name for the cached response, unique to the request
+    fn cache_name(&self) -> String;
+    fn parse(&self, text: &str) -> io::Result<IrradianceSeries>;
+}
+
+// Downloads, or reads from the cache, and parses a provider's data
+#[cfg(any(feature = "pvgis", feature = "nsrdb"))]
+pub fn fetch(provider: &dyn WeatherProvider) -> io::Result<IrradianceSeries> {
+    crate::trace::timed!("fetch_irradiance", provider = provider.name());
+    let path = cache_dir().join(provider.cache_name());
+    let text = cached(&path, || {
+        ureq::get(&provider.url()).call()
+            .map_err(io::Error::other)?
+            .into_string()
+    })?;
+    provider.parse(&text)
+}
+
+// Returns the cached response when there is one, otherwise fetches and
+// caches it
+pub fn cached(path: &Path, fetch: impl FnOnce() -> io::Result<String>) -> io::Result<String> {
+    if let Ok(text) = fs::read_to_string(path) {
+        return Ok(text);
+    }
+    let text = fetch()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, &text)?;
+    Ok(text)
+}
+
+// Where downloaded data is kept between runs
+pub fn cache_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join("solar_battery_simulation")
+}
+
+#[test]
+fn test_shift_to_year() {
+    let leap_day = chrono::NaiveDate::from_ymd_opt(2020, 2, 29).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let march = chrono::NaiveDate::from_ymd_opt(2020, 3, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let shifted = shift_to_year(&[(leap_day, 500.), (march, 800.)], 2023);
+    assert_eq!(shifted, vec![(march.with_year(2023).unwrap(), 800.)]);
+    assert_eq!(coefficient_at(&shifted, march.with_year(2023).unwrap()), 0.8)
+}
+
+#[test]
+fn test_cached() {
+    let path = cache_dir().join(format!("test_cached_{}.txt", std::process::id()));
+    let _ = fs::remove_file(&path);
+    assert_eq!(cached(&path, || Ok("fetched".to_string())).unwrap(), "fetched");
+    // Second call never reaches the network
+    assert_eq!(cached(&path, || Err(io::Error::other("offline"))).unwrap(), "fetched");
+    fs::remove_file(&path).unwrap()
+}
@@ -1,93 +1,1419 @@
+pub mod alternator;
+pub mod appliance;
+pub mod bank;
+pub mod chemistry;
+pub mod clearsky;
+pub mod compare;
+pub mod controller;
+pub mod dispatcher;
+pub mod emissions;
+pub mod generator;
+pub mod grid;
 pub mod gui;
+pub mod hydro;
+pub mod inverter;
+pub mod irradiance;
+pub mod load;
+pub mod margin;
+pub mod montecarlo;
+pub mod nsrdb;
+pub mod optimize;
+pub mod orbit;
+pub mod outage;
+pub mod panel;
+pub mod planet;
+pub mod pvgis;
+pub mod scenario;
+pub mod schedule;
+pub mod series;
+pub mod snow;
+pub mod summary;
+pub mod sweep;
+pub mod system;
+pub mod tariff;
+pub mod temperature;
+pub mod sun;
+mod trace;
+pub mod weather;
+pub mod wind;
+pub mod wiring;
 
-use std::f32::consts::PI;
 use plotters::prelude::*;
 use plotters::coord::types::RangedDateTime;
-use chrono::{Datelike, Timelike, Duration, NaiveDateTime, NaiveDate, NaiveTime};
+use chrono::{Datelike, Timelike, Duration, Months, NaiveDateTime, NaiveDate, NaiveTime};
+use alternator::Alternator;
+use bank::{BatteryBank, DispatchPolicy};
+use chemistry::Chemistry;
+use clearsky::SolarModel;
+use controller::ChargeController;
+use dispatcher::Dispatcher;
+use generator::Generator;
+use grid::Grid;
+use hydro::MicroHydro;
+use inverter::Inverter;
+use irradiance::IrradianceSeries;
+use load::Load;
+use orbit::Orbit;
+use outage::{Component, FailureGenerator, Outage};
+use panel::PanelSpec;
+use planet::Planet;
+use snow::{SnowCover, SnowModel};
+use sun::{Orientation, SolarPosition, TrackingMode};
+use temperature::{AmbientTemperature, BatteryThermal};
+use weather::{DailyWeather, SolarNoise, WeatherGenerator};
+use wind::{WindGenerator, WindTurbine};
+use wiring::Wiring;
 
 #[derive (Debug, Clone)]
 pub struct SimState {
     pub load: f32, // watts
+    pub standby_load: f32, // watts of phantom draw from appliances that are switched off
+    pub load_growth: f32, // fraction the load grows each year from the start of the run
     pub battery_capacity: f32, // Wh
+    pub initial_soc: f32, // fraction of the rated capacity stored when a run starts
+    pub charge_efficiency: f32, // fraction of energy into the battery that is stored
+    pub discharge_efficiency: f32, // fraction of stored energy drawn that reaches the bus
+    pub max_charge_power: f32, // watts into the battery (or all banks together), infinite when unlimited
+    pub max_discharge_power: f32, // watts out of the battery, infinite when unlimited
+    pub minimum_soc: f32, // fraction of battery_capacity kept in reserve, loads are cut below it
+    pub reconnect_soc: f32, // state of charge that ends a load cut, 0 to reconnect as soon as there's charge
+    pub load_disconnected: bool, // whether the low-voltage disconnect has cut the loads
+    pub self_discharge: f32, // fraction of the stored energy lost per month
+    pub taper_soc: f32, // state of charge where charge acceptance starts to fall, 1 for none
+    pub absorption_hours: f32, // time constant of the absorption phase closing the last of the gap
+    pub cycle_life: f32, // equivalent full cycles until the capacity fades to 80%, infinite for no fade
+    pub cycles: f32, // equivalent full cycles of the battery so far in the run
+    pub stored_compensation: f32, // Wh of rounding error carried into the next step's charge
+    pub soh_history: Vec<f32>, // fraction of the rated capacity left
+    pub scheduled_replacements: Vec<NaiveDateTime>, // when to fit a new battery regardless of its health
+    pub replacement_soh: f32, // state of health that prompts a new battery, 0 to run it down
+    pub replacements: Vec<NaiveDateTime>, // when the battery was replaced during the run
+    pub battery_temperature_rise: f32, // degrees C the battery compartment runs above the air, 0 when unheated
+    pub battery_thermal: Option<BatteryThermal>, // the battery follows its compartment instantly when None
+    pub battery_temperature: f32, // degrees C, tracked through the run with a thermal model
+    pub battery_temperature_history: Vec<f32>, // degrees C
+    pub chemistry: Option<Chemistry>, // capacity doesn't change with temperature when None
     pub current_stored_energy: f32, // Wh
     pub solar_nominal_output: f32, // watts
+    pub panel_degradation: f32, // fraction of output lost per year from the start of the run
+    pub panel: Option<PanelSpec>, // rated efficiency at any cell temperature and light level when None
     pub charge_history: Vec<f32>, // Wh
+    pub soc_history: Vec<f32>, // percent of the rated capacity
     pub latitude: f32,
+    pub longitude: f32, // degrees east
+    pub elevation: f32, // m above sea level
+    pub utc_offset: f32, // hours, time zone of the simulation clock
     pub history_dates: Vec<NaiveDateTime>,
     pub now: NaiveDateTime, 
     pub step_size: Duration,
-    pub start_day: u32,
-    pub end_day: u32,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate, // the run stops at midnight as this day begins, a year later if it isn't after the start
     pub solar_history: Vec<f32>,
-    pub daylight_history: Vec<f32>
+    pub daylight_history: Vec<f32>,
+    pub orbit: Option<Orbit>, // replaces surface daylight with orbital eclipses when set
+    pub system_voltage: f32, // volts
+    pub battery_current_history: Vec<f32>, // amps, positive when charging
+    pub controller_current_history: Vec<f32>, // amps out of the charge controller
+    pub load_current_history: Vec<f32>, // amps drawn by the loads, inverter, and controller
+    pub wiring: Wiring,
+    pub wiring_loss_history: Vec<f32>, // Wh
+    pub charge_controller: Option<ChargeController>, // lossless when None
+    pub inverter: Option<Inverter>, // the load is DC when None
+    pub inverter_history: Vec<f32>, // Wh consumed by the inverter itself
+    pub bms_draw: f32, // watts, always on while there's a battery management system
+    pub parasitic_history: Vec<f32>, // Wh drawn by the controller, BMS, and inverter themselves
+    pub served_load_history: Vec<f32>, // watts
+    pub orientation: Option<Orientation>, // always facing the sun when None
+    pub tracking: TrackingMode, // how the mount moves from its orientation
+    pub albedo: f32, // fraction of light the ground reflects, e.g. clearsky::SNOW_ALBEDO
+    pub bifaciality: f32, // rear efficiency relative to the front, 0 for single-sided panels
+    pub solar_model: SolarModel,
+    pub air_mass_attenuation: bool, // dims the simple curve when the sun is low
+    pub irradiance: Option<IrradianceSeries>, // replaces the solar and weather models when set
+    pub power_series: Option<Vec<(NaiveDateTime, f32)>>, // W, logged array output, replaces all solar modeling when set
+    pub unmet_history: Vec<f32>, // Wh of load the battery could not supply
+    pub clipped_energy_history: Vec<f32>, // Wh of surplus wasted, off the grid, with the battery full or charging at its limit
+    pub dump_load: f32, // watts the diversion load can absorb, e.g. a water heater element
+    pub dump_history: Vec<f32>, // Wh of surplus diverted
+    pub discharge_limited_history: Vec<f32>, // Wh of load unserved past the discharge power limit
+    pub load_cuts: Vec<NaiveDateTime>, // when each run of unserved load began
+    pub weather: Option<WeatherGenerator>, // clear skies every day when None
+    pub weather_days: Vec<DailyWeather>, // generated at the start of each run
+    pub solar_noise: Option<SolarNoise>, // steady output through each step when None
+    pub load_noise: Option<SolarNoise>, // the same draw from step to step when None, seeded apart from the solar noise
+    pub monthly_derate: [f32; 12], // factor on solar output for each month, e.g. for coastal fog in June
+    pub generation_factor: f32, // factor on all solar, wind, and hydro output, e.g. to find a design's margin
+    pub loads: Vec<Load>, // in addition to the constant load
+    pub shed_soc: Vec<f32>, // state of charge below which each load priority tier is shed, tier 1 first
+    pub shed_hours: Vec<f32>, // hours each of the loads spent shed in the run
+    pub deferred_history: Vec<f32>, // Wh served to deferrable loads
+    pub pumped_history: Vec<f32>, // liters
+    pub planet: Planet,
+    pub ambient_temperature: AmbientTemperature, // the one source for every temperature-aware model
+    pub temperature_history: Vec<f32>, // degrees C
+    pub array_strings: u32, // parallel strings, each of which can fail on its own
+    pub failures: Option<FailureGenerator>, // no equipment faults when None
+    pub snow: Option<SnowModel>, // panels never snowed over when None
+    pub snow_events: Vec<SnowCover>, // generated at the start of each run
+    pub maintenance: Vec<Outage>, // planned downtime windows
+    pub outage_events: Vec<Outage>, // random faults and maintenance, gathered at the start of each run
+    pub array_online_history: Vec<f32>, // fraction of the array producing
+    pub banks: Vec<BatteryBank>, // a single battery of battery_capacity when empty
+    pub dispatch: DispatchPolicy,
+    pub bank_charge_history: Vec<Vec<f32>>, // Wh, one series per bank
+    pub grid: Option<Grid>, // off-grid when None
+    pub grid_import_history: Vec<f32>, // Wh
+    pub grid_export_history: Vec<f32>, // Wh
+    pub generator: Option<Generator>, // no backup generator when None
+    pub dispatcher: Dispatcher, // how the generator shares the work with the battery
+    pub generator_running: bool,
+    pub generator_starts: Vec<NaiveDateTime>,
+    pub generator_history: Vec<f32>, // Wh delivered
+    pub fuel_history: Vec<f32>, // liters
+    pub wind_turbine: Option<WindTurbine>, // no wind generation when None
+    pub wind: Option<WindGenerator>, // keeps wind_speeds as imported when None
+    pub wind_speeds: Vec<(NaiveDateTime, f32)>, // m/s at 10 m, hourly, generated at the start of each run
+    pub wind_history: Vec<f32>, // watts
+    pub hydro: Option<MicroHydro>, // no hydro turbine when None
+    pub hydro_history: Vec<f32>, // watts
+    pub alternator: Option<Alternator>, // no charging while driving when None
+    pub alternator_history: Vec<f32>, // watts
 }
+impl Default for SimState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SimState {
     pub fn new() -> SimState {
         SimState {
             load: 0.,
+            standby_load: 0.,
+            load_growth: 0.,
             battery_capacity: 0.,
             current_stored_energy: 0.,
+            initial_soc: 0.,
+            charge_efficiency: 1.,
+            discharge_efficiency: 1.,
+            max_charge_power: f32::INFINITY,
+            max_discharge_power: f32::INFINITY,
+            minimum_soc: 0.,
+            reconnect_soc: 0.,
+            load_disconnected: false,
+            self_discharge: 0.,
+            taper_soc: 1.,
+            absorption_hours: 1.,
+            cycle_life: f32::INFINITY,
+            cycles: 0.,
+            stored_compensation: 0.,
+            soh_history: Vec::new(),
+            scheduled_replacements: Vec::new(),
+            replacement_soh: 0.,
+            replacements: Vec::new(),
+            battery_temperature_rise: 0.,
+            battery_thermal: None,
+            battery_temperature: 25.,
+            battery_temperature_history: Vec::new(),
+            chemistry: None,
             solar_nominal_output: 0.,
+            panel_degradation: 0.,
+            panel: None,
             charge_history: Vec::new(),
+            soc_history: Vec::new(),
             latitude: 0.,
+            longitude: 0.,
+            elevation: 0.,
+            utc_offset: 0.,
             history_dates: Vec::new(),
             now:  NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), NaiveTime::from_hms_opt(0,0,0).unwrap()),
             step_size: Duration::minutes(45),
-            start_day: 1,
-            end_day: 364,
+            start_date: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2023, 12, 30).unwrap(),
             solar_history: Vec::new(),
-            daylight_history: Vec::new()
+            daylight_history: Vec::new(),
+            orbit: None,
+            system_voltage: 12.,
+            battery_current_history: Vec::new(),
+            controller_current_history: Vec::new(),
+            load_current_history: Vec::new(),
+            wiring: Wiring::default(),
+            wiring_loss_history: Vec::new(),
+            charge_controller: None,
+            inverter: None,
+            inverter_history: Vec::new(),
+            bms_draw: 0.,
+            parasitic_history: Vec::new(),
+            served_load_history: Vec::new(),
+            orientation: None,
+            tracking: TrackingMode::Fixed,
+            albedo: clearsky::GRASS_ALBEDO,
+            bifaciality: 0.,
+            solar_model: SolarModel::SimpleCurve,
+            air_mass_attenuation: true,
+            irradiance: None,
+            power_series: None,
+            unmet_history: Vec::new(),
+            clipped_energy_history: Vec::new(),
+            discharge_limited_history: Vec::new(),
+            load_cuts: Vec::new(),
+            weather: None,
+            weather_days: Vec::new(),
+            solar_noise: None,
+            load_noise: None,
+            monthly_derate: [1.; 12],
+            generation_factor: 1.,
+            loads: Vec::new(),
+            shed_soc: Vec::new(),
+            shed_hours: Vec::new(),
+            deferred_history: Vec::new(),
+            pumped_history: Vec::new(),
+            planet: Planet::earth(),
+            ambient_temperature: AmbientTemperature::default(),
+            temperature_history: Vec::new(),
+            array_strings: 1,
+            failures: None,
+            snow: None,
+            snow_events: Vec::new(),
+            maintenance: Vec::new(),
+            outage_events: Vec::new(),
+            array_online_history: Vec::new(),
+            banks: Vec::new(),
+            dispatch: DispatchPolicy::default(),
+            bank_charge_history: Vec::new(),
+            grid: None,
+            dump_load: 0.,
+            dump_history: Vec::new(),
+            grid_import_history: Vec::new(),
+            grid_export_history: Vec::new(),
+            generator: None,
+            dispatcher: Dispatcher::default(),
+            generator_running: false,
+            generator_starts: Vec::new(),
+            generator_history: Vec::new(),
+            fuel_history: Vec::new(),
+            wind_turbine: None,
+            wind: None,
+            wind_speeds: Vec::new(),
+            wind_history: Vec::new(),
+            hydro: None,
+            hydro_history: Vec::new(),
+            alternator: None,
+            alternator_history: Vec::new(),
+        }
+    }
+
+    // Plane-of-array irradiance in W/m2, moved onto the simulated year and
+    // scaled by the array's nominal output
+    pub fn with_irradiance_series(mut self, series: Vec<(NaiveDateTime, f32)>) -> SimState {
+        self.irradiance = Some(irradiance::shift_to_year(&series, simulation_start(&self).year()));
+        self
+    }
+
+    // Measured output of an existing array in W, moved onto the simulated
+    // year and used as is
+    pub fn with_power_series(mut self, series: Vec<(NaiveDateTime, f32)>) -> SimState {
+        self.power_series = Some(irradiance::shift_to_year(&series, simulation_start(&self).year()));
+        self
+    }
+
+    // Starting charge in Wh rather than as a state of charge, for the
+    // capacity already set
+    pub fn with_initial_charge(mut self, energy: f32) -> SimState {
+        let capacity = self.storage_capacity();
+        self.initial_soc = if capacity > 0. { (energy / capacity).clamp(0., 1.) } else { 0. };
+        self
+    }
+
+    // Battery capacity from a datasheet's amp-hours at the bank's nominal
+    // voltage, which becomes the system voltage
+    pub fn with_battery_amp_hours(mut self, amp_hours: f32, voltage: f32) -> SimState {
+        self.battery_capacity = amp_hours * voltage;
+        self.system_voltage = voltage;
+        self
+    }
+
+    pub fn battery_amp_hours(&self) -> f32 {
+        if self.system_voltage > 0. { self.battery_capacity / self.system_voltage } else { 0. }
+    }
+
+    // Rated capacity of the battery or all the banks together, in Wh
+    pub fn storage_capacity(&self) -> f32 {
+        if self.banks.is_empty() {
+            self.battery_capacity
+        } else {
+            self.banks.iter().map(|b| b.capacity).sum()
         }
     }
+
+    pub fn soc_percent(&self) -> f32 {
+        let capacity = self.storage_capacity();
+        if capacity > 0. { 100. * self.current_stored_energy / capacity } else { 0. }
+    }
+
+    // Sets the chemistry and its typical efficiency, reserve, and
+    // self-discharge
+    pub fn with_chemistry(mut self, chemistry: Chemistry) -> SimState {
+        self.chemistry = Some(chemistry);
+        self.charge_efficiency = chemistry.charge_efficiency();
+        self.discharge_efficiency = chemistry.discharge_efficiency();
+        self.minimum_soc = chemistry.minimum_soc();
+        self.self_discharge = chemistry.self_discharge();
+        self.taper_soc = chemistry.taper_soc();
+        self.cycle_life = chemistry.cycle_life();
+        self
+    }
+
+    // Configuration values, named for display, used to compare scenarios
+    pub fn parameters(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Load [W]", format!("{}", self.load)),
+            ("Standby Load [W]", format!("{}", self.standby_load)),
+            ("Load Growth [per year]", format!("{}", self.load_growth)),
+            ("Battery Capacity [Wh]", format!("{}", self.battery_capacity)),
+            ("Initial SOC", format!("{}", self.initial_soc)),
+            ("Charge Efficiency", format!("{}", self.charge_efficiency)),
+            ("Discharge Efficiency", format!("{}", self.discharge_efficiency)),
+            ("Max Charge Power [W]", format!("{}", self.max_charge_power)),
+            ("Max Discharge Power [W]", format!("{}", self.max_discharge_power)),
+            ("Minimum SOC", format!("{}", self.minimum_soc)),
+            ("Reconnect SOC", format!("{}", self.reconnect_soc)),
+            ("Self Discharge [/month]", format!("{}", self.self_discharge)),
+            ("Taper SOC", format!("{}", self.taper_soc)),
+            ("Absorption Time [h]", format!("{}", self.absorption_hours)),
+            ("Cycle Life", format!("{}", self.cycle_life)),
+            ("Scheduled Replacements", format!("{:?}", self.scheduled_replacements)),
+            ("Replacement SOH", format!("{}", self.replacement_soh)),
+            ("Battery Temperature Rise [C]", format!("{}", self.battery_temperature_rise)),
+            ("Battery Thermal", format!("{:?}", self.battery_thermal)),
+            ("Chemistry", self.chemistry.map_or("None".to_string(), |c| c.to_string())),
+            ("Solar Power Nominal [W]", format!("{}", self.solar_nominal_output)),
+            ("Panel Degradation [per year]", format!("{}", self.panel_degradation)),
+            ("Panel", format!("{:?}", self.panel.map(|p| p.name))),
+            ("Latitude [degrees]", format!("{}", self.latitude)),
+            ("Longitude [degrees]", format!("{}", self.longitude)),
+            ("Elevation [m]", format!("{}", self.elevation)),
+            ("UTC Offset [hours]", format!("{}", self.utc_offset)),
+            ("Step Size [minutes]", format!("{}", self.step_size.num_minutes())),
+            ("Start Date", format!("{}", self.start_date)),
+            ("End Date", format!("{}", self.end_date)),
+            ("Orbit", format!("{:?}", self.orbit)),
+            ("System Voltage [V]", format!("{}", self.system_voltage)),
+            ("Wiring", format!("{:?}", self.wiring)),
+            ("Charge Controller", format!("{:?}", self.charge_controller)),
+            ("Inverter", format!("{:?}", self.inverter)),
+            ("Orientation", format!("{:?}", self.orientation)),
+            ("Tracking", format!("{:?}", self.tracking)),
+            ("Albedo", format!("{}", self.albedo)),
+            ("Bifaciality", format!("{}", self.bifaciality)),
+            ("Solar Model", format!("{:?}", self.solar_model)),
+            ("Air Mass Attenuation", format!("{}", self.air_mass_attenuation)),
+            ("Irradiance Data", match &self.irradiance {
+                Some(series) => format!("{} points", series.len()),
+                None => "None".to_string(),
+            }),
+            ("Power Data", match &self.power_series {
+                Some(series) => format!("{} points", series.len()),
+                None => "None".to_string(),
+            }),
+            ("Weather", format!("{:?}", self.weather)),
+            ("Solar Noise", format!("{:?}", self.solar_noise)),
+            ("Load Noise", format!("{:?}", self.load_noise)),
+            ("Monthly Derate", format!("{:?}", self.monthly_derate)),
+            ("Generation Factor", format!("{}", self.generation_factor)),
+            ("Loads", format!("{:?}", self.loads)),
+            ("Shed SOC", format!("{:?}", self.shed_soc)),
+            ("Planet", format!("{:?}", self.planet)),
+            ("Ambient Temperature", format!("{:?}", self.ambient_temperature)),
+            ("Array Strings", format!("{}", self.array_strings)),
+            ("Failures", format!("{:?}", self.failures)),
+            ("Snow", format!("{:?}", self.snow)),
+            ("Maintenance", format!("{:?}", self.maintenance)),
+            ("Banks", format!("{:?}", self.banks)),
+            ("Dispatch", format!("{:?}", self.dispatch)),
+            ("Grid", format!("{:?}", self.grid)),
+            ("Dump Load [W]", format!("{}", self.dump_load)),
+            ("Generator", format!("{:?}", self.generator)),
+            ("Dispatcher", format!("{:?}", self.dispatcher)),
+            ("Wind Turbine", format!("{:?}", self.wind_turbine)),
+            ("Wind", format!("{:?}", self.wind)),
+            ("Micro-Hydro", format!("{:?}", self.hydro)),
+            ("Alternator", format!("{:?}", self.alternator)),
+        ]
+    }
 }
 
 pub fn run_simulation(state: &SimState) -> SimState {
+    trace::timed!("run_simulation", start_date = %state.start_date, end_date = %state.end_date, step_minutes = state.step_size.num_minutes());
+    let mut state = start_run(state);
+    let end = simulation_end(&state);
+    while state.now < end {
+        step_mut(&mut state);
+    }
+    state
+}
+
+// One step of a streamed run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepRecord {
+    pub time: NaiveDateTime, // start of the step
+    pub soc: f32, // percent, at the end of the step
+    pub solar: f32, // watts
+    pub net: f32, // Wh into the battery, negative when it discharged
+    pub unmet: f32, // Wh
+}
+
+// Runs the configured simulation a step at a time without keeping its
+// histories, for long runs that are only filtered or aggregated
+pub struct Steps {
+    state: SimState,
+    end: NaiveDateTime,
+}
+
+impl Steps {
+    // The run as of the last step, e.g. for the battery's state of health
+    pub fn state(&self) -> &SimState {
+        &self.state
+    }
+}
+
+impl Iterator for Steps {
+    type Item = StepRecord;
+
+    fn next(&mut self) -> Option<StepRecord> {
+        if self.state.now >= self.end {
+            return None;
+        }
+        let time = self.state.now;
+        let before = self.state.current_stored_energy;
+        step_mut(&mut self.state);
+        let record = StepRecord {
+            time,
+            soc: self.state.soc_percent(),
+            solar: self.state.solar_history.last().cloned().unwrap_or(0.),
+            net: self.state.current_stored_energy - before,
+            unmet: self.state.unmet_history.last().cloned().unwrap_or(0.),
+        };
+        clear_histories(&mut self.state, 1);
+        // The next step reads the last shortfall to spot the start of a cut
+        self.state.unmet_history.push(record.unmet);
+        Some(record)
+    }
+}
+
+impl SimState {
+    pub fn iter_steps(&self) -> Steps {
+        let state = begin_run(self, 1);
+        Steps { end: simulation_end(&state), state }
+    }
+}
+
+#[test]
+fn test_iter_steps() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 1000.;
+    state.solar_nominal_output = 200.;
+    state.load = 40.;
+    let result = run_simulation(&state);
+    assert!(!result.load_cuts.is_empty());
+    let mut steps = state.iter_steps();
+    let records: Vec<StepRecord> = steps.by_ref().collect();
+    assert_eq!(records.len(), result.history_dates.len());
+    assert_eq!(records[10].time, result.history_dates[10]);
+    assert_eq!(records[10].solar, result.solar_history[10]);
+    assert_eq!(records[10].soc, result.soc_history[11]);
+    assert_eq!(records.iter().map(|r| r.unmet).sum::<f32>(), result.unmet_history.iter().sum::<f32>());
+    assert!(steps.state().charge_history.is_empty());
+    assert_eq!(steps.state().load_cuts, result.load_cuts);
+    assert_eq!(steps.state().current_stored_energy, result.current_stored_energy)
+}
+
+// Copy of the configuration positioned at the start of a run with its
+// histories cleared and any weather generated
+pub fn start_run(state: &SimState) -> SimState {
+    // Sized up front so a long run never reallocates its histories
+    begin_run(state, step_count(state))
+}
+
+fn begin_run(state: &SimState, steps: usize) -> SimState {
     let mut state = state.clone();
-    state.now = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()
-        .with_ordinal(match state.start_day {
-            0 => 1,
-            _ => state.start_day
-        }).unwrap()
-        .and_hms_opt(0, 0, 0).unwrap();
+    state.now = simulation_start(&state);
+    clear_histories(&mut state, steps);
 
-    state.current_stored_energy = 0.;
-    state.charge_history = Vec::new();
-    state.history_dates = Vec::new();
-    state.solar_history = Vec::new();
-    state.daylight_history = Vec::new();
-
-    let end = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()
-        .with_ordinal(match state.end_day {
-            0 => 1,
-            _ => state.end_day
-        }).unwrap()
-        .and_hms_opt(0, 0, 0).unwrap();
+    state.current_stored_energy = state.initial_soc * state.storage_capacity();
+    state.cycles = 0.;
+    state.stored_compensation = 0.;
+    state.battery_temperature = compartment_temperature(&state);
+    state.replacements = Vec::new();
+    state.load_disconnected = false;
+    state.load_cuts = Vec::new();
+    state.shed_hours = vec![0.; state.loads.len()];
+    for bank in state.banks.iter_mut() {
+        bank.stored = state.initial_soc * bank.capacity;
+    }
+    state.generator_running = false;
+    state.generator_starts = Vec::new();
 
-    while state.now < end {
+    let end = simulation_end(&state);
+    state.weather_days = match &state.weather {
+        Some(generator) => generator.generate(state.now.date(), end.date()),
+        None => Vec::new(),
+    };
+    if let Some(generator) = &state.wind {
+        state.wind_speeds = generator.generate(state.now, end);
+    }
+    // A sun-facing panel is tilted about as far as the latitude
+    let tilt = state.orientation.map_or(state.latitude.abs(), |o| o.tilt);
+    state.snow_events = match &state.snow {
+        Some(model) => model.generate(state.now.date(), end.date(), tilt),
+        None => Vec::new(),
+    };
+    state.outage_events = match &state.failures {
+        Some(generator) => generator.generate(state.now, end),
+        None => Vec::new(),
+    };
+    state.outage_events.extend(state.maintenance.iter().cloned());
+    if let Some(grid) = &state.grid {
+        state.outage_events.extend(grid.outages(state.now, end));
+    }
+    state.outage_events.sort_by_key(|o| o.start);
+    state
+}
+
+// Steps from the start of the run to its end, counting a final partial step
+pub fn step_count(state: &SimState) -> usize {
+    let step = state.step_size.num_seconds();
+    if step <= 0 {
+        return 0;
+    }
+    let span = (simulation_end(state) - simulation_start(state)).num_seconds().max(0);
+    (span as u64).div_ceil(step as u64) as usize
+}
+
+// Every series recorded a value per step, apart from the dates and banks
+fn histories(state: &mut SimState) -> [&mut Vec<f32>; 28] {
+    [
+        &mut state.soh_history,
+        &mut state.battery_temperature_history,
+        &mut state.charge_history,
+        &mut state.soc_history,
+        &mut state.solar_history,
+        &mut state.daylight_history,
+        &mut state.wiring_loss_history,
+        &mut state.battery_current_history,
+        &mut state.controller_current_history,
+        &mut state.load_current_history,
+        &mut state.inverter_history,
+        &mut state.parasitic_history,
+        &mut state.served_load_history,
+        &mut state.unmet_history,
+        &mut state.clipped_energy_history,
+        &mut state.dump_history,
+        &mut state.discharge_limited_history,
+        &mut state.pumped_history,
+        &mut state.deferred_history,
+        &mut state.temperature_history,
+        &mut state.array_online_history,
+        &mut state.grid_import_history,
+        &mut state.grid_export_history,
+        &mut state.generator_history,
+        &mut state.fuel_history,
+        &mut state.wind_history,
+        &mut state.hydro_history,
+        &mut state.alternator_history,
+    ]
+}
+
+// A copy of the state for trying out its next step, leaving out the
+// histories apart from the last shortfall, which the step reads to spot
+// the start of a cut
+pub(crate) fn probe(state: &mut SimState) -> SimState {
+    let mut aside = SimState::new();
+    swap_histories(state, &mut aside);
+    let mut probe = state.clone();
+    swap_histories(state, &mut aside);
+    probe.unmet_history.extend(state.unmet_history.last());
+    probe
+}
+
+fn swap_histories(a: &mut SimState, b: &mut SimState) {
+    std::mem::swap(&mut a.history_dates, &mut b.history_dates);
+    std::mem::swap(&mut a.bank_charge_history, &mut b.bank_charge_history);
+    for (a, b) in histories(a).into_iter().zip(histories(b)) {
+        std::mem::swap(a, b);
+    }
+}
+
+// Empties every per-step history, leaving room for the given number of steps
+fn clear_histories(state: &mut SimState, steps: usize) {
+    state.history_dates.clear();
+    state.history_dates.reserve(steps);
+    for history in histories(state) {
+        history.clear();
+        history.reserve(steps);
+    }
+    // Cleared in place, so stepping through a run doesn't reallocate
+    state.bank_charge_history.resize_with(state.banks.len(), Vec::new);
+    for history in state.bank_charge_history.iter_mut() {
+        history.clear();
+        history.reserve(steps);
+    }
+}
+
+pub fn simulation_start(state: &SimState) -> NaiveDateTime {
+    state.start_date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+// An end date on or before the start wraps into the following year, so a
+// run can go from November 1 to March 1 without naming the years
+pub fn simulation_end(state: &SimState) -> NaiveDateTime {
+    let mut end = state.end_date;
+    while end <= state.start_date {
+        end = end + Months::new(12);
+    }
+    end.and_hms_opt(0, 0, 0).unwrap()
+}
+
+// Change in stored energy for energy into (positive) or out of the battery
+pub fn stored_change(state: &SimState, energy: f32) -> f32 {
+    if energy >= 0. {
+        energy * state.charge_efficiency
+    } else {
+        energy / state.discharge_efficiency
+    }
+}
+
+// Energy into (positive) or out of the battery for a change in stored energy
+pub fn terminal_energy(state: &SimState, stored: f32) -> f32 {
+    if stored >= 0. {
+        stored / state.charge_efficiency
+    } else {
+        stored * state.discharge_efficiency
+    }
+}
+
+// Remaining share of the rated battery capacity, fading linearly with
+// equivalent full cycles
+pub fn state_of_health(state: &SimState) -> f32 {
+    (1. - 0.2 * state.cycles / state.cycle_life).max(0.)
+}
+
+// Most the single battery can store over the step, in Wh. Charging runs at
+// full power up to the taper, after which the remaining gap closes
+// exponentially as the charger holds the absorption voltage.
+pub fn charge_acceptance(state: &SimState) -> f32 {
+    if state.taper_soc >= 1. || !state.banks.is_empty() {
+        return f32::INFINITY;
+    }
+    let capacity = state.battery_capacity.max(0.) * state_of_health(state);
+    let taper = state.taper_soc * capacity;
+    let bulk = (taper - state.current_stored_energy).max(0.);
+    let gap = capacity - state.current_stored_energy.max(taper);
+    bulk + gap.max(0.) * (1. - (-step_hours(state) / state.absorption_hours).exp())
+}
+
+// Share of the battery the loads can't reach: the minimum state of charge,
+// and whatever capacity the chemistry can't deliver in the cold or, by
+// Peukert's law, at a discharge power (watts) above the 20-hour rate
+pub fn reserve_fraction(state: &SimState, discharge_power: f32) -> f32 {
+    match state.chemistry {
+        Some(chemistry) => {
+            let rate = discharge_power / (state.battery_capacity / 20.);
+            let available = chemistry.capacity_factor(battery_temperature(state)) * chemistry.rate_capacity(rate);
+            1. - (1. - state.minimum_soc) * available
+        },
+        None => state.minimum_soc,
+    }
+}
+
+// Neumaier summation: adds the value and the error carried from earlier
+// additions, returning the sum and the new error. Small charges into a
+// large battery would otherwise round away over hundreds of thousands of
+// steps.
+pub fn compensated_add(sum: f32, value: f32, compensation: f32) -> (f32, f32) {
+    let value = value + compensation;
+    let total = sum + value;
+    let error = if sum.abs() >= value.abs() {
+        (sum - total) + value
+    } else {
+        (value - total) + sum
+    };
+    (total, error)
+}
+
+#[test]
+fn test_compensated_add() {
+    let (mut naive, mut sum, mut compensation) = (1e6_f32, 1e6_f32, 0.);
+    for _ in 0..100_000 {
+        naive += 0.01;
+        (sum, compensation) = compensated_add(sum, 0.01, compensation);
+    }
+    assert_eq!(naive, 1e6);
+    assert!((sum + compensation - 1_001_000.).abs() < 0.1);
+    // Steps keep the carried error too
+    let mut state = SimState::new();
+    state.battery_capacity = 1e7;
+    state.current_stored_energy = 1e6;
+    state.step_size = Duration::hours(1);
+    state.load = -0.01;
+    for _ in 0..1000 {
         state = step(&state);
     }
-    state.clone()
+    assert!((state.current_stored_energy - 1_000_010.).abs() < 0.1)
+}
+
+// Average current at the system voltage for energy over the step
+fn amps(state: &SimState, energy: f32) -> f32 {
+    let hours = step_hours(state);
+    if state.system_voltage <= 0. || hours <= 0. {
+        return 0.;
+    }
+    energy / hours / state.system_voltage
 }
 
+// Advances a copy of the state by one step
 pub fn step(state: &SimState) -> SimState {
-    let delta = net_energy(&state);
+    let mut next = state.clone();
+    step_mut(&mut next);
+    next
+}
+
+// Advances the state by one step in place, appending to its histories
+pub fn step_mut(state: &mut SimState) {
+    step_with(state, 0.)
+}
 
-    let unbounded_charge = state.current_stored_energy + delta;
+// A step with energy from components outside the model, in Wh at the bus,
+// positive for sources and negative for sinks
+pub(crate) fn step_with(state: &mut SimState, external: f32) {
+    let charged = charging_energy(state);
+    let consumed = consumed_energy(state);
+    let grid_up = grid_available(state);
+    // A tripped low-voltage disconnect leaves the loads without the battery
+    let disconnected = if state.load_disconnected && !grid_up { consumed.max(0.) } else { 0. };
+    let mut delta = charged - consumed + disconnected + external;
+    // A grid holding the battery, for outages or a pricier hour, covers deficits
+    let grid = state.grid.as_ref().filter(|_| grid_up);
+    // and otherwise whatever would draw the battery into its backup reserve
+    let imported = match grid {
+        Some(grid) if grid.holds_battery(state.now) => (-delta).max(0.),
+        Some(grid) => {
+            let usable = (state.current_stored_energy - grid.backup_reserve * state.storage_capacity()).max(0.);
+            ((-delta).max(0.) + terminal_energy(state, -usable)).max(0.)
+        },
+        None => 0.,
+    };
+    delta += imported;
+    // A running generator covers the deficit, and under cycle charging fills
+    // the battery too, throttling back as it nears full
+    let hours = step_hours(state);
+    // A generator down for service or repair stays off until it's back
+    let generator_up = |time| !outage::is_down(&state.outage_events, Component::Generator, time);
+    let generator_on = state.generator_running && !grid_up && generator_up(state.now);
+    let generator_available = generator_up(state.now + state.step_size);
+    let generated = match state.generator {
+        Some(generator) if generator_on => {
+            let room = (state.storage_capacity() - state.current_stored_energy).max(0.) / state.charge_efficiency;
+            state.dispatcher.generator_output(&generator, -delta, room, hours)
+        },
+        _ => 0.,
+    };
+    delta += generated;
 
-    let mut new_state = state.clone();
-    new_state.charge_history.push(state.current_stored_energy);
-    new_state.current_stored_energy = if unbounded_charge < 0. {
+    // Power limits of the battery, measured at its terminals, and no charging
+    // at all for a chemistry too cold to take it
+    let charge_limit = match state.chemistry {
+        Some(chemistry) if !chemistry.can_charge(battery_temperature(state)) => 0.,
+        _ => (state.max_charge_power * hours).min(charge_acceptance(state) / state.charge_efficiency),
+    };
+    // Cheap grid energy tops the battery up as fast as it will take it
+    let grid_charge = if grid.is_some_and(|g| g.charges_battery(state.now)) {
+        let room = (state.storage_capacity() - state.current_stored_energy).max(0.) / state.charge_efficiency;
+        (charge_limit.min(room) - delta).max(0.)
+    } else {
         0.
-    } else if unbounded_charge > state.battery_capacity {
-        state.battery_capacity
+    };
+    let imported = imported + grid_charge;
+    delta += grid_charge;
+    let accepted = delta.clamp(-state.max_discharge_power * hours, charge_limit);
+    let clipped = (delta - accepted).max(0.);
+    let limited = (accepted - delta).max(0.);
+    let delta = accepted;
+
+    // Everything recorded for the step is read before the state moves on
+    let shares = load_shares(state, firm_load(state));
+    let shed: Vec<bool> = state.loads.iter().map(|l| is_shed(state, l)).collect();
+    let pumped = state.loads.iter().zip(&shares)
+        .map(|(l, share)| l.liters_pumped(state) * share)
+        .sum::<f32>();
+    let deferred_energy = deferred_load(state, firm_load(state)) * hours;
+    let fuel = match state.generator {
+        Some(generator) if generator_on => {
+            generator.fuel(if hours > 0. { generated / hours } else { 0. }, hours)
+        },
+        _ => 0.,
+    };
+    let solar = solar_power(state);
+    let wind = wind_power(state);
+    let hydro = hydro_power(state);
+    let alternator = alternator_power(state);
+    let wiring = wiring_loss(state);
+    let temperature = battery_temperature(state);
+    let compartment = compartment_temperature(state);
+    let inverter = inverter_draw(state) * hours;
+    let parasitic = parasitic_draw(state) * hours;
+    let served = served_load(state);
+    // AC load a sleeping inverter leaves unpowered
+    let asleep = (total_load(state) - served).max(0.) * hours;
+    let ambient = ambient_temperature(state);
+    let online = outage::array_availability(&state.outage_events, state.array_strings, state.now);
+    let daylight = match state.orbit {
+        Some(orbit) => orbit.sunlit_hours_per_day(),
+        None => state.planet.daylight_hours(state.latitude, state.planet.local_time(state.now).0),
+    };
+    let previous = state.current_stored_energy;
+    let was_disconnected = state.load_disconnected;
+    let was_running = state.generator_running;
+    let start = state.now;
+    state.charge_history.push(previous);
+    state.soc_history.push(state.soc_percent());
+    state.soh_history.push(state_of_health(state));
+    // Energy in the battery's own terms, after charging losses or before
+    // discharging losses
+    let stored_delta = stored_change(state, delta);
+    // Self-discharge over the step, with an average month's hours
+    let retained = 1. - state.self_discharge * hours / (24. * 30.44);
+    let (unbounded_charge, reserve) = if state.banks.is_empty() {
+        // Loads are cut at the reserve, though a battery below it can still charge
+        let capacity = state.battery_capacity.max(0.) * state_of_health(state);
+        let held = (previous * retained).min(capacity);
+        let reserve = (reserve_fraction(state, -delta / hours) * capacity).min(held).max(0.);
+        let (unbounded_charge, compensation) = compensated_add(held, stored_delta, state.stored_compensation);
+        state.current_stored_energy = unbounded_charge.clamp(reserve, capacity.max(reserve));
+        state.stored_compensation = if state.current_stored_energy == unbounded_charge { compensation } else { 0. };
+        if state.battery_capacity > 0. {
+            state.cycles += (held - state.current_stored_energy).max(0.) / state.battery_capacity;
+        }
+        (unbounded_charge, reserve)
     } else {
-        state.current_stored_energy + delta
+        for (history, bank) in state.bank_charge_history.iter_mut().zip(&state.banks) {
+            history.push(bank.stored);
+        }
+        for bank in state.banks.iter_mut() {
+            bank.stored *= retained;
+        }
+        let remainder = bank::dispatch(&mut state.banks, state.dispatch, stored_delta, hours);
+        state.current_stored_energy = state.banks.iter().map(|b| b.stored).sum();
+        // Each bank keeps its own reserve
+        (state.current_stored_energy + remainder, 0.)
     };
-    new_state.now = state.now + state.step_size;
-    new_state.history_dates.push(state.now);
-    new_state.solar_history.push(solar_power(state));
-    new_state.daylight_history.push(daylight_hours(state.latitude, state.now.ordinal0()));
-    new_state
+    // Back to energy on the bus
+    let shortfall = limited + disconnected - terminal_energy(state, (unbounded_charge - reserve).min(0.));
+    // Otherwise the grid makes up whatever the battery couldn't supply
+    let (imported, shortfall) = if grid_up { (imported + shortfall.max(0.), 0.) } else { (imported, shortfall) };
+    // Neither the battery nor the grid reaches loads behind a sleeping inverter
+    let shortfall = shortfall + asleep;
+    // Loads only bank what was actually delivered to them
+    let demand = consumed + asleep;
+    let served_share = if demand > 0. { (1. - shortfall.max(0.) / demand).clamp(0., 1.) } else { 1. };
+    let mut loads = std::mem::take(&mut state.loads);
+    for (load, share) in loads.iter_mut().zip(&shares) {
+        load.advance(state, share * served_share);
+    }
+    state.loads = loads;
+    // The disconnect trips when the battery reaches its reserve and holds
+    // until it recharges past the reconnect level
+    state.load_disconnected = state.reconnect_soc > 0. && !grid_up && if was_disconnected {
+        state.soc_percent() < state.reconnect_soc * 100.
+    } else {
+        unbounded_charge < reserve
+    };
+    state.generator_running = !grid_up && generator_available && state.generator.is_some_and(|generator| {
+        state.dispatcher.generator_running(&generator, was_running, state.soc_percent() / 100.)
+    });
+    if state.generator_running && !was_running {
+        state.generator_starts.push(start + state.step_size);
+    }
+    let overflow = terminal_energy(state, (unbounded_charge - state.current_stored_energy).max(0.));
+    // Surplus the battery couldn't take fast enough is diverted to the dump
+    // load, then goes to the grid if it can, as much as the export limit allows
+    let surplus = overflow + clipped;
+    let diverted = surplus.min(state.dump_load.max(0.) * hours);
+    let (exported, wasted) = if grid_up {
+        let limit = state.grid.as_ref().and_then(|g| g.export_limit).map_or(f32::INFINITY, |w| w * hours);
+        let exported = (surplus - diverted).min(limit);
+        (exported, surplus - diverted - exported)
+    } else {
+        (0., surplus - diverted)
+    };
+    state.now = start + state.step_size;
+    // A new battery restores the rated capacity, keeping the charge
+    let scheduled = state.scheduled_replacements.iter().any(|t| *t >= start && *t < state.now);
+    if scheduled || state_of_health(state) < state.replacement_soh {
+        state.cycles = 0.;
+        state.replacements.push(state.now);
+    }
+    state.history_dates.push(start);
+    state.solar_history.push(solar);
+    state.wind_history.push(wind);
+    state.hydro_history.push(hydro);
+    state.alternator_history.push(alternator);
+    state.wiring_loss_history.push(wiring);
+    let battery_energy = terminal_energy(state, state.current_stored_energy - previous);
+    let battery_amps = amps(state, battery_energy);
+    state.battery_current_history.push(battery_amps);
+    state.battery_temperature_history.push(temperature);
+    if let Some(thermal) = state.battery_thermal {
+        state.battery_temperature = thermal.next(state.battery_temperature, compartment, battery_amps, hours);
+    }
+    state.controller_current_history.push(amps(state, charged));
+    state.load_current_history.push(amps(state, consumed - disconnected));
+    state.inverter_history.push(inverter);
+    state.parasitic_history.push(parasitic);
+    state.served_load_history.push(served);
+    if shortfall > 0. && state.unmet_history.last().is_none_or(|u| *u <= 0.) {
+        state.load_cuts.push(start);
+    }
+    state.unmet_history.push(shortfall);
+    state.clipped_energy_history.push(wasted);
+    state.dump_history.push(diverted);
+    state.discharge_limited_history.push(limited);
+    state.grid_import_history.push(imported);
+    state.generator_history.push(generated);
+    state.fuel_history.push(fuel);
+    state.grid_export_history.push(exported);
+    // Water only moves on the power that was delivered
+    state.pumped_history.push(pumped * served_share);
+    state.deferred_history.push(deferred_energy);
+    for (hours_shed, shed) in state.shed_hours.iter_mut().zip(&shed) {
+        if *shed {
+            *hours_shed += hours;
+        }
+    }
+    state.temperature_history.push(ambient);
+    state.array_online_history.push(online);
+    state.daylight_history.push(daylight);
+}
+
+#[test]
+fn test_round_trip_efficiency() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.charge_efficiency = 0.95;
+    state.discharge_efficiency = 0.9;
+    state.step_size = Duration::hours(1);
+    // A negative load stands in for 100 Wh of charging
+    state.load = -100.;
+    let charged = step(&state);
+    assert!((charged.current_stored_energy - 95.).abs() < 0.001);
+    // Serving 45 Wh takes 50 Wh out of the battery
+    let mut state = charged;
+    state.load = 45.;
+    let discharged = step(&state);
+    assert!((discharged.current_stored_energy - 45.).abs() < 0.001);
+    // Only 90% of what is left can reach a larger load
+    let mut state = discharged;
+    state.load = 100.;
+    let empty = step(&state);
+    assert_eq!(empty.current_stored_energy, 0.);
+    assert!((empty.unmet_history.last().unwrap() - (100. - 45. * 0.9)).abs() < 0.001)
+}
+
+#[test]
+fn test_charge_rate_limits() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 500.;
+    state.max_charge_power = 50.;
+    state.max_discharge_power = 30.;
+    state.step_size = Duration::hours(2);
+    // 200 Wh of surplus, of which the battery takes 100
+    state.load = -100.;
+    let charged = step(&state);
+    assert!((charged.current_stored_energy - 600.).abs() < 0.001);
+    assert!((charged.clipped_energy_history.last().unwrap() - 100.).abs() < 0.001);
+    // A full battery still can't serve more than 60 Wh
+    state.load = 40.;
+    let limited = step(&state);
+    assert!((limited.current_stored_energy - 440.).abs() < 0.001);
+    assert!((limited.unmet_history.last().unwrap() - 20.).abs() < 0.001);
+    assert!((limited.discharge_limited_history.last().unwrap() - 20.).abs() < 0.001)
+}
+
+#[test]
+fn test_minimum_soc() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 300.;
+    state.minimum_soc = 0.2;
+    state.step_size = Duration::hours(1);
+    state.load = 150.;
+    // Only the 100 Wh above the reserve reach the load
+    let cut = step(&state);
+    assert_eq!(cut.current_stored_energy, 200.);
+    assert!((cut.unmet_history.last().unwrap() - 50.).abs() < 0.001);
+    assert_eq!(cut.load_cuts, vec![state.now]);
+    // A cut that carries on is the same event
+    let still_cut = step(&cut);
+    assert_eq!(still_cut.current_stored_energy, 200.);
+    assert_eq!(still_cut.load_cuts.len(), 1);
+    // An empty battery below the reserve can still charge
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.minimum_soc = 0.2;
+    state.load = -50.;
+    state.step_size = Duration::hours(1);
+    assert_eq!(step(&state).current_stored_energy, 50.);
+    state.banks = vec![BatteryBank::new("Lead", 1000.).with_minimum_soc(0.5)];
+    state.banks[0].stored = 600.;
+    state.bank_charge_history = vec![Vec::new()];
+    state.load = 200.;
+    assert_eq!(step(&state).current_stored_energy, 500.)
+}
+
+#[test]
+fn test_chemistry() {
+    let mut state = SimState::new().with_chemistry(Chemistry::Agm);
+    assert_eq!(state.minimum_soc, 0.5);
+    // Presets can be overridden
+    state.minimum_soc = 0.4;
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 800.;
+    state.step_size = Duration::hours(1);
+    state.load = 0.;
+    // Flooded and AGM banks lose a few percent a month sitting idle
+    let mut idle = state.clone();
+    for _ in 0..730 {
+        idle = step(&idle);
+    }
+    assert!((idle.current_stored_energy - 800. * 0.97).abs() < 1.);
+    // At -20 C only 60% of the 600 Wh above the reserve can be drawn
+    state.ambient_temperature = AmbientTemperature::Constant(-20.);
+    state.load = 50.;
+    let mut cold = state.clone();
+    for _ in 0..5 {
+        cold = step(&cold);
+    }
+    assert!((reserve_fraction(&state, 0.) - 0.64).abs() < 0.0001);
+    assert!((cold.current_stored_energy - 640.).abs() < 0.5)
+}
+
+#[test]
+fn test_peukert_effect() {
+    let mut state = SimState::new().with_chemistry(Chemistry::FloodedLeadAcid);
+    state.battery_capacity = 2000.;
+    state.current_stored_energy = 2000.;
+    state.discharge_efficiency = 1.;
+    state.self_discharge = 0.;
+    state.step_size = Duration::hours(1);
+    // A 100 W load is the 20-hour rate, so the whole upper half is usable
+    state.load = 100.;
+    let mut light = state.clone();
+    while light.unmet_history.iter().all(|u| *u == 0.) {
+        light = step(&light);
+    }
+    // Four times the rate leaves about 30% of that behind
+    state.load = 400.;
+    let mut heavy = state.clone();
+    while heavy.unmet_history.iter().all(|u| *u == 0.) {
+        heavy = step(&heavy);
+    }
+    let served = |s: &SimState| s.load * s.unmet_history.len() as f32 - s.unmet_history.iter().sum::<f32>();
+    assert!((served(&light) - 1000.).abs() < 0.1);
+    assert!((served(&heavy) - 707.).abs() < 1.);
+    // but it can still be drawn more slowly
+    assert!(heavy.current_stored_energy > 1250.)
+}
+
+#[test]
+fn test_capacity_fade() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.cycle_life = 100.;
+    state.step_size = Duration::hours(1);
+    // Fill and drain the battery 50 times
+    for _ in 0..50 {
+        state.load = -1000.;
+        state = step(&state);
+        state.load = 1000.;
+        state = step(&state);
+    }
+    // Each cycle is a little shallower as the capacity fades
+    assert!((state.cycles - 47.6).abs() < 0.2);
+    assert!((state_of_health(&state) - 0.905).abs() < 0.001);
+    assert_eq!(state.soh_history.len(), 100);
+    assert!(state.soh_history.windows(2).all(|w| w[1] <= w[0]));
+    state.load = -1000.;
+    assert!((step(&state).current_stored_energy - 905.).abs() < 1.);
+    assert_eq!(state_of_health(&SimState::new()), 1.)
+}
+
+#[test]
+fn test_cold_charge_cutoff() {
+    let mut state = SimState::new().with_chemistry(Chemistry::LiFePO4);
+    state.battery_capacity = 1000.;
+    state.step_size = Duration::hours(1);
+    state.load = -100.;
+    state.ambient_temperature = AmbientTemperature::Constant(-5.);
+    // The surplus is wasted in an unheated compartment
+    let cold = step(&state);
+    assert_eq!(cold.current_stored_energy, 0.);
+    assert_eq!(*cold.clipped_energy_history.last().unwrap(), 100.);
+    // but a heated one keeps charging
+    state.battery_temperature_rise = 10.;
+    assert!(step(&state).current_stored_energy > 90.);
+    // Lead-acid charges through the cold
+    let mut state = SimState::new().with_chemistry(Chemistry::Agm);
+    state.battery_capacity = 1000.;
+    state.step_size = Duration::hours(1);
+    state.load = -100.;
+    state.ambient_temperature = AmbientTemperature::Constant(-5.);
+    assert_eq!(step(&state).current_stored_energy, 90.)
+}
+
+#[test]
+fn test_charge_taper() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 800.;
+    state.taper_soc = 0.9;
+    state.absorption_hours = 1.;
+    state.step_size = Duration::hours(1);
+    state.load = -500.;
+    // Bulk to 90%, then the absorption phase closes 63% of the last 100 Wh
+    let absorbing = step(&state);
+    assert!((absorbing.current_stored_energy - 963.2).abs() < 0.1);
+    assert!((absorbing.clipped_energy_history.last().unwrap() - 336.8).abs() < 0.1);
+    let topping = step(&absorbing);
+    assert!((topping.current_stored_energy - 986.5).abs() < 0.1);
+    state.taper_soc = 1.;
+    assert_eq!(step(&state).current_stored_energy, 1000.)
+}
+
+#[test]
+fn test_currents() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 500.;
+    state.system_voltage = 24.;
+    state.step_size = Duration::hours(2);
+    state.load = 120.;
+    let next = step(&state);
+    assert_eq!(*next.load_current_history.last().unwrap(), 5.);
+    assert_eq!(*next.battery_current_history.last().unwrap(), -5.);
+    assert_eq!(*next.controller_current_history.last().unwrap(), 0.)
+}
+
+#[test]
+fn test_low_voltage_disconnect() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 250.;
+    state.minimum_soc = 0.2;
+    state.reconnect_soc = 0.5;
+    state.step_size = Duration::hours(1);
+    state.load = 100.;
+    let tripped = step(&state);
+    assert!(tripped.load_disconnected);
+    assert_eq!(tripped.current_stored_energy, 200.);
+    // The loads stay off even with charge above the reserve
+    let mut waiting = tripped.clone();
+    waiting.current_stored_energy = 300.;
+    let waiting = step(&waiting);
+    assert_eq!(waiting.current_stored_energy, 300.);
+    assert_eq!(*waiting.unmet_history.last().unwrap(), 100.);
+    assert_eq!(waiting.load_cuts.len(), 1);
+    assert!(waiting.load_disconnected);
+    // until the battery recharges to the reconnect level
+    let mut charging = waiting;
+    charging.load = -200.;
+    let charging = step(&charging);
+    assert_eq!(charging.current_stored_energy, 500.);
+    assert!(!charging.load_disconnected);
+    state.reconnect_soc = 0.;
+    assert!(!step(&state).load_disconnected)
+}
+
+#[test]
+fn test_battery_replacement() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.cycle_life = 100.;
+    state.replacement_soh = 0.95;
+    state.step_size = Duration::hours(1);
+    let start = state.now;
+    state.scheduled_replacements = vec![start + Duration::minutes(30)];
+    for _ in 0..100 {
+        state.load = -1000.;
+        state = step(&state);
+        state.load = 1000.;
+        state = step(&state);
+    }
+    // Once on the schedule, then after a little over 25 cycles each time
+    assert_eq!(state.replacements[0], start + Duration::hours(1));
+    assert_eq!(state.replacements.len(), 4);
+    assert!(state.soh_history.iter().all(|h| *h >= 0.95))
+}
+
+#[test]
+fn test_amp_hours() {
+    let state = SimState::new().with_battery_amp_hours(100., 12.8);
+    assert_eq!(state.battery_capacity, 1280.);
+    assert_eq!(state.system_voltage, 12.8);
+    let mut doubled = state.clone();
+    doubled.system_voltage = 25.6;
+    assert_eq!(doubled.battery_amp_hours(), 50.);
+    assert_eq!(state.battery_amp_hours(), 100.)
+}
+
+#[test]
+fn test_battery_thermal_run() {
+    let mut state = SimState::new().with_chemistry(Chemistry::LiFePO4);
+    state.battery_capacity = 1000.;
+    state.step_size = Duration::hours(1);
+    state.load = -20.;
+    state.ambient_temperature = AmbientTemperature::Constant(-10.);
+    state.battery_thermal = Some(BatteryThermal::new(24., 3., 0.01));
+    // An insulated battery still warm from the day keeps charging for about
+    // 17 hours of a -10 C night
+    state.battery_temperature = 10.;
+    let mut run = state.clone();
+    for _ in 0..30 {
+        run = step(&run);
+    }
+    assert_eq!(run.battery_temperature_history[0], 10.);
+    assert!(run.battery_temperature < 0.);
+    assert!(run.charge_history[17] > 300.);
+    // then stops once it has cooled below freezing
+    assert!(run.charge_history[29] <= run.charge_history[18])
+}
+
+#[test]
+fn test_soc_percent() {
+    let mut state = SimState::new();
+    state.battery_capacity = 400.;
+    state.current_stored_energy = 100.;
+    assert_eq!(state.soc_percent(), 25.);
+    state.step_size = Duration::hours(1);
+    state.load = -100.;
+    let next = step(&state);
+    assert_eq!(next.soc_history, vec![25.]);
+    assert_eq!(next.soc_percent(), 50.);
+    state.banks = vec![BatteryBank::new("A", 300.), BatteryBank::new("B", 500.)];
+    assert_eq!(state.soc_percent(), 12.5);
+    assert_eq!(SimState::new().soc_percent(), 0.)
+}
+
+#[test]
+fn test_standby_load() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 1000.;
+    state.load = 20.;
+    state.standby_load = 5.;
+    assert_eq!(total_load(&state), 25.);
+    let summary = crate::summary::Summary::new(&run_simulation(&state));
+    assert!((summary.standby_share - 0.2).abs() < 0.001);
+    assert!((summary.standby_per_year - 43800.).abs() < 1.)
+}
+
+#[test]
+fn test_clipped_energy() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 950.;
+    state.step_size = Duration::hours(1);
+    state.load = -200.;
+    // A full battery spills the rest of the surplus
+    let full = step(&state);
+    assert_eq!(full.current_stored_energy, 1000.);
+    assert_eq!(full.clipped_energy_history, vec![150.]);
+    // which a grid takes instead
+    state.grid = Some(Grid::new());
+    let exported = step(&state);
+    assert_eq!(exported.clipped_energy_history, vec![0.]);
+    assert_eq!(exported.grid_export_history, vec![150.])
+}
+
+#[test]
+fn test_dump_load() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 950.;
+    state.step_size = Duration::hours(1);
+    state.load = -200.;
+    state.dump_load = 100.;
+    // The element takes what it can of the spill, the rest is still clipped
+    let full = step(&state);
+    assert_eq!(full.dump_history, vec![100.]);
+    assert_eq!(full.clipped_energy_history, vec![50.]);
+    // and it's never fed from the battery
+    state.current_stored_energy = 0.;
+    assert_eq!(step(&state).dump_history, vec![0.])
+}
+
+#[test]
+fn test_initial_soc() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(1);
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 1000.;
+    state.load = 20.;
+    // An empty battery blacks out the first night
+    assert!(run_simulation(&state).unmet_history[0] > 0.);
+    state.initial_soc = 0.5;
+    let result = run_simulation(&state);
+    assert_eq!(result.unmet_history.iter().sum::<f32>(), 0.);
+    assert_eq!(result.charge_history[0], 500.);
+    assert_eq!(state.with_initial_charge(250.).initial_soc, 0.25)
+}
+
+#[test]
+fn test_load_shedding() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.step_size = Duration::hours(1);
+    state.loads = vec![
+        Load::constant("Fridge", 50.),
+        Load::constant("Lights", 50.).with_priority(1),
+        Load::constant("Entertainment", 100.).with_priority(2),
+    ];
+    state.shed_soc = vec![0.5, 0.8];
+    let mut state = start_run(&state);
+    state.current_stored_energy = 1000.;
+    for _ in 0..6 {
+        state = step(&state);
+    }
+    // Everything runs down to 80%, then only the fridge and lights to 50%,
+    // then the fridge alone
+    assert_eq!(state.charge_history, vec![1000., 800., 600., 500., 400., 350.]);
+    assert_eq!(state.shed_hours, vec![0., 2., 4.]);
+    assert_eq!(state.unmet_history.iter().sum::<f32>(), 0.)
+}
+
+#[test]
+fn test_deferrable_load() {
+    let mut state = SimState::new();
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 500.;
+    state.step_size = Duration::hours(1);
+    state.load = 20.;
+    state.loads = vec![Load::constant("Water Heater", 300.).deferrable(0.9)];
+    // Waits at night with the battery half full
+    assert_eq!(total_load(&state), 20.);
+    state.current_stored_energy = 950.;
+    assert_eq!(total_load(&state), 320.);
+    // and runs on a sunny afternoon with spare solar
+    state.current_stored_energy = 500.;
+    state.solar_nominal_output = 1000.;
+    state.now = state.now.date().and_hms_opt(12, 0, 0).unwrap();
+    assert!(charging_energy(&state) > 320.);
+    assert_eq!(total_load(&state), 320.);
+    assert_eq!(step(&state).deferred_history, vec![300.])
 }
 
 #[test]
@@ -105,43 +1431,527 @@ fn test_step_1() {
 
 #[test]
 fn test_step_2() {
+    // An hour from midnight, so the panel contributes nothing and the load
+    // draws 20 Wh
     let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
     state.battery_capacity = 100.;
     state.current_stored_energy = 50.;
     state.solar_nominal_output = 10.;
     state.load = 20.;
     let net = step(&state);
-    assert_eq!(net.current_stored_energy, 40.)
+    assert_eq!(net.current_stored_energy, 30.)
+}
+
+#[test]
+fn test_step_unmet() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 100.;
+    state.current_stored_energy = 5.;
+    state.load = 20.;
+    let net = step(&state);
+    assert_eq!(net.current_stored_energy, 0.);
+    assert_eq!(net.unmet_history, vec![15.])
+}
+
+#[test]
+fn test_step_banks() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.load = 10.;
+    state.battery_capacity = 600.;
+    let single = run_simulation(&state);
+    state.banks = vec![BatteryBank::new("New", 400.), BatteryBank::new("Old", 200.)];
+    state.dispatch = DispatchPolicy::FillSmallestFirst;
+    let banked = run_simulation(&state);
+    // Ideal banks only change where the energy sits
+    assert!((banked.current_stored_energy - single.current_stored_energy).abs() < 0.01);
+    assert_eq!(banked.bank_charge_history.len(), 2);
+    assert_eq!(banked.bank_charge_history[1].len(), banked.charge_history.len());
+    // The small bank fills before the large one takes anything
+    let first_new = banked.bank_charge_history[0].iter().position(|c| *c > 0.).unwrap();
+    assert_eq!(banked.bank_charge_history[1][first_new], 200.)
+}
+
+#[test]
+fn test_step_orbit() {
+    // Midnight on the ground, but a high beta angle orbit never enters eclipse
+    let mut state = SimState::new();
+    state.orbit = Some(Orbit::leo(500., 80.));
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 100.;
+    state.current_stored_energy = 50.;
+    state.solar_nominal_output = 30.;
+    state.load = 20.;
+    let net = step(&state);
+    assert!((net.current_stored_energy - 60.).abs() < 0.001);
+    assert_eq!(net.daylight_history, vec![24.])
+}
+
+#[test]
+fn test_degraded_orbit() {
+    let mut state = SimState::new();
+    state.orbit = Some(Orbit::leo(500., 80.));
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 30.;
+    state.now = simulation_start(&state) + Duration::days(365);
+    let fresh = charging_energy(&state);
+    state.panel_degradation = 0.1;
+    let degraded = charging_energy(&state);
+    assert!((degraded / fresh - 0.9).abs() < 0.01);
+    assert_eq!(degraded, solar_power(&state) * step_hours(&state))
 }
 
 pub fn net_energy(state: &SimState) -> f32 {
-    let actual_solar_energy = solar_power(state) * bounded_daylight_hours(
-        state.now, 
-        state.now + state.step_size, 
-        daylight_hours(state.latitude, state.now.ordinal0()));
-    let load_energy = state.load * state.step_size.num_minutes() as f32 / 60.;
-    actual_solar_energy - load_energy
+    charging_energy(state) - consumed_energy(state)
 }
 
+// Energy reaching the battery from the array, and any wind or hydro turbine
+// or alternator through its own controller, over the step, in Wh
+pub fn charging_energy(state: &SimState) -> f32 {
+    let hours = step_hours(state);
+    if hours <= 0. {
+        return 0.;
+    }
+    let others = (wind_power(state) + hydro_power(state) + alternator_power(state)) * hours;
+    if outage::is_down(&state.outage_events, Component::ChargeController, state.now) {
+        return others;
+    }
+    let array_watts = solar_energy(state) / hours;
+    let delivered = array_watts - state.wiring.charging_loss(array_watts, state.system_voltage);
+    let delivered = match state.charge_controller {
+        Some(controller) => controller.output(delivered),
+        None => delivered,
+    };
+    delivered * hours + others
+}
+
+pub fn alternator_power(state: &SimState) -> f32 {
+    state.alternator.as_ref().map_or(0., |a| a.power(state.now, state.step_size))
+}
+
+pub fn hydro_power(state: &SimState) -> f32 {
+    state.hydro.map_or(0., |hydro| hydro.power_at(state.now) * state.generation_factor)
+}
+
+// Watts from the wind turbine
+pub fn wind_power(state: &SimState) -> f32 {
+    match state.wind_turbine {
+        Some(turbine) => turbine.power(turbine.hub_speed(wind::speed_at(&state.wind_speeds, state.now)))
+            * state.generation_factor,
+        None => 0.,
+    }
+}
+
+// Energy drawn from the battery over the step, in Wh
+pub fn consumed_energy(state: &SimState) -> f32 {
+    let quiescent = match state.charge_controller {
+        Some(controller) => controller.quiescent_draw,
+        None => 0.,
+    };
+    let load = served_load(state) + inverter_draw(state);
+    let watts = load
+        + state.wiring.load_loss(load, state.system_voltage)
+        + quiescent
+        + state.bms_draw;
+    watts * step_hours(state)
+}
+
+// Watts the equipment itself draws day and night, apart from any load: the
+// charge controller, the BMS, and the inverter idling or in standby
+pub fn parasitic_draw(state: &SimState) -> f32 {
+    let controller = state.charge_controller.map_or(0., |c| c.quiescent_draw);
+    let inverter = state.inverter.map_or(0., |i| i.self_consumption(ac_load(state)));
+    controller + state.bms_draw + inverter
+}
+
+// Watts of load actually powered
+pub fn served_load(state: &SimState) -> f32 {
+    match state.inverter {
+        Some(inverter) => inverter.served_load(ac_load(state)) + dc_load(state),
+        None => total_load(state),
+    }
+}
+
+// Watts demanded by all loads
+pub fn total_load(state: &SimState) -> f32 {
+    let firm = firm_load(state);
+    firm + deferred_load(state, firm)
+}
+
+// Watts of the running loads wired to the DC bus
+pub fn dc_load(state: &SimState) -> f32 {
+    state.loads.iter().zip(load_shares(state, firm_load(state)))
+        .filter(|(l, _)| l.dc)
+        .map(|(l, share)| l.power(state) * share)
+        .sum::<f32>() * load_growth_factor(state)
+}
+
+// Watts the inverter has to supply
+pub fn ac_load(state: &SimState) -> f32 {
+    (total_load(state) - dc_load(state)).max(0.)
+}
+
+// Watts of every load that runs regardless of the battery and solar
+pub fn firm_load(state: &SimState) -> f32 {
+    let watts = state.load + state.standby_load + state.loads.iter()
+        .filter(|l| l.deferrable.is_none() && !is_shed(state, l))
+        .map(|l| l.power(state))
+        .sum::<f32>();
+    let noise = state.load_noise.map_or(1., |noise| noise.factor(state.now));
+    watts * load_growth_factor(state) * noise
+}
+
+// Indices of the deferrable loads that get to run, with the share of their
+// power each draws: in full above its state of charge, or when what the
+// array delivers covers it on top of the firm load and the deferrable loads
+// already running. A load that can throttle, like an EV charger, takes
+// whatever part of its power the surplus covers.
+pub fn running_deferrable(state: &SimState, firm: f32) -> Vec<(usize, f32)> {
+    let hours = step_hours(state);
+    let mut surplus = None;
+    let mut running = 0.;
+    let mut indices = Vec::new();
+    for (i, load) in state.loads.iter().enumerate().filter(|(_, l)| !is_shed(state, l)) {
+        let Some(above_soc) = load.deferrable else {
+            continue;
+        };
+        let watts = load.power(state) * load_growth_factor(state);
+        let spare = *surplus.get_or_insert_with(|| {
+            if hours > 0. { charging_energy(state) / hours - firm } else { 0. }
+        }) - running;
+        let share = if state.soc_percent() >= above_soc * 100. || spare >= watts {
+            1.
+        } else if load.throttles() && spare > 0. {
+            spare / watts
+        } else {
+            continue;
+        };
+        running += watts * share;
+        indices.push((i, share));
+    }
+    indices
+}
+
+// Share of each load's power it draws over the step, 0 for shed loads and
+// deferrable loads that wait
+pub fn load_shares(state: &SimState, firm: f32) -> Vec<f32> {
+    let mut shares: Vec<f32> = state.loads.iter()
+        .map(|l| if l.deferrable.is_none() && !is_shed(state, l) { 1. } else { 0. })
+        .collect();
+    for (i, share) in running_deferrable(state, firm) {
+        shares[i] = share;
+    }
+    shares
+}
+
+pub fn deferred_load(state: &SimState, firm: f32) -> f32 {
+    running_deferrable(state, firm).iter().map(|(i, share)| state.loads[*i].power(state) * share).sum::<f32>()
+        * load_growth_factor(state)
+}
+
+// Loads of a tier are turned off while the charge is below that tier's
+// threshold; lower priorities have higher thresholds and go first
+pub fn is_shed(state: &SimState, load: &Load) -> bool {
+    load.priority > 0 && state.shed_soc.get(load.priority - 1)
+        .is_some_and(|threshold| state.soc_percent() < threshold * 100.)
+}
+
+// Watts consumed by the inverter itself, idling and converting the load
+pub fn inverter_draw(state: &SimState) -> f32 {
+    let load = ac_load(state);
+    match state.inverter {
+        Some(inverter) => inverter.self_consumption(load) + inverter.conversion_loss(load),
+        None => 0.,
+    }
+}
+
+#[test]
+fn test_solar_pump_run() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 300.;
+    state.battery_capacity = 500.;
+    state.loads.push(Load::solar_pump("Pump", 150., 5.));
+    let result = run_simulation(&state);
+    assert_eq!(result.pumped_history[0], 0.);
+    let pumped: f32 = result.pumped_history.iter().sum();
+    let solar: f32 = result.solar_history.iter().sum();
+    assert!((pumped - solar * 0.5 * 5.).abs() < 1.)
+}
+
+#[test]
+fn test_unpowered_pump() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.battery_capacity = 10.;
+    state.load = 0.;
+    // A pump ten times the array's size runs short of power all day
+    state.loads.push(Load::solar_pump("Pump", 1000., 1.));
+    let result = run_simulation(&state);
+    let unmet: f32 = result.unmet_history.iter().sum();
+    assert!(unmet > 0.);
+    let pumped: f32 = result.pumped_history.iter().sum();
+    let solar: f32 = result.solar_history.iter().sum();
+    assert!(pumped <= solar + 10.)
+}
+
+#[test]
+fn test_inverter_standby_comparison() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.load = 3.;
+    state.inverter = Some(Inverter::new(8.));
+    assert!((net_energy(&state) + 11.).abs() < 0.001);
+    state.inverter = Some(Inverter::new(8.).with_standby(5., 0.5));
+    assert!((net_energy(&state) + 0.5).abs() < 0.001);
+    assert_eq!(served_load(&state), 0.)
+}
+
+#[test]
+fn test_sleeping_inverter_unmet() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.battery_capacity = 1000.;
+    state.initial_soc = 1.;
+    state.load = 3.;
+    state.inverter = Some(Inverter::new(8.).with_standby(5., 0.5));
+    let result = run_simulation(&state);
+    // The battery has plenty, but the load never wakes the inverter
+    assert!(result.unmet_history.iter().all(|u| (u - 3.).abs() < 0.001));
+    assert_eq!(result.load_cuts.len(), 1);
+    assert!(result.served_load_history.iter().all(|s| *s == 0.))
+}
+
+#[test]
+fn test_sleep_mode_winter_survival() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(3);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 500.;
+    state.initial_soc = 1.;
+    state.load = 0.;
+    let mut profile = [0.; 24];
+    profile[18] = 60.;
+    profile[19] = 60.;
+    state.loads = vec![Load::hourly("Lights", profile)];
+    // Idling all day, the inverter outdraws the lights and flattens the
+    // battery on the second night; asleep between uses it lasts the stretch
+    state.inverter = Some(Inverter::new(8.));
+    let always_on = summary::Summary::new(&run_simulation(&state));
+    state.inverter = Some(Inverter::new(8.).with_standby(5., 0.5));
+    let sleeping = summary::Summary::new(&run_simulation(&state));
+    assert!(always_on.unmet_energy > 0.);
+    assert_eq!(sleeping.unmet_energy, 0.);
+    assert!(sleeping.inverter_consumption < always_on.inverter_consumption / 4.)
+}
+
+#[test]
+fn test_generator() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 1000.;
+    state.load = 100.;
+    state.generator = Some(Generator::new(500., 0.3, 0.9, 1.));
+    let result = run_simulation(&state);
+    // Starting flat, it runs after the first hour and charges the battery
+    // full, throttling back near the top. Then the battery drains to its
+    // start level and the cycle repeats.
+    assert_eq!(result.generator_starts[0], simulation_start(&state) + Duration::hours(1));
+    assert_eq!(result.generator_history[1], 500.);
+    assert_eq!(result.generator_history[3], 300.);
+    assert!(result.generator_starts.len() >= 3);
+    assert!(result.charge_history.iter().skip(2).all(|c| *c >= 200.));
+    assert_eq!(result.unmet_history.iter().sum::<f32>(), 100.);
+    assert!((result.fuel_history[1] - 1.).abs() < 0.0001)
+}
 
-pub fn daylight_hours(lat: f32, day: u32) -> f32{
+#[test]
+fn test_wind_turbine() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 10000.;
+    state.wind_turbine = Some(WindTurbine::small(400.));
+    // Imported speeds are kept without a generator
+    let start = simulation_start(&state);
+    state.wind_speeds = vec![(start, 15.), (start + Duration::days(1), 15.), (start + Duration::days(2), 0.)];
+    let result = run_simulation(&state);
+    assert_eq!(result.wind_history[0], 400.);
+    assert!((result.charge_history[23] - 9200.).abs() < 1.);
+    state.wind = Some(WindGenerator::new(6., 1));
+    let result = run_simulation(&state);
+    assert_eq!(result.wind_speeds.len(), 25);
+    assert!(result.wind_history.iter().any(|w| *w > 0. && *w < 400.))
+}
+
+#[test]
+fn test_hydro_and_solar() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 1000.;
+    state.load = 100.;
+    // A small turbine alone can't carry the load, but it never stops
+    state.hydro = Some(MicroHydro::constant(60.));
+    let result = run_simulation(&state);
+    assert!(result.unmet_history.iter().all(|u| (*u - 40.).abs() < 0.001));
+    assert_eq!(result.hydro_history[12], 60.);
+    state.solar_nominal_output = 300.;
+    let combined = run_simulation(&state);
+    assert!(combined.unmet_history.iter().sum::<f32>() < result.unmet_history.iter().sum::<f32>())
+}
+
+#[test]
+fn test_alternator_charging() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(7);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 2000.;
+    let saturday = NaiveDate::from_ymd_opt(2023, 1, 7).unwrap().and_hms_opt(10, 0, 0).unwrap();
+    state.alternator = Some(Alternator::from_amps(40., 12.).with_weekly_drive(saturday, Duration::hours(2)));
+    let result = run_simulation(&state);
+    assert!((result.current_stored_energy - 960.).abs() < 0.001);
+    assert_eq!(result.alternator_history.iter().filter(|a| **a > 0.).count(), 2)
+}
+
+#[test]
+fn test_dispatch_strategies() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(7);
+    state.solar_nominal_output = 400.;
+    state.battery_capacity = 2000.;
+    state.load = 100.;
+    state.generator = Some(Generator::new(1000., 0.3, 0.9, 1.));
+    let cycle = run_simulation(&state);
+    state.dispatcher = Dispatcher::LoadFollowing;
+    let following = run_simulation(&state);
+    let fuel = |r: &SimState| r.fuel_history.iter().sum::<f32>();
+    let runtime = |r: &SimState| r.fuel_history.iter().filter(|f| **f > 0.).count();
+    // Both keep the lights on, but cycle charging runs fewer, fuller hours
+    // and gets more out of each liter
+    assert!(cycle.unmet_history.iter().skip(1).all(|u| *u == 0.));
+    assert!(following.unmet_history.iter().skip(1).all(|u| *u == 0.));
+    assert!(runtime(&cycle) < runtime(&following));
+    let per_liter = |r: &SimState| r.generator_history.iter().sum::<f32>() / fuel(r);
+    assert!(per_liter(&cycle) > per_liter(&following))
+}
 
-    let p = (0.39795*
-        (0.2163108 + 2.*
-            (0.9671396*
-                (0.00860*(day as f32)).tan()
-            ).atan()
-        ).cos()
-    ).asin();
+#[test]
+fn test_dc_loads() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.inverter = Some(Inverter::new(10.).with_rating(1000., 0.9));
+    state.loads = vec![Load::constant("Fridge", 50.)];
+    let through_inverter = -net_energy(&state);
+    // A DC fridge skips the conversion loss, and with nothing left on the AC
+    // side a sleeping inverter saves its idle draw as well
+    state.loads = vec![Load::constant("Fridge", 50.).dc()];
+    assert!(-net_energy(&state) < through_inverter);
+    assert_eq!(ac_load(&state), 0.);
+    state.inverter = Some(Inverter::new(10.).with_rating(1000., 0.9).with_standby(5., 0.5));
+    assert!((net_energy(&state) + 50.5).abs() < 0.001);
+    assert_eq!(served_load(&state), 50.)
+}
 
-//                           _                                         _
-//                          / sin(0.8333*pi/180) + sin(L*pi/180)*sin(P) \
-//    D = 24 - (24/pi)*acos{  -----------------------------------------  }
-//                          \_          cos(L*pi/180)*cos(P)           _/
-    let numerator = 0.8333_f32.to_radians().sin() + lat.to_radians().sin()*p.sin();
-    let denom = (lat*PI/180.).cos()*p.cos();
-    let d = (24./PI)*(numerator/denom).acos();
-    d
+#[test]
+fn test_controller_quiescent_draw() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(10);
+    state.load = 5.;
+    state.charge_controller = Some(ChargeController::new(100.));
+    assert!((net_energy(&state) + 60.).abs() < 0.001)
+}
+
+#[test]
+fn test_parasitic_draw() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(10);
+    state.load = 5.;
+    state.bms_draw = 2.;
+    state.charge_controller = Some(ChargeController::new(100.));
+    state.inverter = Some(Inverter::new(8.));
+    // 1 W controller, 2 W BMS, and 8 W inverter, apart from the 5 W load
+    assert!((parasitic_draw(&state) - 11.).abs() < 0.001);
+    assert!((net_energy(&state) + 160.).abs() < 0.001);
+    state.battery_capacity = 1000.;
+    state.current_stored_energy = 500.;
+    assert!((step(&state).parasitic_history[0] - 110.).abs() < 0.001)
+}
+
+pub fn step_hours(state: &SimState) -> f32 {
+    state.step_size.num_seconds() as f32 / (60.*60.)
+}
+
+// Energy produced at the array over the step, in Wh
+pub fn solar_energy(state: &SimState) -> f32 {
+    solar_power(state) * step_hours(state)
+}
+
+pub fn grid_available(state: &SimState) -> bool {
+    state.grid.is_some() && !outage::is_down(&state.outage_events, Component::Grid, state.now)
+}
+
+// Degrees C at the start of the step
+pub fn ambient_temperature(state: &SimState) -> f32 {
+    state.ambient_temperature.at(state.now)
+}
+
+// Degrees C of the battery compartment
+pub fn compartment_temperature(state: &SimState) -> f32 {
+    ambient_temperature(state) + state.battery_temperature_rise
+}
+
+pub fn battery_temperature(state: &SimState) -> f32 {
+    match state.battery_thermal {
+        Some(_) => state.battery_temperature,
+        None => compartment_temperature(state),
+    }
+}
+
+pub fn unmet_energy(state: &SimState) -> f32 {
+    state.unmet_history.iter().sum()
+}
+
+// Energy lost in the cable runs over the step, in Wh
+pub fn wiring_loss(state: &SimState) -> f32 {
+    let hours = step_hours(state);
+    if hours <= 0. {
+        return 0.;
+    }
+    let solar_watts = solar_energy(state) / hours;
+    (state.wiring.charging_loss(solar_watts, state.system_voltage)
+        + state.wiring.load_loss(served_load(state) + inverter_draw(state), state.system_voltage)) * hours
+}
+
+#[test]
+fn test_wiring_loss() {
+    // 10 A through 0.1 ohm for two hours
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(2);
+    state.load = 120.;
+    state.wiring.load = Some(wiring::CableRun::new(5., 0.01));
+    assert!((wiring_loss(&state) - 20.).abs() < 0.001);
+    assert!((net_energy(&state) + 260.).abs() < 0.001)
+}
+
+
+pub fn daylight_hours(lat: f32, day: u32) -> f32 {
+    Planet::earth().daylight_hours(lat, day)
 }
 
 #[test]
@@ -241,39 +2051,559 @@ pub fn earlier_of(a: NaiveDateTime, b: NaiveDateTime) -> NaiveDateTime {
     }
 }
 
-pub fn sunrise(date: NaiveDate, lat: f32) -> NaiveTime {
-    let light_hours = daylight_hours(lat, date.ordinal0());
-    NaiveTime::from_num_seconds_from_midnight_opt(43200 - ((light_hours / 2.)*60.*60.) as u32, 0).unwrap()
+// Clock time at which the sun crosses the meridian
+pub fn solar_noon(date: NaiveDate, lon: f32, utc_offset: f32) -> NaiveTime {
+    let noon = NaiveDateTime::new(date, NaiveTime::from_hms_opt(12, 0, 0).unwrap());
+    clock_time(12. - sun::solar_time_offset(noon, lon, utc_offset))
+}
+
+fn clock_time(hours: f32) -> NaiveTime {
+    let seconds = (hours * 3600.) as i64;
+    NaiveTime::from_num_seconds_from_midnight_opt(seconds.rem_euclid(86400) as u32, 0).unwrap()
+}
+
+// Hours from sunrise to solar noon, with the sun's upper limb on the horizon
+// through standard refraction. None when the sun doesn't rise or set.
+fn half_day(date: NaiveDate, lat: f32, lon: f32, utc_offset: f32) -> Option<f32> {
+    let noon = NaiveDateTime::new(date, solar_noon(date, lon, utc_offset));
+    let utc = noon - Duration::seconds((utc_offset * 3600.) as i64);
+    let d = sun::solar_position(utc, lat, lon).declination;
+    let phi = lat.to_radians();
+    let cos_w = ((-0.8333_f32).to_radians().sin() - phi.sin() * d.sin()) / (phi.cos() * d.cos());
+    (cos_w.abs() < 1.).then(|| cos_w.acos().to_degrees() / 15.)
+}
+
+// None when the sun doesn't rise or doesn't set that day
+pub fn sunrise(date: NaiveDate, lat: f32, lon: f32, utc_offset: f32) -> Option<NaiveTime> {
+    let noon = solar_noon(date, lon, utc_offset);
+    half_day(date, lat, lon, utc_offset).map(|h| noon - Duration::seconds((h * 3600.) as i64))
 }
 #[test]
 fn test_sunrise_1() {
     let date = NaiveDate::from_ymd_opt(2023, 3, 15).unwrap();
-    assert_eq!(sunrise(date, 45.).hour(), 6)
+    assert_eq!(sunrise(date, 45., 0., 0.).unwrap().hour(), 6)
 }
-pub fn sunset(date: NaiveDate, lat: f32) -> NaiveTime {
-    let light_hours = daylight_hours(lat, date.ordinal0());
-    NaiveTime::from_num_seconds_from_midnight_opt(43200 + ((light_hours / 2.)*60.*60.) as u32, 0).unwrap()
+pub fn sunset(date: NaiveDate, lat: f32, lon: f32, utc_offset: f32) -> Option<NaiveTime> {
+    let noon = solar_noon(date, lon, utc_offset);
+    half_day(date, lat, lon, utc_offset).map(|h| noon + Duration::seconds((h * 3600.) as i64))
 }
+
+#[test]
+fn test_polar_day_and_night() {
+    let june = NaiveDate::from_ymd_opt(2023, 6, 21).unwrap();
+    let december = NaiveDate::from_ymd_opt(2023, 12, 21).unwrap();
+    assert_eq!(daylight_hours(70., june.ordinal0()), 24.);
+    assert_eq!(daylight_hours(70., december.ordinal0()), 0.);
+    assert_eq!(sunrise(june, 70., 0., 0.), None);
+    assert_eq!(sunset(december, 70., 0., 0.), None);
+    assert!(sunrise(NaiveDate::from_ymd_opt(2023, 3, 21).unwrap(), 70., 0., 0.).is_some());
+
+    // A day's run is finite, with light around the clock in June and none in December
+    let mut state = SimState::new();
+    state.latitude = 70.;
+    state.solar_nominal_output = 100.;
+    state.step_size = Duration::hours(1);
+    state.start_date = june;
+    state.end_date = june.succ_opt().unwrap();
+    let summer = run_simulation(&state).solar_history;
+    assert!(summer.iter().all(|e| e.is_finite()));
+    assert!(summer.iter().filter(|e| **e > 0.).count() >= 20);
+    state.start_date = december;
+    state.end_date = december.succ_opt().unwrap();
+    let winter = run_simulation(&state).solar_history;
+    assert!(winter.iter().all(|e| *e == 0.))
+}
+
+#[test]
+fn test_solar_noon() {
+    // Early November the sun runs 16 minutes fast
+    let november = NaiveDate::from_ymd_opt(2023, 11, 3).unwrap();
+    let noon = solar_noon(november, 0., 0.);
+    assert_eq!((noon.hour(), noon.minute()), (11, 43));
+    // Far west in its time zone, noon comes late
+    let madrid = solar_noon(NaiveDate::from_ymd_opt(2023, 2, 14).unwrap(), -3.7, 1.);
+    assert_eq!(madrid.hour(), 13);
+    assert!(madrid.minute() > 25);
+    let rise = sunrise(november, 40., -3.7, 1.).unwrap();
+    let set = sunset(november, 40., -3.7, 1.).unwrap();
+    let middle = rise + (set - rise) / 2;
+    assert!((middle - solar_noon(november, -3.7, 1.)).num_seconds().abs() < 60);
+
+    // Production peaks at solar noon rather than 12:00
+    let mut state = SimState::new();
+    state.latitude = 40.;
+    state.longitude = -3.7;
+    state.utc_offset = 1.;
+    let peak = november.and_time(solar_noon(november, -3.7, 1.));
+    let clock_noon = november.and_hms_opt(12, 0, 0).unwrap();
+    assert!(production_coefficient(&state, peak) > production_coefficient(&state, clock_noon))
+}
+
 pub fn solar_power(state: &SimState) -> f32 {
     let start = state.now;
     let end = state.now + state.step_size;
-    
-    let start_coeff = solar_production_curve(start, state.latitude);
-    let end_coeff = solar_production_curve(end, state.latitude);
-    let avg_coeff = (start_coeff + end_coeff)/2.;
-    state.solar_nominal_output * avg_coeff
+    let online = outage::array_availability(&state.outage_events, state.array_strings, start)
+        * (1. - snow::snow_loss(&state.snow_events, start))
+        * state.generation_factor;
+    if let Some(series) = &state.power_series {
+        let logged = |time| series::interpolate_yearly(series, time).unwrap_or(0.).max(0.);
+        return online * (logged(start) + logged(end)) / 2.;
+    }
+    let nominal = state.solar_nominal_output * degradation_factor(state);
+    if let Some(orbit) = state.orbit {
+        return nominal * online * orbit.sunlit_fraction(start, end);
+    }
+
+    let avg_coeff = mean_coefficient(state, start, end);
+    // The panel sees the irradiance while the sun is up, not the step's mean
+    let daylight = state.planet.bounded_daylight_hours(solar_time(state, start), solar_time(state, end), state.latitude);
+    let irradiance = match state.irradiance {
+        None if daylight > 0. => avg_coeff * step_hours(state) / daylight,
+        _ => avg_coeff,
+    } * clearsky::STANDARD_IRRADIANCE;
+    let efficiency = match state.panel {
+        Some(panel) => panel.temperature_factor(ambient_temperature(state), irradiance)
+            * panel.low_light_efficiency(irradiance),
+        None => 1.,
+    };
+    let noise = match state.solar_noise {
+        Some(noise) => noise.factor(start),
+        None => 1.,
+    };
+    let derate = state.monthly_derate[start.month0() as usize];
+    nominal * online * avg_coeff * efficiency * noise * derate
+}
+
+// Remaining share of the array's output after degrading since the run began
+pub fn degradation_factor(state: &SimState) -> f32 {
+    let years = (state.now - simulation_start(state)).num_seconds() as f32 / (365.25 * 24. * 3600.);
+    (1. - state.panel_degradation).powf(years.max(0.))
+}
+
+// Multiple of the configured load after growing since the run began
+pub fn load_growth_factor(state: &SimState) -> f32 {
+    let years = (state.now - simulation_start(state)).num_seconds() as f32 / (365.25 * 24. * 3600.);
+    (1. + state.load_growth).powf(years.max(0.))
+}
+
+#[test]
+fn test_daily_solar_independent_of_step() {
+    let mut state = SimState::new();
+    state.latitude = 40.;
+    state.solar_nominal_output = 100.;
+    state.start_date = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+    state.end_date = state.start_date + Duration::days(1);
+    let daily = |state: &SimState, minutes: i64| {
+        let mut state = state.clone();
+        state.step_size = Duration::minutes(minutes);
+        let result = run_simulation(&state);
+        result.solar_history.iter().sum::<f32>() * step_hours(&result)
+    };
+    let oriented = SimState { orientation: Some(Orientation::new(30., 180.)), ..state.clone() };
+    let ineichen = SimState { solar_model: SolarModel::Ineichen { linke_turbidity: 3. }, ..state.clone() };
+    for state in [state, oriented, ineichen] {
+        let reference = daily(&state, 5);
+        for minutes in [45, 60, 180, 24 * 60] {
+            assert!((daily(&state, minutes) - reference).abs() < 0.01 * reference,
+                "{} minute steps: {} against {}", minutes, daily(&state, minutes), reference);
+        }
+    }
+}
+
+#[test]
+fn test_hot_panels() {
+    let mut state = SimState::new();
+    state.solar_nominal_output = 100.;
+    state.step_size = Duration::hours(1);
+    state.now = NaiveDate::from_ymd_opt(2023, 6, 21).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let rated = solar_power(&state);
+    state.panel = panel::preset("400 W Mono PERC");
+    let warm = solar_power(&state);
+    state.ambient_temperature = AmbientTemperature::Constant(40.);
+    let hot = solar_power(&state);
+    assert!(hot < warm && warm < 0.92 * rated);
+    // Weak morning light converts less efficiently than the noon sun
+    state.ambient_temperature = AmbientTemperature::Constant(0.);
+    state.now = state.now.date().and_hms_opt(6, 30, 0).unwrap();
+    let dim = solar_power(&state);
+    state.panel.as_mut().unwrap().low_light_loss = 0.;
+    assert!(dim > 0. && dim < 0.97 * solar_power(&state))
+}
+
+#[test]
+fn test_degradation_factor() {
+    let mut state = SimState::new();
+    state.panel_degradation = 0.005;
+    state.now = simulation_start(&state);
+    assert_eq!(degradation_factor(&state), 1.);
+    state.now += Duration::days(365 * 2 + 1);
+    assert!((degradation_factor(&state) - 0.995 * 0.995).abs() < 0.0001)
+}
+
+#[test]
+fn test_load_growth() {
+    let mut state = SimState::new();
+    state.load = 100.;
+    state.load_growth = 0.05;
+    state.now = simulation_start(&state);
+    assert_eq!(firm_load(&state), 100.);
+    // Five years of 5% growth is about 28% more
+    state.now += Duration::days(5 * 365 + 1);
+    assert!((firm_load(&state) - 127.63).abs() < 0.01)
+}
+
+#[test]
+fn test_winter_run() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(6);
+    state.start_date = NaiveDate::from_ymd_opt(2023, 11, 1).unwrap();
+    state.end_date = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+    assert_eq!(simulation_end(&state), NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+    let result = run_simulation(&state);
+    // November through February of a leap winter
+    assert_eq!(result.history_dates.len(), 4 * (30 + 31 + 31 + 29))
+}
+
+#[test]
+fn test_multi_year_run() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(12);
+    state.start_date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    state.end_date = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+    state.solar_nominal_output = 100.;
+    state.panel_degradation = 0.1;
+    let result = run_simulation(&state);
+    // 2024 is a leap year
+    assert_eq!(result.history_dates.len(), 2 * (366 + 365));
+    // The histories were sized for the whole run up front
+    assert_eq!(step_count(&state), result.history_dates.len());
+    assert_eq!(result.unmet_history.capacity(), result.unmet_history.len());
+    assert_eq!(result.history_dates[0], NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+    assert_eq!(*result.history_dates.last().unwrap(), NaiveDate::from_ymd_opt(2025, 12, 31).unwrap().and_hms_opt(12, 0, 0).unwrap());
+    // The same winter day a year on produces less from the aged panels
+    assert!(result.solar_history[2 * 366 + 1] < result.solar_history[1])
+}
+
+// Apparent solar time at the site for a clock time, which the sun's position
+// and the production curve are measured in. Other planets keep local time.
+pub fn solar_time(state: &SimState, now: NaiveDateTime) -> NaiveDateTime {
+    if state.planet != Planet::earth() {
+        return now;
+    }
+    let offset = sun::solar_time_offset(now, state.longitude, state.utc_offset);
+    now + Duration::seconds((offset * 3600.) as i64)
+}
+
+// Where the sun is in the sky at a clock time
+pub fn sun_position(state: &SimState, now: NaiveDateTime) -> SolarPosition {
+    let utc = now - Duration::seconds((state.utc_offset * 3600.) as i64);
+    sun::solar_position(utc, state.latitude, state.longitude)
+}
+
+// Production coefficient averaged over a step. Each stretch of daylight in
+// the step is integrated with three-point Gauss-Legendre over pieces of an
+// hour or less, so the clouds, air mass, and panel angle are followed across
+// long steps too.
+pub fn mean_coefficient(state: &SimState, start: NaiveDateTime, end: NaiveDateTime) -> f32 {
+    let hours = (end - start).num_seconds() as f32 / 3600.;
+    if hours <= 0. {
+        return 0.;
+    }
+    if state.irradiance.is_some() {
+        return (production_coefficient(state, start) + production_coefficient(state, end)) / 2.;
+    }
+    let gauss = [(-0.774_596_7, 5. / 9.), (0., 8. / 9.), (0.774_596_7, 5. / 9.)];
+    let mut total = 0.;
+    for (first, last, _, _) in state.planet.daylight_spans(solar_time(state, start), solar_time(state, end), state.latitude) {
+        let pieces = (last - first).ceil().max(1.);
+        let width = (last - first) / pieces;
+        for piece in 0..pieces as usize {
+            let middle = first + (piece as f32 + 0.5) * width;
+            for (node, weight) in gauss {
+                let at = start + Duration::seconds(((middle + node * width / 2.) * 3600.) as i64);
+                total += weight * width / 2. * production_coefficient(state, at);
+            }
+        }
+    }
+    total / hours
+}
+
+pub fn production_coefficient(state: &SimState, now: NaiveDateTime) -> f32 {
+    if let Some(series) = &state.irradiance {
+        return irradiance::coefficient_at(series, now);
+    }
+    let clearness = weather::clearness_at(&state.weather_days, now);
+    let position = sun_position(state, now);
+    let cos_zenith = position.cos_zenith();
+    let clear = match state.solar_model {
+        SolarModel::Ineichen { linke_turbidity } => clearsky::ineichen(
+            cos_zenith, now.ordinal(), state.planet.solar_constant, linke_turbidity, state.elevation,
+        ),
+        SolarModel::SimpleCurve => {
+            let mut normal = state.planet.production_curve(solar_time(state, now), state.latitude)
+                * state.planet.irradiance_factor()
+                * clearsky::STANDARD_IRRADIANCE;
+            if state.air_mass_attenuation {
+                normal *= clearsky::air_mass_factor(cos_zenith, state.elevation);
+            }
+            clearsky::Irradiance::beam(normal, cos_zenith)
+        }
+    };
+    let sky = clearsky::cloudy(clear, clearness, cos_zenith);
+    let front = clearsky::plane_of_array(position, state.orientation, state.tracking, sky, state.albedo);
+    let rear = clearsky::rear_irradiance(position, state.orientation, state.tracking, sky, state.albedo);
+    (front + state.bifaciality * rear) / clearsky::STANDARD_IRRADIANCE
 }
 
 #[test]
 fn test_solar_power_2() {
     let mut state = SimState::new();
-    state.now = NaiveDateTime::new(
+    let nine_am = NaiveDateTime::new(
         NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), 
         NaiveTime::from_hms_opt(9,0,0).unwrap());
+    // 9 am by the sun, about 12 minutes later on the clock
+    state.now = nine_am - (solar_time(&state, nine_am) - nine_am);
     state.step_size = Duration::seconds(1);
     state.solar_nominal_output = 1.;
+    state.air_mass_attenuation = false;
     let net = solar_power(&state);
-    assert!((net-0.33).abs() < 0.01)
+    assert!((net-0.51).abs() < 0.01);
+    // The sun is about 45 degrees up, so through about 1.4 air masses
+    state.air_mass_attenuation = true;
+    assert!((solar_power(&state) - 0.51 * 0.91).abs() < 0.01)
+}
+
+#[test]
+fn test_roof_versus_flat_panel() {
+    let mut state = SimState::new();
+    state.latitude = 40.;
+    state.end_date = state.start_date + Duration::days(9);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.orientation = Some(Orientation::new(30., 180.));
+    let roof: f32 = run_simulation(&state).solar_history.iter().sum();
+    state.orientation = Some(Orientation::new(0., 180.));
+    let flat: f32 = run_simulation(&state).solar_history.iter().sum();
+    // The low winter sun favours the tilted roof
+    assert!(roof > 1.3 * flat)
+}
+
+#[test]
+fn test_bifacial_gain() {
+    let mut state = SimState::new();
+    state.latitude = 40.;
+    state.start_date = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+    state.end_date = NaiveDate::from_ymd_opt(2023, 7, 1).unwrap();
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.orientation = Some(Orientation::equator_facing(30.));
+    let single: f32 = run_simulation(&state).solar_history.iter().sum();
+    state.bifaciality = 0.7;
+    let bifacial: f32 = run_simulation(&state).solar_history.iter().sum();
+    assert!(bifacial > 1.05 * single && bifacial < 1.15 * single);
+    // Bright snow lights the back more
+    state.albedo = clearsky::SNOW_ALBEDO;
+    let snowy: f32 = run_simulation(&state).solar_history.iter().sum();
+    assert!(snowy > bifacial * 1.2)
+}
+
+#[test]
+fn test_overcast_panel_tilt() {
+    let mut state = SimState::new();
+    state.latitude = 40.;
+    state.now = NaiveDate::from_ymd_opt(2023, 1, 5).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    state.weather_days = vec![weather::DailyWeather {
+        date: state.now.date(),
+        sky: weather::Sky::Overcast,
+        clearness: 0.25,
+        hourly: [0.25; 24],
+    }];
+    state.orientation = Some(Orientation::new(60., 180.));
+    let steep = production_coefficient(&state, state.now);
+    state.orientation = Some(Orientation::new(0., 180.));
+    let flat = production_coefficient(&state, state.now);
+    // Under cloud there is no beam to aim at, and a flat panel sees more sky
+    assert!(flat > steep && steep > 0.)
+}
+
+#[test]
+fn test_winter_tracking_gain() {
+    let mut state = SimState::new();
+    state.latitude = 45.;
+    state.end_date = state.start_date + Duration::days(9);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.orientation = Some(Orientation::equator_facing(45.));
+    let mut energy = Vec::new();
+    for tracking in [TrackingMode::Fixed, TrackingMode::SingleAxis, TrackingMode::DualAxis] {
+        state.tracking = tracking;
+        energy.push(run_simulation(&state).solar_history.iter().sum::<f32>());
+    }
+    assert!(energy[0] < energy[1] && energy[1] < energy[2])
+}
+
+#[test]
+fn test_clear_sky_models() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.end_date = state.start_date + Duration::days(2);
+    let simple: f32 = run_simulation(&state).solar_history.iter().sum();
+    state.solar_model = SolarModel::Ineichen { linke_turbidity: 3. };
+    let ineichen: f32 = run_simulation(&state).solar_history.iter().sum();
+    // A sun-facing panel sees nearly full irradiance for most of the day,
+    // well above the half cosine
+    assert!(ineichen > 1.3 * simple);
+    state.latitude = 55.;
+    let northern: f32 = run_simulation(&state).solar_history.iter().sum();
+    // The low winter sun passes through much more air
+    assert!(northern < 0.7 * ineichen);
+    // and less of it on a mountain
+    state.elevation = 3000.;
+    let mountain: f32 = run_simulation(&state).solar_history.iter().sum();
+    assert!(mountain > 1.05 * northern);
+    state.solar_model = SolarModel::SimpleCurve;
+    let simple_mountain: f32 = run_simulation(&state).solar_history.iter().sum();
+    state.elevation = 0.;
+    assert!(simple_mountain > 1.1 * run_simulation(&state).solar_history.iter().sum::<f32>())
+}
+
+#[test]
+fn test_irradiance_series() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.now = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    state.irradiance = Some(vec![
+        (state.now, 400.),
+        (state.now + Duration::hours(1), 600.),
+    ]);
+    assert!((solar_power(&state) - 50.).abs() < 0.001)
+}
+
+#[test]
+fn test_solar_noise_run() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(4);
+    state.step_size = Duration::minutes(15);
+    state.solar_nominal_output = 100.;
+    let steady = run_simulation(&state).solar_history;
+    state.solar_noise = Some(SolarNoise::new(0.3, 5));
+    let noisy = run_simulation(&state).solar_history;
+    assert_eq!(noisy, run_simulation(&state).solar_history);
+    assert_ne!(noisy, steady);
+    // Night stays dark
+    assert!(steady.iter().zip(&noisy).all(|(s, n)| *s > 0. || *n == 0.))
+}
+
+#[test]
+fn test_monthly_derate() {
+    let mut state = SimState::new();
+    state.start_date = NaiveDate::from_ymd_opt(2023, 5, 30).unwrap();
+    state.end_date = NaiveDate::from_ymd_opt(2023, 7, 9).unwrap();
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    let clear = run_simulation(&state);
+    state.monthly_derate[5] = 0.6;
+    let gloomy = run_simulation(&state);
+    for (i, date) in clear.history_dates.iter().enumerate() {
+        let expected = if date.month() == 6 { 0.6 } else { 1. } * clear.solar_history[i];
+        assert!((gloomy.solar_history[i] - expected).abs() < 0.001)
+    }
+}
+
+#[test]
+fn test_logged_series() {
+    let noon = NaiveDate::from_ymd_opt(2021, 5, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let logged = vec![(noon, 240.), (noon + Duration::hours(1), 180.)];
+    let mut state = SimState::new().with_power_series(logged.clone());
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 1000.;
+    // Moved onto the simulated year, and not scaled by the nominal output
+    state.now = noon.with_year(2023).unwrap();
+    assert!((solar_power(&state) - 210.).abs() < 0.001);
+    state.now -= Duration::days(1);
+    assert_eq!(solar_power(&state), 0.);
+
+    let mut state = SimState::new().with_irradiance_series(logged);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 1000.;
+    state.now = noon.with_year(2023).unwrap();
+    assert!((solar_power(&state) - 210.).abs() < 0.001)
+}
+
+#[test]
+fn test_weather_reduces_solar() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(29);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    let clear: f32 = run_simulation(&state).solar_history.iter().sum();
+    state.weather = Some(WeatherGenerator::temperate(1));
+    let cloudy = run_simulation(&state);
+    assert_eq!(cloudy.weather_days.len(), 30);
+    assert!(cloudy.solar_history.iter().sum::<f32>() < 0.8 * clear)
+}
+
+#[test]
+fn test_equipment_outages() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(7);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.battery_capacity = 10000.;
+    state.array_strings = 2;
+    let start = simulation_start(&state);
+    let healthy = run_simulation(&state);
+
+    let mut failed = start_run(&state);
+    failed.outage_events = vec![
+        Outage::new(Component::ArrayString, start, Duration::days(7)),
+        Outage::new(Component::ChargeController, start + Duration::days(7), Duration::days(1)),
+    ];
+    let end = simulation_end(&state);
+    while failed.now < end {
+        failed = step(&failed);
+    }
+    let week = 7 * 24;
+    let solar = |s: &SimState| s.solar_history[..week].iter().sum::<f32>();
+    assert!((solar(&failed) - 0.5 * solar(&healthy)).abs() < 0.01);
+    // Nothing charges once the controller is down
+    assert!(failed.charge_history[week..].iter().all(|c| *c == failed.current_stored_energy))
+}
+
+#[test]
+fn test_maintenance_window() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    let cleaning = simulation_start(&state) + Duration::days(1);
+    state.maintenance = vec![Outage::new(Component::Array, cleaning, Duration::days(1))];
+    let result = run_simulation(&state);
+    assert!(result.solar_history[24..48].iter().all(|p| *p == 0.));
+    assert!(result.solar_history[48..].iter().sum::<f32>() > 0.);
+    assert_eq!(summary::Summary::new(&result).array_downtime, 24.)
+}
+
+#[test]
+fn test_generator_service() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.end_date = state.start_date + Duration::days(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 1000.;
+    state.load = 100.;
+    state.generator = Some(Generator::new(500., 0.3, 0.9, 1.));
+    let service = simulation_start(&state);
+    state.maintenance = vec![Outage::new(Component::Generator, service, Duration::hours(6))];
+    let result = run_simulation(&state);
+    // Nothing runs, burns fuel, or starts until the service is over
+    assert!(result.generator_history[..6].iter().all(|g| *g == 0.));
+    assert!(result.fuel_history[..6].iter().all(|f| *f == 0.));
+    assert_eq!(result.generator_starts[0], service + Duration::hours(6));
+    assert_eq!(result.generator_history[6], 500.);
+    assert_eq!(result.unmet_history[..6].iter().sum::<f32>(), 600.)
 }
 
 pub fn time_hours(time:NaiveTime) -> f32 {
@@ -286,19 +2616,7 @@ fn test_time_hours() {
 }
 
 pub fn solar_production_curve(now: NaiveDateTime, lat: f32) -> f32 {
-    let light_hours = daylight_hours(lat, now.ordinal0());
-    let rise = sunrise(now.date(), lat);
-    let set = sunset(now.date(), lat);
-    let hour = time_hours(now.time());
-    
-    let coeff = if now.time() <= rise || now.time() >= set {
-        0.
-    } else {
-        let time_scaler = (2.*PI)/light_hours;
-        let cos_part = (time_scaler*(hour-12.)).cos();
-        0.5*cos_part+0.5
-    }; 
-    coeff
+    Planet::earth().production_curve(now, lat)
 }
 
 #[test] 
@@ -313,7 +2631,11 @@ fn test_solar_production_curve() {
         now += Duration::minutes(30);
         i += 1.;
     }
-    assert!(false)
+    // Dark at midnight, full output at noon, and symmetric about it
+    assert_eq!(hist[0], 0.);
+    assert!((hist[24] - 1.).abs() < 0.01);
+    assert!((hist[20] - hist[28]).abs() < 0.01);
+    assert!(hist.iter().all(|c| (0. ..=1.).contains(c)))
 }
 
 #[test]
@@ -339,18 +2661,14 @@ pub fn chart(
 
     //const PLOT_LINE_COLOR: RGBColor = RGBColor(0, 175, 255);
     
-    let from_date = *xs.first().clone().expect("No dates to display");
+    let from_date = *xs.first().expect("No dates to display");
     let to_date = *xs.last().expect("No dates to display");
 
-    let y_max: f32 = ys.iter().map(|y| 
-        y.clone().into_iter().reduce(f32::max))
-        .filter(|i| i.is_some())
-        .map(|i| i.unwrap()).reduce(f32::max).unwrap();
+    let y_max: f32 = ys.iter().filter_map(|y| 
+        y.clone().into_iter().reduce(f32::max)).reduce(f32::max).unwrap();
     
-    let y_secondary_max: f32 = ys_secondary.iter().map(|y| 
-        y.clone().into_iter().reduce(f32::max))
-        .filter(|i| i.is_some())
-        .map(|i| i.unwrap()).reduce(f32::max).unwrap();
+    let y_secondary_max: f32 = ys_secondary.iter().filter_map(|y| 
+        y.clone().into_iter().reduce(f32::max)).reduce(f32::max).unwrap_or(1.);
 
     let mut chart = if title.is_some(){
         builder
@@ -399,12 +2717,14 @@ pub fn chart(
         .draw()
         .expect("failed to draw chart mesh");
 
-    chart
-        .configure_secondary_axes()
-        .y_desc("Daylight Hours")
-        .draw().unwrap();
+    if !ys_secondary.is_empty() {
+        chart
+            .configure_secondary_axes()
+            .y_desc("Daylight Hours")
+            .draw().unwrap();
+    }
 
-    let colors = vec![
+    let colors = [
         &BLUE, 
         &RED, 
         &BLACK, 
@@ -415,11 +2735,11 @@ pub fn chart(
         &RGBColor(255, 150, 150), // pink
     ];
     let mut color_index = 0;
-    let n = vec![ys.len(), colors.len(), labels.len()].iter().min().unwrap_or(&1).clone() as usize;
+    let n = *[ys.len(), colors.len(), labels.len()].iter().min().unwrap_or(&1);
 
     for i in 0..n {
         let this_data: Vec<(NaiveDateTime,f32)> = xs.clone().into_iter()
-            .zip(ys[i.clone()].clone().into_iter()).collect();
+            .zip(ys[i].clone()).collect();
         let this_color = colors[color_index];
         let this_label = labels[i].clone();
         chart
@@ -433,16 +2753,16 @@ pub fn chart(
         )
         .expect("failed to draw chart data")
         .label(this_label)
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], this_color.clone()));
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], *this_color));
         color_index += 1;
     }
 
 
-    let n = vec![ys_secondary.len(), colors.len(), labels.len()].iter().min().unwrap_or(&1).clone() as usize;
+    let n = *[ys_secondary.len(), colors.len(), labels.len()].iter().min().unwrap_or(&1);
 
-    for i in 0..n {
+    for y in ys_secondary.iter().take(n) {
         let this_data: Vec<(NaiveDateTime,f32)> = xs.clone().into_iter()
-            .zip(ys_secondary[i.clone()].clone().into_iter()).collect();
+            .zip(y.clone()).collect();
         let this_color = colors[color_index];
         let this_label = labels[color_index].clone();
         chart
@@ -456,14 +2776,14 @@ pub fn chart(
         )
         .expect("failed to draw chart data")
         .label(this_label)
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], this_color.clone()));
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], *this_color));
         color_index += 1;
     }
 
     if show_legend {
         chart.configure_series_labels()
-        .background_style(&WHITE)
-        .border_style(&BLACK)
+        .background_style(WHITE)
+        .border_style(BLACK)
         .draw().expect("Failed to draw legend")    
     }
     root.present().expect("Unable to write result to file, please make sure 'plotters-doc-data' dir exists under current dir");
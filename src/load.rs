@@ -0,0 +1,514 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime, Timelike};
+use std::io;
+use std::path::Path;
+
+use crate::{SimState, ambient_temperature, series, solar_power};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadKind {
+    Constant(f32), // watts
+    // Direct-drive pump whose draw follows the available solar, as a
+    // fraction of the array's nominal output
+    SolarPump { rated_power: f32, liters_per_wh: f32 },
+    // Watts for each hour of the day on the clock, midnight first
+    Hourly([f32; 24]),
+    // Measured watts, sorted by time, e.g. from a smart meter. Runs outside
+    // the data replay the same dates from its first year.
+    Series(Vec<(NaiveDateTime, f32)>),
+    // Compressor cycling between on and off, e.g. a fridge
+    DutyCycle { on_power: f32, off_power: f32, on: Duration, off: Duration },
+    // Heater or fan whose runtime grows with the ambient temperature's
+    // distance past the setpoint, running flat out once it's full_load_difference C past
+    Thermostat { rated_power: f32, setpoint: f32, full_load_difference: f32, mode: ThermostatMode },
+    // Watts for a duration from start, repeating at an interval if any,
+    // e.g. a kettle every morning or a weekly power-tool session
+    Event { watts: f32, start: NaiveDateTime, duration: Duration, every: Option<Duration> },
+    // Electric vehicle plugged in from arrive until depart each day, coming
+    // home at arrival_soc and charging at charge_power until full
+    EvCharger {
+        capacity: f32, // Wh
+        charge_power: f32, // watts
+        arrival_soc: f32, // fraction
+        arrive: NaiveTime,
+        depart: NaiveTime,
+        charged: f32, // Wh added since it was plugged in
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThermostatMode {
+    Heating, // runs below the setpoint
+    Cooling, // runs above the setpoint
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Load {
+    pub name: String,
+    pub kind: LoadKind,
+    pub priority: usize, // tier for load shedding, 0 for loads that are never shed
+    pub deferrable: Option<f32>, // runs only above this state of charge or on surplus solar, always when None
+    pub dc: bool, // runs straight off the battery rather than through the inverter
+}
+
+impl Load {
+    pub fn new(name: &str, kind: LoadKind) -> Load {
+        Load {
+            name: name.to_string(),
+            kind,
+            priority: 0,
+            deferrable: None,
+            dc: false,
+        }
+    }
+
+    // Opportunistic loads like water heating that wait for a charged
+    // battery or spare solar
+    pub fn deferrable(mut self, above_soc: f32) -> Load {
+        self.deferrable = Some(above_soc);
+        self
+    }
+
+    // Runs only on solar the array delivers beyond the other loads, at part
+    // power if it can throttle
+    pub fn surplus_only(self) -> Load {
+        self.deferrable(f32::INFINITY)
+    }
+
+    // Wired to the DC bus, e.g. a 12 V fridge, skipping the inverter's losses
+    pub fn dc(mut self) -> Load {
+        self.dc = true;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: usize) -> Load {
+        self.priority = priority;
+        self
+    }
+
+    pub fn constant(name: &str, watts: f32) -> Load {
+        Load::new(name, LoadKind::Constant(watts))
+    }
+
+    pub fn solar_pump(name: &str, rated_power: f32, liters_per_wh: f32) -> Load {
+        Load::new(name, LoadKind::SolarPump { rated_power, liters_per_wh })
+    }
+
+    // One-off task drawing watts from start until end
+    pub fn window(name: &str, watts: f32, start: NaiveDateTime, end: NaiveDateTime) -> Load {
+        Load::new(name, LoadKind::Event { watts, start, duration: end - start, every: None })
+    }
+
+    pub fn hourly(name: &str, profile: [f32; 24]) -> Load {
+        Load::new(name, LoadKind::Hourly(profile))
+    }
+
+    // A cycle of the period spending the duty cycle's fraction of it on
+    pub fn duty_cycle(name: &str, on_power: f32, off_power: f32, duty: f32, period: Duration) -> Load {
+        let on = Duration::seconds((period.num_seconds() as f32 * duty.clamp(0., 1.)) as i64);
+        Load::new(name, LoadKind::DutyCycle { on_power, off_power, on, off: period - on })
+    }
+
+    pub fn thermostat(name: &str, rated_power: f32, setpoint: f32, full_load_difference: f32, mode: ThermostatMode) -> Load {
+        Load::new(name, LoadKind::Thermostat { rated_power, setpoint, full_load_difference, mode })
+    }
+
+    pub fn event(name: &str, watts: f32, start: NaiveDateTime, duration: Duration) -> Load {
+        Load::new(name, LoadKind::Event { watts, start, duration, every: None })
+    }
+
+    pub fn recurring(name: &str, watts: f32, first: NaiveDateTime, duration: Duration, every: Duration) -> Load {
+        Load::new(name, LoadKind::Event { watts, start: first, duration, every: Some(every) })
+    }
+
+    pub fn ev_charger(name: &str, capacity: f32, charge_power: f32, arrival_soc: f32, arrive: NaiveTime, depart: NaiveTime) -> Load {
+        Load::new(name, LoadKind::EvCharger { capacity, charge_power, arrival_soc, arrive, depart, charged: 0. })
+    }
+
+    pub fn series(name: &str, mut series: Vec<(NaiveDateTime, f32)>) -> Load {
+        series.sort_by_key(|(time, _)| *time);
+        Load::new(name, LoadKind::Series(series))
+    }
+
+    // Reads `datetime,watts` rows as with measured data
+    pub fn from_csv<P: AsRef<Path>>(name: &str, path: P) -> io::Result<Load> {
+        series::read_csv(path).map(|series| Load::series(name, series))
+    }
+
+    // Average watts over the current step
+    pub fn power(&self, state: &SimState) -> f32 {
+        match self.kind {
+            LoadKind::Series(ref series) => series_average(series, state.now, state.step_size),
+            LoadKind::Constant(watts) => watts,
+            LoadKind::SolarPump { rated_power, .. } => {
+                if state.solar_nominal_output <= 0. {
+                    0.
+                } else {
+                    rated_power * (solar_power(state) / state.solar_nominal_output).min(1.)
+                }
+            },
+            LoadKind::Hourly(profile) => hourly_average(&profile, state.now, state.step_size),
+            LoadKind::DutyCycle { on_power, off_power, on, off } => {
+                let fraction = on_fraction(state.now, state.step_size, on, off);
+                on_power * fraction + off_power * (1. - fraction)
+            },
+            LoadKind::Thermostat { rated_power, setpoint, full_load_difference, mode } => {
+                let past = match mode {
+                    ThermostatMode::Heating => setpoint - ambient_temperature(state),
+                    ThermostatMode::Cooling => ambient_temperature(state) - setpoint,
+                };
+                if full_load_difference <= 0. {
+                    if past > 0. { rated_power } else { 0. }
+                } else {
+                    rated_power * (past / full_load_difference).clamp(0., 1.)
+                }
+            },
+            LoadKind::Event { watts, start, duration, every } => {
+                watts * event_fraction(state.now, state.step_size, start, duration, every)
+            },
+            LoadKind::EvCharger { capacity, charge_power, arrival_soc, arrive, depart, charged } => {
+                if !plugged_in(state.now, arrive, depart) {
+                    return 0.;
+                }
+                let needed = (capacity * (1. - arrival_soc) - charged).max(0.);
+                let hours = crate::step_hours(state);
+                if hours > 0. {
+                    charge_power.min(needed / hours)
+                } else if needed > 0. {
+                    charge_power
+                } else {
+                    0.
+                }
+            },
+        }
+    }
+
+    // State of charge of an electric vehicle's battery, None for other loads
+    pub fn ev_soc(&self) -> Option<f32> {
+        match self.kind {
+            LoadKind::EvCharger { capacity, arrival_soc, charged, .. } if capacity > 0. => {
+                Some(arrival_soc + charged / capacity)
+            },
+            _ => None,
+        }
+    }
+
+    // Carries a load's own state over the step, given the share of its power
+    // delivered. An electric vehicle banks what it was charged while it stays
+    // plugged in and starts over on its next arrival.
+    pub fn advance(&mut self, state: &SimState, served: f32) {
+        let watts = self.power(state) * served.clamp(0., 1.);
+        if let LoadKind::EvCharger { arrive, depart, ref mut charged, .. } = self.kind {
+            let next = state.now + state.step_size;
+            *charged = if plugged_in(state.now, arrive, depart) && plugged_in(next, arrive, depart) {
+                *charged + watts * crate::step_hours(state)
+            } else {
+                0.
+            };
+        }
+    }
+
+    // Draws anything from nothing up to its power, so a deferrable one can
+    // run on part of what it needs
+    pub fn throttles(&self) -> bool {
+        matches!(self.kind, LoadKind::EvCharger { .. })
+    }
+
+    // Liters moved over the current step, zero for anything but a pump
+    pub fn liters_pumped(&self, state: &SimState) -> f32 {
+        match self.kind {
+            LoadKind::SolarPump { liters_per_wh, .. } => {
+                self.power(state) * crate::step_hours(state) * liters_per_wh
+            },
+            _ => 0.,
+        }
+    }
+
+    pub fn scaled(&self, factor: f32) -> Load {
+        let kind = match self.kind {
+            LoadKind::Series(ref series) => {
+                LoadKind::Series(series.iter().map(|(time, watts)| (*time, watts * factor)).collect())
+            },
+            LoadKind::Constant(watts) => LoadKind::Constant(watts * factor),
+            LoadKind::SolarPump { rated_power, liters_per_wh } => {
+                LoadKind::SolarPump { rated_power: rated_power * factor, liters_per_wh }
+            },
+            LoadKind::Hourly(profile) => LoadKind::Hourly(profile.map(|w| w * factor)),
+            LoadKind::DutyCycle { on_power, off_power, on, off } => {
+                LoadKind::DutyCycle { on_power: on_power * factor, off_power: off_power * factor, on, off }
+            },
+            LoadKind::Thermostat { rated_power, setpoint, full_load_difference, mode } => {
+                LoadKind::Thermostat { rated_power: rated_power * factor, setpoint, full_load_difference, mode }
+            },
+            LoadKind::Event { watts, start, duration, every } => {
+                LoadKind::Event { watts: watts * factor, start, duration, every }
+            },
+            LoadKind::EvCharger { capacity, charge_power, arrival_soc, arrive, depart, charged } => {
+                LoadKind::EvCharger {
+                    capacity: capacity * factor,
+                    charge_power: charge_power * factor,
+                    arrival_soc,
+                    arrive,
+                    depart,
+                    charged: charged * factor,
+                }
+            },
+        };
+        Load { kind, ..self.clone() }
+    }
+}
+
+// Average of the profile from now over the step, weighting each clock hour
+// by how much of the step falls in it
+fn hourly_average(profile: &[f32; 24], now: NaiveDateTime, step: Duration) -> f32 {
+    if step <= Duration::zero() {
+        return profile[now.hour() as usize];
+    }
+    let end = now + step;
+    let mut time = now;
+    let mut energy = 0.;
+    while time < end {
+        let next_hour = time.date().and_hms_opt(time.hour(), 0, 0).unwrap() + Duration::hours(1);
+        let until = next_hour.min(end);
+        energy += profile[time.hour() as usize] * (until - time).num_seconds() as f32;
+        time = until;
+    }
+    energy / step.num_seconds() as f32
+}
+
+// Share of the step spent in the on part of the cycle. Cycles start on,
+// counted from the Unix epoch so they don't depend on the run's start.
+fn on_fraction(now: NaiveDateTime, step: Duration, on: Duration, off: Duration) -> f32 {
+    let (on, period) = (on.num_seconds(), (on + off).num_seconds());
+    if period <= 0 {
+        return 0.;
+    }
+    // Seconds on from the start of a cycle until some point in it
+    let on_until = |t: i64| t.div_euclid(period) * on + t.rem_euclid(period).min(on);
+    let (start, end) = (now.timestamp(), (now + step).timestamp());
+    if end <= start {
+        return if start.rem_euclid(period) < on { 1. } else { 0. };
+    }
+    (on_until(end) - on_until(start)) as f32 / (end - start) as f32
+}
+
+// Share of the step an event, or any of its repeats, is running. Steps
+// longer than the event see its energy spread over the step.
+pub(crate) fn event_fraction(now: NaiveDateTime, step: Duration, start: NaiveDateTime, duration: Duration, every: Option<Duration>) -> f32 {
+    let on = duration.num_seconds().max(0);
+    // Seconds running from the first start until some point
+    let on_until = |t: i64| {
+        let t = t - start.timestamp();
+        if t <= 0 {
+            return 0;
+        }
+        match every.map(|e| e.num_seconds()) {
+            Some(period) if period > 0 => t.div_euclid(period) * on.min(period) + t.rem_euclid(period).min(on),
+            _ => t.min(on),
+        }
+    };
+    let (from, to) = (now.timestamp(), (now + step).timestamp());
+    if to <= from {
+        return (on_until(from + 1) - on_until(from)) as f32;
+    }
+    (on_until(to) - on_until(from)) as f32 / (to - from) as f32
+}
+
+// Overnight when departing is earlier in the day than arriving
+fn plugged_in(now: NaiveDateTime, arrive: NaiveTime, depart: NaiveTime) -> bool {
+    let time = now.time();
+    if arrive <= depart {
+        arrive <= time && time < depart
+    } else {
+        time >= arrive || time < depart
+    }
+}
+
+// Resamples measured data to the step: the mean of the samples within it,
+// or the value interpolated at its middle when it falls between samples
+fn series_average(series: &[(NaiveDateTime, f32)], now: NaiveDateTime, step: Duration) -> f32 {
+    let start = series::replay_time(series, now);
+    let end = start + step;
+    let from = series.partition_point(|(time, _)| *time < start);
+    let to = series.partition_point(|(time, _)| *time < end);
+    if to <= from {
+        series::interpolate(series, start + step / 2).unwrap_or(0.)
+    } else {
+        series[from..to].iter().map(|(_, watts)| watts).sum::<f32>() / (to - from) as f32
+    }
+}
+
+// Liters lifted per Wh: one Wh is 3600 J, lifting one liter takes g * head J
+pub fn pump_liters_per_wh(head: f32, efficiency: f32) -> f32 {
+    if head <= 0. {
+        0.
+    } else {
+        3600. * efficiency / (9.81 * head)
+    }
+}
+
+#[test]
+fn test_pump_liters_per_wh() {
+    assert!((pump_liters_per_wh(10., 1.) - 36.7).abs() < 0.1);
+    assert!((pump_liters_per_wh(20., 0.5) - 9.17).abs() < 0.01)
+}
+
+#[test]
+fn test_solar_pump_tracks_output() {
+    let mut state = SimState::new();
+    state.now = chrono::NaiveDate::from_ymd_opt(2023, 3, 21).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    state.step_size = chrono::Duration::hours(1);
+    state.solar_nominal_output = 400.;
+    let pump = Load::solar_pump("Pump", 200., 2.);
+    let fraction = solar_power(&state) / 400.;
+    assert!((pump.power(&state) - 200. * fraction).abs() < 0.001);
+    assert!((pump.liters_pumped(&state) - 400. * fraction).abs() < 0.01);
+
+    state.now = state.now.date().and_hms_opt(0, 0, 0).unwrap();
+    assert_eq!(pump.power(&state), 0.)
+}
+
+#[test]
+fn test_hourly_profile() {
+    let mut profile = [10.; 24];
+    profile[19] = 100.;
+    profile[20] = 60.;
+    let lights = Load::hourly("Lights", profile);
+    let mut state = SimState::new();
+    state.now = chrono::NaiveDate::from_ymd_opt(2023, 3, 21).unwrap().and_hms_opt(19, 0, 0).unwrap();
+    state.step_size = Duration::minutes(15);
+    assert_eq!(lights.power(&state), 100.);
+    // Longer steps average the hours they cover
+    state.step_size = Duration::hours(2);
+    assert_eq!(lights.power(&state), 80.);
+    state.now += Duration::minutes(30);
+    assert_eq!(lights.power(&state), (50. + 60. + 5.) / 2.);
+    assert_eq!(lights.scaled(2.).power(&state), 115.)
+}
+
+#[test]
+fn test_series_resampling() {
+    let start = chrono::NaiveDate::from_ymd_opt(2022, 6, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let meter = Load::series("Meter", (0..96).map(|i| (start + Duration::minutes(15 * i), if i % 4 == 0 { 400. } else { 0. })).collect());
+    let mut state = SimState::new();
+    state.now = start;
+    state.step_size = Duration::hours(1);
+    assert_eq!(meter.power(&state), 100.);
+    // Shorter steps than the data interpolate between readings
+    state.now = start + Duration::minutes(5);
+    state.step_size = Duration::minutes(5);
+    assert!((meter.power(&state) - 200.).abs() < 0.01);
+    // The simulated year replays the meter's dates
+    state.now = chrono::NaiveDate::from_ymd_opt(2023, 6, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    state.step_size = Duration::hours(1);
+    assert_eq!(meter.power(&state), 100.);
+    state.now += Duration::days(30);
+    assert_eq!(meter.power(&state), 0.)
+}
+
+#[test]
+fn test_duty_cycle() {
+    // A fridge running 40% of each 30 minute cycle
+    let fridge = Load::duty_cycle("Fridge", 60., 2., 0.4, Duration::minutes(30));
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    assert!((fridge.power(&state) - (0.4 * 60. + 0.6 * 2.)).abs() < 0.001);
+    // Short steps see the compressor on or off
+    state.step_size = Duration::minutes(6);
+    assert_eq!(fridge.power(&state), 60.);
+    state.now += Duration::minutes(12);
+    assert_eq!(fridge.power(&state), 2.);
+    state.now -= Duration::minutes(3);
+    assert_eq!(fridge.power(&state), 31.)
+}
+
+#[test]
+fn test_thermostat() {
+    let heater = Load::thermostat("Heater", 500., 5., 20., ThermostatMode::Heating);
+    let fan = Load::thermostat("Fan", 30., 28., 0., ThermostatMode::Cooling);
+    let mut state = SimState::new();
+    state.ambient_temperature = crate::temperature::AmbientTemperature::Constant(-5.);
+    assert_eq!(heater.power(&state), 250.);
+    assert_eq!(fan.power(&state), 0.);
+    state.ambient_temperature = crate::temperature::AmbientTemperature::Constant(-30.);
+    assert_eq!(heater.power(&state), 500.);
+    state.ambient_temperature = crate::temperature::AmbientTemperature::Constant(30.);
+    assert_eq!(heater.power(&state), 0.);
+    assert_eq!(fan.power(&state), 30.)
+}
+
+#[test]
+fn test_load_events() {
+    let morning = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(7, 0, 0).unwrap();
+    let kettle = Load::recurring("Kettle", 1500., morning, Duration::minutes(10), Duration::days(1));
+    let tools = Load::event("Power Tools", 800., morning + Duration::hours(3), Duration::hours(3));
+    let mut state = SimState::new();
+    state.now = morning + Duration::days(5);
+    state.step_size = Duration::minutes(10);
+    assert_eq!(kettle.power(&state), 1500.);
+    // An hour step averages the 10 minutes of boiling
+    state.step_size = Duration::hours(1);
+    assert_eq!(kettle.power(&state), 250.);
+    state.now += Duration::hours(1);
+    assert_eq!(kettle.power(&state), 0.);
+    // Before the first morning
+    state.now = morning - Duration::days(1);
+    assert_eq!(kettle.power(&state), 0.);
+    state.now = morning + Duration::hours(4);
+    assert_eq!(tools.power(&state), 800.);
+    state.now += Duration::days(7);
+    assert_eq!(tools.power(&state), 0.)
+}
+
+#[test]
+fn test_ev_charger() {
+    let arrive = NaiveTime::from_hms_opt(18, 0, 0).unwrap();
+    let depart = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+    // 60 kWh coming home at 40% needs 36 kWh, five hours at 7.2 kW
+    let mut ev = Load::ev_charger("EV", 60000., 7200., 0.4, arrive, depart);
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.now = state.now.date().and_hms_opt(17, 0, 0).unwrap();
+    let mut energy = 0.;
+    for _ in 0..24 {
+        if state.now.hour() == 6 {
+            assert_eq!(ev.ev_soc(), Some(1.));
+        }
+        energy += ev.power(&state);
+        ev.advance(&state, 1.);
+        state.now += state.step_size;
+    }
+    assert!((energy - 36000.).abs() < 1.);
+    // Unplugged at 17:00 again, ready for the next arrival
+    assert_eq!(ev.ev_soc(), Some(0.4))
+}
+
+#[test]
+fn test_ev_on_partial_surplus() {
+    let mut state = SimState::new();
+    state.start_date = chrono::NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+    state.end_date = state.start_date + Duration::days(1);
+    state.step_size = Duration::hours(1);
+    state.latitude = 40.;
+    state.solar_nominal_output = 2000.;
+    state.battery_capacity = 1000.;
+    state.load = 0.;
+    // Parked at home through the middle of the day
+    let arrive = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+    let depart = NaiveTime::from_hms_opt(17, 0, 0).unwrap();
+    state.loads.push(Load::ev_charger("EV", 60000., 7200., 0.4, arrive, depart).surplus_only());
+    let result = crate::run_simulation(&state);
+    // The array never covers the full 7.2 kW, so the car takes what there is
+    let charged: Vec<f32> = result.deferred_history.iter().cloned().filter(|e| *e > 0.).collect();
+    assert!(charged.len() >= 6);
+    assert!(charged.iter().all(|e| *e < 2000.));
+    assert!(result.unmet_history.iter().all(|u| *u == 0.));
+    // and banks only that, without draining the battery
+    let solar: f32 = result.solar_history[9..17].iter().sum();
+    assert!(charged.iter().sum::<f32>() <= solar);
+    assert!(result.charge_history[10..17].windows(2).all(|w| w[1] >= w[0] - 0.01));
+    // A car served a quarter of its draw banks a quarter
+    let mut ev = Load::ev_charger("EV", 60000., 7200., 0.4, arrive, depart);
+    state.now = state.start_date.and_hms_opt(10, 0, 0).unwrap();
+    ev.advance(&state, 0.25);
+    assert!((ev.ev_soc().unwrap() - 0.43).abs() < 0.0001)
+}
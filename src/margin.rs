@@ -0,0 +1,156 @@
+use chrono::Duration;
+
+use crate::{SimState, run_simulation};
+use crate::trace;
+
+const BISECTION_STEPS: usize = 16;
+const MAX_LOAD_MULTIPLIER: f32 = 100.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Margin {
+    pub load_multiplier: Option<f32>, // load scale where the first blackout appears, None if beyond 100x
+    pub solar_factor: Option<f32>, // fraction of the solar, wind, and hydro output where the first blackout appears, None if never
+}
+
+impl Margin {
+    // Percent extra load the design tolerates, negative when it already fails
+    pub fn load_margin_percent(&self) -> Option<f32> {
+        self.load_multiplier.map(|m| 100. * (m - 1.))
+    }
+
+    // Percent solar reduction the design tolerates
+    pub fn solar_margin_percent(&self) -> Option<f32> {
+        self.solar_factor.map(|f| 100. * (1. - f))
+    }
+}
+
+pub fn design_margin(state: &SimState) -> Margin {
+    Margin {
+        load_multiplier: load_multiplier_at_failure(state),
+        solar_factor: solar_factor_at_failure(state),
+    }
+}
+
+// A run starting with an empty battery has its first day ignored
+pub fn has_blackout(result: &SimState) -> bool {
+    let warm_up = match result.history_dates.first() {
+        Some(start) if result.initial_soc <= 0. => *start + Duration::days(1),
+        Some(start) => *start,
+        None => return false,
+    };
+    result.history_dates.iter().zip(&result.unmet_history)
+        .any(|(date, unmet)| *date >= warm_up && *unmet > 0.001)
+}
+
+// Scales every load, standby included, and the generation, logged or
+// modeled. The controller, BMS, and inverter's own draws stay as they are,
+// since they don't grow with the loads.
+fn fails_with(state: &SimState, load_multiplier: f32, solar_factor: f32) -> bool {
+    let mut trial = state.clone();
+    trial.load *= load_multiplier;
+    trial.standby_load *= load_multiplier;
+    trial.loads = trial.loads.iter().map(|l| l.scaled(load_multiplier)).collect();
+    trial.generation_factor *= solar_factor;
+    has_blackout(&run_simulation(&trial))
+}
+
+// Bisects between a passing and a failing value, returning the failing edge
+fn bisect(mut passing: f32, mut failing: f32, fails: impl Fn(f32) -> bool) -> f32 {
+    for _ in 0..BISECTION_STEPS {
+        let mid = (passing + failing) / 2.;
+        if fails(mid) {
+            failing = mid;
+        } else {
+            passing = mid;
+        }
+    }
+    failing
+}
+
+pub fn load_multiplier_at_failure(state: &SimState) -> Option<f32> {
+    trace::timed!("load_multiplier_at_failure");
+    let fails = |m: f32| fails_with(state, m, 1.);
+    if fails(1.) {
+        return Some(bisect(0., 1., fails));
+    }
+    let mut high = 2.;
+    while !fails(high) {
+        if high >= MAX_LOAD_MULTIPLIER {
+            return None;
+        }
+        high *= 2.;
+    }
+    Some(bisect(high / 2., high, fails))
+}
+
+pub fn solar_factor_at_failure(state: &SimState) -> Option<f32> {
+    trace::timed!("solar_factor_at_failure");
+    let fails = |f: f32| fails_with(state, 1., f);
+    if fails(1.) {
+        return Some(1.);
+    }
+    if !fails(0.) {
+        return None;
+    }
+    Some(bisect(1., 0., fails))
+}
+
+#[test]
+fn test_design_margin() {
+    let mut state = SimState::new();
+    state.latitude = 30.;
+    state.end_date = state.start_date + Duration::days(5);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 200.;
+    state.battery_capacity = 1000.;
+    state.load = 10.;
+    let margin = design_margin(&state);
+    let load = margin.load_multiplier.unwrap();
+    let solar = margin.solar_factor.unwrap();
+    assert!(load > 1. && solar < 1.);
+    assert!(!fails_with(&state, load * 0.98, 1.));
+    assert!(fails_with(&state, load * 1.02, 1.));
+    assert!(!fails_with(&state, 1., solar * 1.02));
+    assert!(fails_with(&state, 1., solar * 0.98))
+}
+
+#[test]
+fn test_blackout_warm_up() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 1000.;
+    state.load = 10.;
+    // An empty battery's first night doesn't count
+    let mut result = run_simulation(&state);
+    result.unmet_history = vec![0.; result.unmet_history.len()];
+    result.unmet_history[3] = 10.;
+    assert!(!has_blackout(&result));
+    // but a run that starts charged is judged from the start
+    state.initial_soc = 1.;
+    let mut result = run_simulation(&state);
+    result.unmet_history = vec![0.; result.unmet_history.len()];
+    result.unmet_history[3] = 10.;
+    assert!(has_blackout(&result));
+    // and standby load counts against the margin like any other
+    state.solar_nominal_output = 200.;
+    state.load = 0.;
+    state.standby_load = 10.;
+    assert!(fails_with(&state, 20., 1.) && !fails_with(&state, 1., 1.))
+}
+
+#[test]
+fn test_margin_of_logged_output() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 1000.;
+    state.load = 10.;
+    // A flat 50 W logged for the array, whatever its nominal output
+    let start = crate::simulation_start(&state);
+    state.power_series = Some(vec![(start, 50.), (start + Duration::days(3), 50.)]);
+    state.solar_nominal_output = 0.;
+    let solar = solar_factor_at_failure(&state).unwrap();
+    assert!((solar - 0.2).abs() < 0.01)
+}
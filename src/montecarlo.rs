@@ -0,0 +1,158 @@
+use chrono::NaiveDateTime;
+use rayon::prelude::*;
+
+use crate::{SimState, run_simulation};
+use crate::trace;
+
+// Low, median, and high percentiles of a series across the realizations,
+// step by step
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bands {
+    pub p10: Vec<f32>,
+    pub p50: Vec<f32>,
+    pub p90: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub mean: f32,
+    pub std_dev: f32,
+    pub min: f32,
+    pub p10: f32,
+    pub p50: f32,
+    pub p90: f32,
+    pub max: f32,
+}
+
+impl Distribution {
+    pub fn new(values: &[f32]) -> Distribution {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let count = sorted.len().max(1) as f32;
+        let mean = sorted.iter().sum::<f32>() / count;
+        let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+        Distribution {
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted.first().cloned().unwrap_or(0.),
+            p10: percentile(&sorted, 0.1),
+            p50: percentile(&sorted, 0.5),
+            p90: percentile(&sorted, 0.9),
+            max: sorted.last().cloned().unwrap_or(0.),
+        }
+    }
+}
+
+// Linear interpolation between the nearest ranks of sorted values
+pub fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.;
+    }
+    let rank = fraction.clamp(0., 1.) * (sorted.len() - 1) as f32;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f32)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonteCarloReport {
+    pub dates: Vec<NaiveDateTime>,
+    pub soc: Bands, // percent
+    pub unmet: Bands, // Wh
+    pub total_unmet: Distribution, // Wh over each run
+    pub minimum_soc: Distribution, // percent, lowest in each run
+    pub loss_of_load_probability: f32, // share of the runs with any unmet load
+}
+
+// Reruns the configuration with each random model reseeded: the weather,
+// solar and load noise, wind, snow, equipment failures, and random grid
+// outages. Models that aren't configured stay off, so a run without any
+// gives the same result every time.
+pub fn monte_carlo(state: &SimState, runs: usize, seed: u64) -> MonteCarloReport {
+    trace::timed!("monte_carlo", runs, seed);
+    let results: Vec<SimState> = (0..runs as u64).into_par_iter()
+        .map(|i| run_simulation(&reseeded(state, seed.wrapping_mul(0x9e37_79b9_7f4a_7c15).wrapping_add(i))))
+        .collect();
+    let dates = results.first().map_or(Vec::new(), |r| r.history_dates.clone());
+    let total_unmet: Vec<f32> = results.iter().map(|r| r.unmet_history.iter().sum()).collect();
+    let minimum_soc: Vec<f32> = results.iter()
+        .map(|r| r.soc_history.iter().cloned().fold(f32::INFINITY, f32::min))
+        .collect();
+    MonteCarloReport {
+        soc: bands(&results, dates.len(), |r| &r.soc_history),
+        unmet: bands(&results, dates.len(), |r| &r.unmet_history),
+        dates,
+        loss_of_load_probability: total_unmet.iter().filter(|u| **u > 0.).count() as f32 / runs.max(1) as f32,
+        total_unmet: Distribution::new(&total_unmet),
+        minimum_soc: Distribution::new(&minimum_soc),
+    }
+}
+
+fn reseeded(state: &SimState, seed: u64) -> SimState {
+    let mut state = state.clone();
+    if let Some(weather) = &mut state.weather {
+        weather.seed = seed;
+    }
+    if let Some(noise) = &mut state.solar_noise {
+        noise.seed = seed.wrapping_add(1);
+    }
+    if let Some(noise) = &mut state.load_noise {
+        noise.seed = seed.wrapping_add(2);
+    }
+    if let Some(wind) = &mut state.wind {
+        wind.seed = seed.wrapping_add(3);
+    }
+    if let Some(snow) = &mut state.snow {
+        snow.seed = seed.wrapping_add(4);
+    }
+    if let Some(failures) = &mut state.failures {
+        failures.seed = seed.wrapping_add(5);
+    }
+    if let Some(grid) = &mut state.grid {
+        grid.seed = seed.wrapping_add(6);
+    }
+    state
+}
+
+fn bands(results: &[SimState], steps: usize, series: impl Fn(&SimState) -> &Vec<f32>) -> Bands {
+    let mut bands = Bands::default();
+    for i in 0..steps {
+        let mut values: Vec<f32> = results.iter().filter_map(|r| series(r).get(i).cloned()).collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+        bands.p10.push(percentile(&values, 0.1));
+        bands.p50.push(percentile(&values, 0.5));
+        bands.p90.push(percentile(&values, 0.9));
+    }
+    bands
+}
+
+#[test]
+fn test_percentile() {
+    let sorted = [1., 2., 3., 4., 5.];
+    assert_eq!(percentile(&sorted, 0.5), 3.);
+    assert_eq!(percentile(&sorted, 0.1), 1.4);
+    let distribution = Distribution::new(&[4., 2., 2., 4.]);
+    assert_eq!((distribution.mean, distribution.std_dev, distribution.min, distribution.max), (3., 1., 2., 4.))
+}
+
+#[test]
+fn test_monte_carlo() {
+    let mut state = SimState::new();
+    state.start_date = chrono::NaiveDate::from_ymd_opt(2023, 4, 1).unwrap();
+    state.end_date = state.start_date + chrono::Duration::days(10);
+    state.step_size = chrono::Duration::hours(1);
+    state.latitude = 45.;
+    state.solar_nominal_output = 200.;
+    state.battery_capacity = 800.;
+    state.initial_soc = 1.;
+    state.load = 20.;
+    state.weather = Some(crate::weather::WeatherGenerator::temperate(1));
+    state.load_noise = Some(crate::weather::SolarNoise::new(0.2, 1));
+    let report = monte_carlo(&state, 16, 7);
+    assert_eq!(report.soc.p50.len(), report.dates.len());
+    assert!(report.soc.p10.iter().zip(&report.soc.p90).all(|(low, high)| low <= high));
+    // A marginal spring system blacks out in some weathers but not all
+    assert!(report.total_unmet.max > report.total_unmet.min);
+    assert!(report.loss_of_load_probability > 0. && report.loss_of_load_probability < 1.);
+    // and the same seed gives the same report
+    assert_eq!(monte_carlo(&state, 16, 7), report)
+}
@@ -0,0 +1,109 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use std::io;
+
+use crate::clearsky::{self, Irradiance};
+use crate::irradiance::{IrradianceSeries, WeatherProvider};
+use crate::sun::{self, Orientation, TrackingMode};
+
+// Satellite-derived hourly irradiance for the Americas from NREL's National
+// Solar Radiation Database (PSM3). Needs a free API key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsrdbRequest {
+    pub latitude: f32,
+    pub longitude: f32, // degrees east
+    pub utc_offset: f32, // hours, the site's standard time, which the data is stamped in
+    pub orientation: Option<Orientation>, // always facing the sun when None
+    pub tracking: TrackingMode,
+    pub year: i32, // 1998 to 2022 are available
+    pub api_key: String,
+    pub email: String,
+    pub albedo: f32, // of the ground in front of the panel
+}
+
+impl WeatherProvider for NsrdbRequest {
+    fn name(&self) -> &str {
+        "NSRDB"
+    }
+
+    fn url(&self) -> String {
+        format!(
+            "https://developer.nrel.gov/api/nsrdb/v2/solar/psm3-download.csv?wkt=POINT({}%20{})&names={}&interval=60&attributes=ghi,dni,dhi&utc=false&leap_day=false&api_key={}&email={}",
+            self.longitude, self.latitude, self.year, self.api_key, self.email,
+        )
+    }
+
+    // The key is left out so a cache can be shared
+    fn cache_name(&self) -> String {
+        format!("nsrdb_{}_{}_{}.csv", self.latitude, self.longitude, self.year)
+    }
+
+    fn parse(&self, text: &str) -> io::Result<IrradianceSeries> {
+        let rows = parse(text)?;
+        Ok(rows.into_iter()
+            .map(|(time, sky)| {
+                let utc = time - Duration::seconds((self.utc_offset * 3600.) as i64);
+                let position = sun::solar_position(utc, self.latitude, self.longitude);
+                (time, clearsky::plane_of_array(position, self.orientation, self.tracking, sky, self.albedo))
+            })
+            .collect())
+    }
+}
+
+// Horizontal components as downloaded, before they're turned to the panel.
+// Two metadata lines come before the `Year,Month,Day,Hour,Minute,...` header.
+pub fn parse(text: &str) -> io::Result<Vec<(NaiveDateTime, Irradiance)>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("NSRDB: {}", message));
+    let mut lines = text.lines();
+    let header: Vec<String> = lines.by_ref()
+        .find(|line| line.starts_with("Year,"))
+        .ok_or_else(|| invalid("no data header"))?
+        .split(',').map(|c| c.trim().to_uppercase()).collect();
+    let column = |name: &str| header.iter().position(|c| c == name)
+        .ok_or_else(|| invalid(&format!("no {} column", name)));
+    let (ghi, dni, dhi) = (column("GHI")?, column("DNI")?, column("DHI")?);
+
+    let mut rows = Vec::new();
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<f32> = line.split(',')
+            .map(|f| f.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid(&format!("bad row {}", line)))?;
+        let field = |i: usize| fields.get(i).cloned().ok_or_else(|| invalid(&format!("short row {}", line)));
+        let time = NaiveDate::from_ymd_opt(field(0)? as i32, field(1)? as u32, field(2)? as u32)
+            .and_then(|d| d.and_hms_opt(field(3).ok()? as u32, field(4).ok()? as u32, 0))
+            .ok_or_else(|| invalid(&format!("bad time {}", line)))?;
+        rows.push((time, Irradiance { global: field(ghi)?, direct_normal: field(dni)?, diffuse: field(dhi)? }));
+    }
+    Ok(rows)
+}
+
+#[test]
+fn test_parse() {
+    let text = "Source,Location ID,City,State,Country,Latitude,Longitude\n\
+        NSRDB,123,-,-,-,40.01,-105.26\n\
+        Year,Month,Day,Hour,Minute,GHI,DNI,DHI\n\
+        2019,1,1,0,30,0,0,0\n\
+        2019,6,21,12,30,980,870,110\n";
+    let rows = parse(text).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].1.direct_normal, 870.);
+    assert!(parse("Year,Month,Day,Hour,Minute,GHI\n").is_err());
+
+    let request = NsrdbRequest {
+        latitude: 40.01,
+        longitude: -105.26,
+        utc_offset: -7.,
+        orientation: None,
+        tracking: TrackingMode::Fixed,
+        year: 2019,
+        api_key: "KEY".to_string(),
+        email: "me@example.com".to_string(),
+        albedo: clearsky::GRASS_ALBEDO,
+    };
+    let series = request.parse(text).unwrap();
+    assert_eq!(series[0].1, 0.);
+    // Facing the noon sun it sees all the beam and most of the sky
+    assert!(series[1].1 > 870. + 0.9 * 110. && series[1].1 < 980.);
+    assert!(request.url().contains("POINT(-105.26%2040.01)"));
+    assert!(!request.cache_name().contains("KEY"))
+}
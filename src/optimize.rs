@@ -0,0 +1,170 @@
+use crate::{SimState, consumed_energy, run_simulation, simulation_end, start_run, step_hours, unmet_energy};
+use crate::sun::Orientation;
+use crate::trace;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Reliability, // least unmet load
+    MinimumBattery, // smallest battery that is as reliable as an unlimited one
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrientationResult {
+    pub orientation: Orientation,
+    pub unmet_energy: f32, // Wh
+    pub solar_energy: f32, // Wh
+    pub required_battery: Option<f32>, // Wh, only searched for Objective::MinimumBattery
+}
+
+// Sweeps tilt from flat to vertical and azimuth up to 90 degrees either side
+// of the equator, returning every candidate with the best first.
+pub fn optimize_orientation(state: &SimState, objective: Objective, tilt_step: f32, azimuth_step: f32) -> Vec<OrientationResult> {
+    trace::timed!("optimize_orientation", ?objective, tilt_step, azimuth_step);
+    let facing: f32 = if state.latitude >= 0. { 180. } else { 0. };
+    let mut results = Vec::new();
+    let mut tilt = 0.;
+    while tilt <= 90. {
+        let mut offset = -90.;
+        while offset <= 90. {
+            let orientation = Orientation::new(tilt, (facing + offset).rem_euclid(360.));
+            results.push(evaluate(state, orientation, objective));
+            if tilt == 0. || azimuth_step <= 0. {
+                // Azimuth doesn't matter for a flat panel
+                break;
+            }
+            offset += azimuth_step;
+        }
+        if tilt_step <= 0. {
+            break;
+        }
+        tilt += tilt_step;
+    }
+
+    results.sort_by(|a, b| {
+        a.required_battery.zip(b.required_battery)
+            .map_or(std::cmp::Ordering::Equal, |(a, b)| a.total_cmp(&b))
+            .then(a.unmet_energy.total_cmp(&b.unmet_energy))
+            .then(b.solar_energy.total_cmp(&a.solar_energy))
+    });
+    results
+}
+
+fn evaluate(state: &SimState, orientation: Orientation, objective: Objective) -> OrientationResult {
+    let mut trial = state.clone();
+    trial.orientation = Some(orientation);
+    let result = run_simulation(&trial);
+    OrientationResult {
+        orientation,
+        unmet_energy: unmet_energy(&result),
+        solar_energy: result.solar_history.iter().sum::<f32>() * step_hours(&result),
+        required_battery: match objective {
+            Objective::Reliability => None,
+            Objective::MinimumBattery => Some(minimum_battery(&trial)),
+        },
+    }
+}
+
+// Smallest capacity, found by bisection, whose unmet load is within 1 Wh of
+// what an unlimited battery achieves. Storage beyond this buys nothing. The
+// unlimited battery holds the whole run's load, more than a battery can
+// ever deliver, and banks keep their share of the capacity throughout.
+pub fn minimum_battery(state: &SimState) -> f32 {
+    trace::timed!("minimum_battery");
+    let mut trial = state.clone();
+    set_capacity(&mut trial, state, load_energy(state) / state.discharge_efficiency);
+    let unlimited = run_simulation(&trial);
+    let target = unmet_energy(&unlimited) + 1.;
+    let mut high = unlimited.charge_history.iter().cloned().fold(0., f32::max);
+    let mut low = 0.;
+    for _ in 0..20 {
+        let mid = (low + high) / 2.;
+        set_capacity(&mut trial, state, mid);
+        if unmet_energy(&run_simulation(&trial)) <= target {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    high
+}
+
+// Wh the equipment and loads would draw over the run with the battery full,
+// none of them shed and every deferrable load running
+fn load_energy(state: &SimState) -> f32 {
+    let mut probe = start_run(state);
+    probe.current_stored_energy = probe.storage_capacity();
+    let end = simulation_end(state);
+    let mut energy = 0.;
+    while probe.now < end {
+        energy += consumed_energy(&probe);
+        probe.now += probe.step_size;
+    }
+    energy
+}
+
+fn set_capacity(trial: &mut SimState, state: &SimState, capacity: f32) {
+    let total = state.storage_capacity();
+    if state.banks.is_empty() || total <= 0. {
+        trial.banks.clear();
+        trial.battery_capacity = capacity;
+        return;
+    }
+    for (bank, original) in trial.banks.iter_mut().zip(&state.banks) {
+        bank.capacity = original.capacity * capacity / total;
+    }
+}
+
+#[test]
+fn test_optimize_winter_tilt() {
+    // A winter-only run at 45N rewards a steep, south facing panel
+    let mut state = SimState::new();
+    state.latitude = 45.;
+    state.end_date = state.start_date + chrono::Duration::days(7);
+    state.step_size = chrono::Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.battery_capacity = 2000.;
+    state.load = 25.;
+    let results = optimize_orientation(&state, Objective::Reliability, 15., 45.);
+    let best = results[0].orientation;
+    assert_eq!(best.azimuth, 180.);
+    assert!(best.tilt >= 45.);
+    assert!(results[0].unmet_energy < results.last().unwrap().unmet_energy)
+}
+
+#[test]
+fn test_minimum_battery() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + chrono::Duration::days(3);
+    state.step_size = chrono::Duration::hours(1);
+    state.solar_nominal_output = 200.;
+    state.load = 10.;
+    let capacity = minimum_battery(&state);
+    assert!(capacity > 0.);
+    state.battery_capacity = capacity;
+    let with_minimum = unmet_energy(&run_simulation(&state));
+    state.battery_capacity = capacity * 0.5;
+    assert!(unmet_energy(&run_simulation(&state)) > with_minimum)
+}
+
+#[test]
+fn test_minimum_battery_with_initial_charge_and_banks() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + chrono::Duration::days(3);
+    state.step_size = chrono::Duration::hours(1);
+    state.solar_nominal_output = 200.;
+    state.load = 10.;
+    state.initial_soc = 0.5;
+    state.shed_soc = vec![0.3];
+    let capacity = minimum_battery(&state);
+    // A finite reference run still has a meaningful state of charge
+    assert!(capacity > 0. && capacity < 1000.);
+    state.battery_capacity = capacity;
+    let single = unmet_energy(&run_simulation(&state));
+    state.banks = vec![crate::bank::BatteryBank::new("New", 300.), crate::bank::BatteryBank::new("Old", 100.)];
+    let banked = minimum_battery(&state);
+    assert!(banked > 0. && banked < 1000.);
+    let mut trial = state.clone();
+    set_capacity(&mut trial, &state, banked);
+    assert!((trial.banks[0].capacity - 3. * trial.banks[1].capacity).abs() < 0.01);
+    assert!(unmet_energy(&run_simulation(&trial)) <= single + 1.)
+}
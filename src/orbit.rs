@@ -0,0 +1,111 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use std::f64::consts::PI;
+
+pub const EARTH_RADIUS: f32 = 6378.137; // km
+pub const EARTH_MU: f32 = 398600.44; // km^3/s^2
+
+// Illumination model for a spacecraft in a circular orbit. The panel is
+// assumed to be sun-pointing, so it produces nominal output whenever the
+// spacecraft is outside the Earth's shadow (cylindrical shadow model).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orbit {
+    pub period: Duration,
+    pub altitude: f32, // km
+    pub beta_angle: f32, // degrees, angle between the orbit plane and the sun vector
+}
+
+impl Orbit {
+    pub fn new(period: Duration, altitude: f32, beta_angle: f32) -> Orbit {
+        Orbit {
+            period,
+            altitude,
+            beta_angle,
+        }
+    }
+
+    // Circular LEO where the period follows from the altitude
+    pub fn leo(altitude: f32, beta_angle: f32) -> Orbit {
+        let a = (EARTH_RADIUS + altitude) as f64;
+        let period = 2. * PI * (a.powi(3) / EARTH_MU as f64).sqrt();
+        Orbit::new(Duration::milliseconds((period * 1000.) as i64), altitude, beta_angle)
+    }
+
+    pub fn eclipse_fraction(&self) -> f32 {
+        let r = EARTH_RADIUS;
+        let h = self.altitude;
+        let beta_star = (r / (r + h)).asin();
+        let beta = self.beta_angle.to_radians().abs();
+        if beta >= beta_star {
+            return 0.;
+        }
+        let x = (h * h + 2. * r * h).sqrt() / ((r + h) * beta.cos());
+        x.clamp(-1., 1.).acos() / std::f32::consts::PI
+    }
+
+    pub fn sunlit_hours_per_day(&self) -> f32 {
+        24. * (1. - self.eclipse_fraction())
+    }
+
+    // Hours spent in sunlight between start and end. Orbit phase is measured
+    // from a fixed reference epoch, with the eclipse centred on half an orbit.
+    pub fn sunlit_hours(&self, start: NaiveDateTime, end: NaiveDateTime) -> f32 {
+        let period = self.period.num_milliseconds() as f64 / 1000.;
+        if end <= start || period <= 0. {
+            return 0.;
+        }
+        let a = seconds_since_epoch(start);
+        let b = seconds_since_epoch(end);
+        let f = self.eclipse_fraction() as f64;
+
+        let mut eclipsed = 0.;
+        let mut k = (a / period).floor() - 1.;
+        while k * period < b {
+            let e_start = (k + 0.5 - f / 2.) * period;
+            let e_end = (k + 0.5 + f / 2.) * period;
+            let overlap = e_end.min(b) - e_start.max(a);
+            if overlap > 0. {
+                eclipsed += overlap;
+            }
+            k += 1.;
+        }
+        ((b - a - eclipsed) / 3600.) as f32
+    }
+
+    pub fn sunlit_fraction(&self, start: NaiveDateTime, end: NaiveDateTime) -> f32 {
+        let hours = (end - start).num_milliseconds() as f32 / (1000. * 60. * 60.);
+        if hours <= 0. {
+            0.
+        } else {
+            self.sunlit_hours(start, end) / hours
+        }
+    }
+}
+
+fn seconds_since_epoch(time: NaiveDateTime) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    (time - epoch).num_milliseconds() as f64 / 1000.
+}
+
+#[test]
+fn test_iss_eclipse_fraction() {
+    let orbit = Orbit::leo(408., 0.);
+    let period_minutes = orbit.period.num_seconds() as f32 / 60.;
+    assert!((period_minutes - 92.6).abs() < 0.5);
+    assert!((orbit.eclipse_fraction() * period_minutes - 35.8).abs() < 1.)
+}
+
+#[test]
+fn test_full_sun_beta_angle() {
+    let orbit = Orbit::leo(500., 75.);
+    assert_eq!(orbit.eclipse_fraction(), 0.);
+    assert_eq!(orbit.sunlit_hours_per_day(), 24.)
+}
+
+#[test]
+fn test_sunlit_hours_over_one_orbit() {
+    let orbit = Orbit::leo(550., 20.);
+    let start = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap().and_hms_opt(3, 17, 0).unwrap();
+    let hours = orbit.sunlit_hours(start, start + orbit.period);
+    let expected = orbit.period.num_milliseconds() as f32 / 3600000. * (1. - orbit.eclipse_fraction());
+    assert!((hours - expected).abs() < 0.001)
+}
@@ -0,0 +1,124 @@
+use chrono::{Duration, NaiveDateTime};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    ChargeController, // nothing reaches the battery from the array
+    ArrayString, // one string of the array produces nothing
+    Array, // the whole array produces nothing, e.g. while being cleaned
+    Grid, // the utility supply is lost
+    Generator, // the backup generator can't run, e.g. while being serviced
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outage {
+    pub component: Component,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl Outage {
+    pub fn new(component: Component, start: NaiveDateTime, duration: Duration) -> Outage {
+        Outage { component, start, end: start + duration }
+    }
+
+    pub fn covers(&self, now: NaiveDateTime) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailureMode {
+    pub component: Component,
+    pub rate: f32, // failures per year
+    pub duration: Duration, // time to repair
+}
+
+impl FailureMode {
+    pub fn new(component: Component, rate: f32, duration: Duration) -> FailureMode {
+        FailureMode { component, rate, duration }
+    }
+}
+
+// Random equipment faults, each mode failing as a Poisson process
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureGenerator {
+    pub modes: Vec<FailureMode>,
+    pub seed: u64,
+}
+
+impl FailureGenerator {
+    pub fn new(modes: Vec<FailureMode>, seed: u64) -> FailureGenerator {
+        FailureGenerator { modes, seed }
+    }
+
+    pub fn generate(&self, start: NaiveDateTime, end: NaiveDateTime) -> Vec<Outage> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut outages = Vec::new();
+        for mode in &self.modes {
+            if mode.rate <= 0. {
+                continue;
+            }
+            let mean_gap_hours = 365. * 24. / mode.rate;
+            let mut time = start;
+            loop {
+                // Exponential time to the next failure, counted once repaired
+                let gap = -mean_gap_hours * (1. - rng.gen::<f32>()).ln();
+                time += Duration::seconds((gap * 3600.) as i64);
+                if time >= end {
+                    break;
+                }
+                outages.push(Outage::new(mode.component, time, mode.duration));
+                time += mode.duration;
+            }
+        }
+        outages.sort_by_key(|o| o.start);
+        outages
+    }
+}
+
+pub fn is_down(outages: &[Outage], component: Component, now: NaiveDateTime) -> bool {
+    outages.iter().any(|o| o.component == component && o.covers(now))
+}
+
+// Fraction of the array's strings producing
+pub fn array_availability(outages: &[Outage], strings: u32, now: NaiveDateTime) -> f32 {
+    if is_down(outages, Component::Array, now) {
+        return 0.;
+    }
+    let strings = strings.max(1);
+    let down = outages.iter()
+        .filter(|o| o.component == Component::ArrayString && o.covers(now))
+        .count() as u32;
+    strings.saturating_sub(down) as f32 / strings as f32
+}
+
+#[test]
+fn test_failure_rate_and_seed() {
+    let start = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let end = start + Duration::days(365 * 20);
+    let generator = FailureGenerator::new(
+        vec![FailureMode::new(Component::ChargeController, 2., Duration::hours(12))],
+        3,
+    );
+    let outages = generator.generate(start, end);
+    assert!((outages.len() as f32 / 20. - 2.).abs() < 0.5);
+    assert!(outages.windows(2).all(|w| w[0].end <= w[1].start));
+    assert_eq!(outages, generator.generate(start, end))
+}
+
+#[test]
+fn test_array_availability() {
+    let start = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let outages = vec![
+        Outage::new(Component::ArrayString, start, Duration::days(7)),
+        Outage::new(Component::ChargeController, start, Duration::hours(4)),
+    ];
+    assert_eq!(array_availability(&outages, 4, start + Duration::days(1)), 0.75);
+    assert_eq!(array_availability(&outages, 4, start + Duration::days(7)), 1.);
+    let cleaning = [Outage::new(Component::Array, start, Duration::hours(8))];
+    assert_eq!(array_availability(&cleaning, 4, start), 0.);
+    assert!(is_down(&outages, Component::ChargeController, start + Duration::hours(3)));
+    assert!(!is_down(&outages, Component::ChargeController, start + Duration::hours(4)))
+}
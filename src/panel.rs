@@ -0,0 +1,79 @@
+// Datasheet values for a solar module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PanelSpec {
+    pub name: &'static str,
+    pub rated_power: f32, // watts at standard test conditions
+    pub temperature_coefficient: f32, // fraction of power per C above 25 C, negative
+    pub noct: f32, // C, nominal operating cell temperature at 800 W/m2 and 20 C air
+    pub low_light_loss: f32, // fraction of efficiency lost at 200 W/m2
+    pub width: f32, // m
+    pub height: f32, // m
+}
+
+// Typical modules of each kind, not any one manufacturer's
+pub const PRESETS: [PanelSpec; 6] = [
+    PanelSpec { name: "100 W Mono (12 V)", rated_power: 100., temperature_coefficient: -0.0040, noct: 45., low_light_loss: 0.04, width: 0.54, height: 1.0 },
+    PanelSpec { name: "100 W Flexible", rated_power: 100., temperature_coefficient: -0.0045, noct: 48., low_light_loss: 0.06, width: 0.54, height: 1.12 },
+    PanelSpec { name: "200 W Mono (RV)", rated_power: 200., temperature_coefficient: -0.0037, noct: 45., low_light_loss: 0.04, width: 0.67, height: 1.48 },
+    PanelSpec { name: "300 W Polycrystalline", rated_power: 300., temperature_coefficient: -0.0041, noct: 46., low_light_loss: 0.05, width: 0.99, height: 1.96 },
+    PanelSpec { name: "400 W Mono PERC", rated_power: 400., temperature_coefficient: -0.0035, noct: 45., low_light_loss: 0.03, width: 1.13, height: 1.72 },
+    PanelSpec { name: "450 W Half-Cut Mono", rated_power: 450., temperature_coefficient: -0.0034, noct: 43., low_light_loss: 0.03, width: 1.04, height: 2.09 },
+];
+
+pub fn preset(name: &str) -> Option<PanelSpec> {
+    PRESETS.iter().find(|p| p.name == name).copied()
+}
+
+impl PanelSpec {
+    pub fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    // Whole panels needed for an array of at least the nominal output
+    pub fn count_for(&self, nominal_output: f32) -> u32 {
+        (nominal_output / self.rated_power).ceil().max(1.) as u32
+    }
+
+    // The NOCT model: cells warm above the air in proportion to irradiance
+    pub fn cell_temperature(&self, ambient: f32, irradiance: f32) -> f32 {
+        ambient + (self.noct - 20.) / 800. * irradiance
+    }
+
+    // Efficiency relative to standard test conditions, falling off
+    // logarithmically as the light dims
+    pub fn low_light_efficiency(&self, irradiance: f32) -> f32 {
+        if irradiance <= 0. {
+            return 0.;
+        }
+        let slope = self.low_light_loss / 5_f32.ln();
+        (1. + slope * (irradiance / 1000.).ln()).clamp(0., 1.)
+    }
+
+    // Output relative to the rating for the cells' temperature
+    pub fn temperature_factor(&self, ambient: f32, irradiance: f32) -> f32 {
+        (1. + self.temperature_coefficient * (self.cell_temperature(ambient, irradiance) - 25.)).max(0.)
+    }
+}
+
+#[test]
+fn test_presets() {
+    let panel = preset("400 W Mono PERC").unwrap();
+    assert_eq!(panel.count_for(1000.), 3);
+    assert!((panel.area() - 1.94).abs() < 0.01);
+    // Full sun on a 25 C day heats the cells to about 56 C
+    assert!((panel.cell_temperature(25., 1000.) - 56.25).abs() < 0.01);
+    assert!((panel.temperature_factor(25., 1000.) - 0.89).abs() < 0.01);
+    // Cold, bright days beat the rating
+    assert!(panel.temperature_factor(-10., 600.) > 1.);
+    assert_eq!(preset("Unknown"), None)
+}
+
+#[test]
+fn test_low_light_efficiency() {
+    let panel = preset("300 W Polycrystalline").unwrap();
+    assert_eq!(panel.low_light_efficiency(1000.), 1.);
+    assert!((panel.low_light_efficiency(200.) - 0.95).abs() < 0.0001);
+    // Dawn light converts worse still
+    assert!(panel.low_light_efficiency(20.) < 0.9);
+    assert_eq!(panel.low_light_efficiency(0.), 0.)
+}
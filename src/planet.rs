@@ -0,0 +1,177 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use std::f32::consts::PI;
+
+pub const EARTH_SOLAR_CONSTANT: f32 = 1361.; // W/m2
+
+// Body the site is on. Defaults to Earth; other values are for non-Earth or
+// exaggerated scenarios.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Planet {
+    pub day_length: f32, // hours from noon to noon
+    pub year_length: f32, // local days
+    pub solar_constant: f32, // W/m2 at the top of the atmosphere
+    pub obliquity: f32, // degrees of axial tilt
+}
+
+impl Default for Planet {
+    fn default() -> Self {
+        Planet::earth()
+    }
+}
+
+impl Planet {
+    pub fn earth() -> Planet {
+        Planet {
+            day_length: 24.,
+            year_length: 365.25,
+            solar_constant: EARTH_SOLAR_CONSTANT,
+            obliquity: 23.45,
+        }
+    }
+
+    pub fn mars() -> Planet {
+        Planet {
+            day_length: 24.6597,
+            year_length: 668.6,
+            solar_constant: 586.2,
+            obliquity: 25.19,
+        }
+    }
+
+    // Panel output relative to the same panel on Earth
+    pub fn irradiance_factor(&self) -> f32 {
+        self.solar_constant / EARTH_SOLAR_CONSTANT
+    }
+
+    // Local day of year and hour of day. Days are counted from the start of
+    // the calendar year, so on Earth these are the ordinal and clock time.
+    pub fn local_time(&self, now: NaiveDateTime) -> (u32, f32) {
+        let year_start = NaiveDate::from_ymd_opt(now.year(), 1, 1).unwrap()
+            .and_hms_opt(0, 0, 0).unwrap();
+        let hours = (now - year_start).num_seconds() as f64 / 3600.;
+        let day_length = self.day_length as f64;
+        let day = (hours / day_length).floor();
+        let hour = (hours - day * day_length) as f32;
+        (day.rem_euclid(self.year_length as f64) as u32, hour)
+    }
+
+    // Forsythe's CBM model, with the days measured from just after the June
+    // solstice so the declination is positive in the northern summer.
+    // Negative latitudes get the mirrored seasons.
+    pub fn daylight_hours(&self, lat: f32, day: u32) -> f32 {
+        let solstice = 186. * self.year_length / 365.25;
+        let p = (self.obliquity.to_radians().sin()*
+            (0.2163108 + 2.*
+                (0.9671396*
+                    (PI / self.year_length*(day as f32 - solstice)).tan()
+                ).atan()
+            ).cos()
+        ).asin();
+
+        let numerator = 0.8333_f32.to_radians().sin() + lat.to_radians().sin()*p.sin();
+        let denom = lat.to_radians().cos()*p.cos();
+        self.day_length - (self.day_length/PI)*(numerator/denom).clamp(-1., 1.).acos()
+    }
+
+    // Stretches of daylight between two instants, as (from, to, noon,
+    // daylight hours of that day), the first three in hours after the start.
+    // The span may run on into any number of later local days.
+    pub fn daylight_spans(&self, start: NaiveDateTime, end: NaiveDateTime, lat: f32) -> Vec<(f32, f32, f32, f32)> {
+        let (day, hour) = self.local_time(start);
+        let finish = hour + (end - start).num_seconds() as f32 / 3600.;
+        let mut spans = Vec::new();
+        let mut days = 0;
+        while days as f32 * self.day_length < finish {
+            let light = self.daylight_hours(lat, (day + days) % self.year_length as u32);
+            let noon = (days as f32 + 0.5) * self.day_length;
+            let (from, to) = (hour.max(noon - light / 2.), finish.min(noon + light / 2.));
+            if to > from {
+                spans.push((from - hour, to - hour, noon - hour, light));
+            }
+            days += 1;
+        }
+        spans
+    }
+
+    // Hours of daylight between two instants
+    pub fn bounded_daylight_hours(&self, start: NaiveDateTime, end: NaiveDateTime, lat: f32) -> f32 {
+        self.daylight_spans(start, end, lat).iter().map(|(from, to, _, _)| to - from).sum()
+    }
+
+    // Integral of the production curve between two instants, in hours at
+    // full output. Exact for any span, where sampling the curve misses its
+    // shape over long steps.
+    pub fn production_hours(&self, start: NaiveDateTime, end: NaiveDateTime, lat: f32) -> f32 {
+        self.daylight_spans(start, end, lat).iter()
+            .map(|(from, to, noon, light)| {
+                let phase = |h: f32| (2. * PI * (h - noon) / light).sin();
+                0.5 * (to - from) + light / (4. * PI) * (phase(*to) - phase(*from))
+            })
+            .sum()
+    }
+
+    // Fraction of nominal output over the day, a cosine peaking at local noon
+    pub fn production_curve(&self, now: NaiveDateTime, lat: f32) -> f32 {
+        let (day, hour) = self.local_time(now);
+        let light_hours = self.daylight_hours(lat, day);
+        let noon = self.day_length / 2.;
+        if (hour - noon).abs() >= light_hours / 2. {
+            0.
+        } else {
+            let time_scaler = (2.*PI)/light_hours;
+            0.5*(time_scaler*(hour - noon)).cos() + 0.5
+        }
+    }
+}
+
+#[test]
+fn test_earth_local_time_is_clock_time() {
+    let now = NaiveDate::from_ymd_opt(2023, 3, 15).unwrap().and_hms_opt(13, 30, 0).unwrap();
+    assert_eq!(Planet::earth().local_time(now), (now.ordinal0(), 13.5));
+    assert!((Planet::earth().daylight_hours(0., 85) - 12.).abs() < 0.15)
+}
+
+#[test]
+fn test_southern_seasons() {
+    let earth = Planet::earth();
+    let (june, december) = (171, 354);
+    assert!(earth.daylight_hours(45., june) > 15.);
+    assert!(earth.daylight_hours(-45., december) > 15.);
+    assert!(earth.daylight_hours(-45., june) < 9.);
+    // The seasons mirror across the equator
+    assert!((earth.daylight_hours(-35., december) - earth.daylight_hours(35., june)).abs() < 0.2);
+    let longest = (0..365).max_by(|a, b| {
+        earth.daylight_hours(-35., *a).total_cmp(&earth.daylight_hours(-35., *b))
+    });
+    assert!(matches!(longest, Some(day) if (350..360).contains(&day)));
+    // A December afternoon in Sydney outproduces one in June
+    let afternoon = |month| NaiveDate::from_ymd_opt(2023, month, 21).unwrap().and_hms_opt(16, 0, 0).unwrap();
+    assert!(earth.production_curve(afternoon(12), -33.9) > 2. * earth.production_curve(afternoon(6), -33.9))
+}
+
+#[test]
+fn test_mars_sol_drifts_from_clock() {
+    let mars = Planet::mars();
+    let now = NaiveDate::from_ymd_opt(2023, 1, 11).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let (day, hour) = mars.local_time(now);
+    // Ten Earth days are a little under ten sols
+    assert_eq!(day, 9);
+    assert!((hour - (240. - 9. * 24.6597)).abs() < 0.001);
+    let noon = now + chrono::Duration::minutes(((mars.day_length / 2. - hour) * 60.) as i64);
+    assert!((mars.production_curve(noon, 0.) - 1.).abs() < 0.01);
+    assert!((mars.irradiance_factor() - 0.43).abs() < 0.01)
+}
+
+#[test]
+fn test_production_hours() {
+    let earth = Planet::earth();
+    let midnight = NaiveDate::from_ymd_opt(2023, 3, 21).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    // The raised cosine averages half of full output over the daylight
+    let light = earth.daylight_hours(40., midnight.ordinal0());
+    let day = earth.production_hours(midnight, midnight + chrono::Duration::days(1), 40.);
+    assert!((day - light / 2.).abs() < 0.001);
+    // Two days at once are the sum of each, whatever the step
+    let two = earth.production_hours(midnight, midnight + chrono::Duration::days(2), 40.);
+    let second = earth.production_hours(midnight + chrono::Duration::days(1), midnight + chrono::Duration::days(2), 40.);
+    assert!((two - day - second).abs() < 0.001)
+}
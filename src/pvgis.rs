@@ -0,0 +1,114 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use std::io;
+
+use crate::irradiance::{IrradianceSeries, WeatherProvider};
+use crate::sun::{Orientation, TrackingMode};
+
+// Hourly plane-of-array irradiance from the EU's PVGIS service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PvgisRequest {
+    pub latitude: f32,
+    pub longitude: f32, // degrees east
+    pub orientation: Orientation,
+    pub tracking: TrackingMode,
+    pub year: i32, // 2005 to 2020 are available
+}
+
+impl WeatherProvider for PvgisRequest {
+    fn name(&self) -> &str {
+        "PVGIS"
+    }
+
+    fn url(&self) -> String {
+        // PVGIS aspect is measured from south, west positive
+        let tracking = match self.tracking {
+            TrackingMode::Fixed => 0,
+            TrackingMode::SingleAxis => 4,
+            TrackingMode::DualAxis => 2,
+        };
+        format!(
+            "https://re.jrc.ec.europa.eu/api/v5_2/seriescalc?lat={}&lon={}&angle={}&aspect={}&trackingtype={}&startyear={}&endyear={}&outputformat=csv",
+            self.latitude, self.longitude, self.orientation.tilt, self.orientation.azimuth - 180.,
+            tracking, self.year, self.year,
+        )
+    }
+
+    fn cache_name(&self) -> String {
+        format!(
+            "pvgis_{}_{}_{}_{}_{:?}_{}.csv",
+            self.latitude, self.longitude, self.orientation.tilt, self.orientation.azimuth,
+            self.tracking, self.year,
+        )
+    }
+
+    fn parse(&self, text: &str) -> io::Result<IrradianceSeries> {
+        parse(text)
+    }
+}
+
+// The CSV has a preamble, a `time,...,G(i),...` header, hourly rows stamped
+// like 20190101:0010 and a legend after the data
+pub fn parse(text: &str) -> io::Result<IrradianceSeries> {
+    let mut lines = text.lines();
+    let column = lines.by_ref()
+        .find_map(|line| {
+            let columns: Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+            if columns.first() == Some(&"time") {
+                columns.iter().position(|c| *c == "G(i)")
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no G(i) column in PVGIS response"))?;
+    let mut series = IrradianceSeries::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').collect();
+        let time = fields.first().and_then(|t| parse_time(t.trim()));
+        let value = fields.get(column).and_then(|v| v.trim().parse::<f32>().ok());
+        match (time, value) {
+            (Some(time), Some(value)) => series.push((time, value)),
+            // End of the data
+            _ => break,
+        }
+    }
+    if series.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no rows in PVGIS response"));
+    }
+    Ok(series)
+}
+
+fn parse_time(text: &str) -> Option<NaiveDateTime> {
+    let (date, time) = text.split_once(':')?;
+    let date = NaiveDate::parse_from_str(date, "%Y%m%d").ok()?;
+    let hour = time.get(..2)?.parse().ok()?;
+    let minute = time.get(2..4)?.parse().ok()?;
+    date.and_hms_opt(hour, minute, 0)
+}
+
+#[test]
+fn test_parse() {
+    let text = "Latitude (decimal degrees):\t45.000\n\
+        Slope: 35 deg.\n\
+        \n\
+        time,G(i),H_sun,T2m,WS10m,Int\n\
+        20190101:0010,0.0,0.0,-2.1,1.2,0.0\n\
+        20190101:1210,512.3,21.5,3.4,2.0,0.0\n\
+        \n\
+        G(i): Global irradiance on the inclined plane (W/m2)\n";
+    let series = parse(text).unwrap();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[1], (NaiveDate::from_ymd_opt(2019, 1, 1).unwrap().and_hms_opt(12, 10, 0).unwrap(), 512.3));
+    assert!(parse("time,P\n").is_err())
+}
+
+#[test]
+fn test_url() {
+    let request = PvgisRequest {
+        latitude: 45.,
+        longitude: 7.5,
+        orientation: Orientation::new(35., 200.),
+        tracking: TrackingMode::Fixed,
+        year: 2019,
+    };
+    assert!(request.url().contains("lat=45&lon=7.5&angle=35&aspect=20&trackingtype=0&startyear=2019"))
+}
@@ -0,0 +1,93 @@
+use crate::SimState;
+use crate::summary::Summary;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterChange {
+    pub name: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricChange {
+    pub name: &'static str,
+    pub before: f32,
+    pub after: f32,
+}
+
+impl MetricChange {
+    pub fn change(&self) -> f32 {
+        self.after - self.before
+    }
+
+    pub fn percent_change(&self) -> Option<f32> {
+        if self.before == 0. {
+            None
+        } else {
+            Some(100. * self.change() / self.before.abs())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioDiff {
+    pub parameters: Vec<ParameterChange>, // only parameters that differ
+    pub metrics: Vec<MetricChange>, // every summary metric
+}
+
+// Compares two finished runs, e.g. the result of run_simulation before and
+// after adding storage
+pub fn diff(before: &SimState, after: &SimState) -> ScenarioDiff {
+    let parameters = before.parameters().into_iter()
+        .zip(after.parameters())
+        .filter(|((_, b), (_, a))| b != a)
+        .map(|((name, b), (_, a))| ParameterChange { name, before: b, after: a })
+        .collect();
+    let metrics = Summary::new(before).metrics().into_iter()
+        .zip(Summary::new(after).metrics())
+        .map(|((name, b), (_, a))| MetricChange { name, before: b, after: a })
+        .collect();
+    ScenarioDiff { parameters, metrics }
+}
+
+impl ScenarioDiff {
+    pub fn report(&self) -> String {
+        let mut lines = Vec::new();
+        if self.parameters.is_empty() {
+            lines.push("No parameters changed".to_string());
+        }
+        for p in &self.parameters {
+            lines.push(format!("{}: {} -> {}", p.name, p.before, p.after));
+        }
+        for m in &self.metrics {
+            let percent = match m.percent_change() {
+                Some(p) => format!(" ({:+.1}%)", p),
+                None => String::new(),
+            };
+            lines.push(format!("{}: {:.1} -> {:.1}{}", m.name, m.before, m.after, percent));
+        }
+        lines.join("\n")
+    }
+}
+
+#[test]
+fn test_diff_added_storage() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + chrono::Duration::days(4);
+    state.step_size = chrono::Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.load = 20.;
+    state.battery_capacity = 200.;
+    let before = crate::run_simulation(&state);
+    state.battery_capacity = 400.;
+    let after = crate::run_simulation(&state);
+
+    let diff = diff(&before, &after);
+    assert_eq!(diff.parameters, vec![ParameterChange {
+        name: "Battery Capacity [Wh]",
+        before: "200".to_string(),
+        after: "400".to_string(),
+    }]);
+    let unmet = diff.metrics.iter().find(|m| m.name == "Unmet Energy [Wh]").unwrap();
+    assert!(unmet.change() < 0.)
+}
@@ -0,0 +1,279 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+
+use crate::{SimState, run_simulation, simulation_end, simulation_start, step_mut, step_hours};
+use crate::load::Load;
+use crate::trace;
+
+// One-off job to fit into the run, e.g. 2 kWh of laundry over 2 hours
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Task {
+    pub energy: f32, // Wh
+    pub duration: Duration, // contiguous
+}
+
+impl Task {
+    pub fn new(energy: f32, duration: Duration) -> Task {
+        Task { energy, duration }
+    }
+
+    pub fn power(&self) -> f32 {
+        let hours = self.duration.num_seconds() as f32 / 3600.;
+        if hours > 0. { self.energy / hours } else { 0. }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recommendation {
+    pub start: NaiveDateTime,
+    pub added_unmet: f32, // Wh of other load left unpowered because of the task
+    pub unserved_task: f32, // Wh of the task itself the battery couldn't supply
+    pub minimum_charge: f32, // Wh, lowest charge until the battery recovers
+}
+
+// Tries the task at every step of the configured run and returns the best
+// start times, fewest blackouts first and then the shallowest discharge
+pub fn recommend(state: &SimState, task: Task, count: usize) -> Vec<Recommendation> {
+    trace::timed!("recommend", energy = task.energy, count);
+    let baseline = run_simulation(state);
+    let end = match baseline.history_dates.last() {
+        Some(last) => *last + baseline.step_size,
+        None => return Vec::new(),
+    };
+    // Replays the baseline so each trial starts from the system as it was
+    let mut steps = state.iter_steps();
+    let mut options = Vec::new();
+    for (i, start) in baseline.history_dates.iter().enumerate() {
+        if *start + task.duration <= end {
+            options.push(try_start(steps.state(), &baseline, i, task));
+        }
+        steps.next();
+    }
+    options.sort_by(|a, b| {
+        (a.added_unmet + a.unserved_task).total_cmp(&(b.added_unmet + b.unserved_task))
+            .then(b.minimum_charge.total_cmp(&a.minimum_charge))
+    });
+    options.truncate(count);
+    options
+}
+
+// Reruns from step i, given the baseline's system at that step, with the
+// task added, until the battery is back on the baseline's track
+fn try_start(at: &SimState, baseline: &SimState, i: usize, task: Task) -> Recommendation {
+    let start = baseline.history_dates[i];
+    let finish = start + task.duration;
+    let mut trial = at.clone();
+    trial.loads.push(Load::window("Task", task.power(), start, finish));
+
+    // Shortfalls beyond the baseline's, split at the end of the task
+    let (mut during_task, mut after_task) = (0., 0.);
+    let mut minimum_charge = trial.current_stored_energy;
+    for j in i..baseline.history_dates.len() {
+        if trial.now >= finish
+            && (trial.current_stored_energy - baseline.charge_history[j]).abs() < 0.01 {
+            break;
+        }
+        let now = trial.now;
+        step_mut(&mut trial);
+        let added = trial.unmet_history.last().unwrap() - baseline.unmet_history[j];
+        if now < finish {
+            during_task += added;
+        } else {
+            after_task += added;
+        }
+        minimum_charge = minimum_charge.min(trial.current_stored_energy);
+    }
+    // Shortfalls during the task are charged to the task first
+    let unserved_task = during_task.clamp(0., task.energy);
+    Recommendation {
+        start,
+        added_unmet: (during_task + after_task - unserved_task).max(0.),
+        unserved_task,
+        minimum_charge,
+    }
+}
+
+// Daily job that can run any time in a window, e.g. a dishwasher between
+// 8 am and 8 pm
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShiftableLoad {
+    pub name: String,
+    pub task: Task,
+    pub earliest: NaiveTime, // start
+    pub latest: NaiveTime, // finish, later the same day
+}
+
+impl ShiftableLoad {
+    pub fn new(name: &str, task: Task, earliest: NaiveTime, latest: NaiveTime) -> ShiftableLoad {
+        ShiftableLoad { name: name.to_string(), task, earliest, latest }
+    }
+
+    // The job as a deferrable load starting then, so what it was served
+    // shows in deferred_history
+    pub fn load(&self, start: NaiveDateTime) -> Load {
+        Load::window(&self.name, self.task.power(), start, start + self.task.duration).deferrable(0.)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShiftReport {
+    pub starts: Vec<(String, NaiveDateTime)>,
+    pub baseline: SimState, // every job at the start of its window
+    pub shifted: SimState,
+}
+
+impl ShiftReport {
+    // Change from the baseline, positive when shifting helped
+    pub fn unmet_reduction(&self) -> f32 {
+        unmet(&self.baseline) - unmet(&self.shifted)
+    }
+
+    pub fn self_consumption_gain(&self) -> f32 {
+        self_consumption(&self.shifted) - self_consumption(&self.baseline)
+    }
+}
+
+fn unmet(result: &SimState) -> f32 {
+    result.unmet_history.iter().sum()
+}
+
+// Share of the solar that was used rather than clipped or exported
+pub fn self_consumption(result: &SimState) -> f32 {
+    let solar = result.solar_history.iter().sum::<f32>() * step_hours(result);
+    if solar <= 0. {
+        return 0.;
+    }
+    let spilled = result.clipped_energy_history.iter().chain(&result.grid_export_history).sum::<f32>();
+    (1. - spilled / solar).max(0.)
+}
+
+// Places each job on each day of the run in the part of its window with the
+// most solar to spare, greedily in order, and reruns to compare with every
+// job starting as early as it can. A job whose window is too short for it
+// starts as early as it can in both.
+pub fn shift_loads(state: &SimState, jobs: &[ShiftableLoad]) -> ShiftReport {
+    trace::timed!("shift_loads", jobs = jobs.len());
+    let base = run_simulation(state);
+    let hours = step_hours(state);
+    let mut spare: Vec<f32> = base.solar_history.iter().zip(&base.served_load_history)
+        .map(|(solar, load)| (solar - load).max(0.) * hours)
+        .collect();
+    let mut earliest = state.clone();
+    let mut shifted = state.clone();
+    let mut starts = Vec::new();
+    let mut day = simulation_start(state).date();
+    while day < simulation_end(state).date() {
+        for job in jobs {
+            let (open, close) = (day.and_time(job.earliest), day.and_time(job.latest));
+            let candidates: Vec<usize> = (0..base.history_dates.len())
+                .filter(|i| base.history_dates[*i] >= open && base.history_dates[*i] + job.task.duration <= close)
+                .collect();
+            let steps = if hours > 0. { (job.task.duration.num_seconds() as f32 / 3600. / hours).ceil() as usize } else { 0 };
+            let covered = |i: usize, spare: &[f32]| spare[i..(i + steps).min(spare.len())].iter()
+                .map(|s| s.min(job.task.power() * hours))
+                .sum::<f32>();
+            let best = candidates.iter().cloned()
+                .reduce(|best, i| if covered(i, &spare) > covered(best, &spare) { i } else { best });
+            let start = match best {
+                Some(best) => {
+                    for s in spare[best..(best + steps).min(base.history_dates.len())].iter_mut() {
+                        *s = (*s - job.task.power() * hours).max(0.);
+                    }
+                    base.history_dates[best]
+                },
+                None => open,
+            };
+            earliest.loads.push(job.load(open));
+            shifted.loads.push(job.load(start));
+            starts.push((job.name.clone(), start));
+        }
+        day = day.succ_opt().unwrap();
+    }
+    ShiftReport {
+        starts,
+        baseline: run_simulation(&earliest),
+        shifted: run_simulation(&shifted),
+    }
+}
+
+#[test]
+fn test_recommend_daytime() {
+    use chrono::Timelike;
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 400.;
+    state.battery_capacity = 1500.;
+    state.load = 20.;
+    let best = recommend(&state, Task::new(2000., Duration::hours(2)), 3);
+    assert_eq!(best.len(), 3);
+    // With a small battery the laundry should run while the sun is up
+    for r in &best {
+        assert!((8..16).contains(&r.start.hour()));
+        assert_eq!(r.added_unmet + r.unserved_task, 0.);
+    }
+}
+
+#[test]
+fn test_recommend_blames_only_added_shortfall() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(1);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 1000.;
+    state.initial_soc = 1.;
+    state.load = 0.;
+    state.max_discharge_power = 100.;
+    // A spike the battery already can't keep up with in the first hour
+    let start = simulation_start(&state);
+    state.loads.push(Load::event("Kettle", 150., start, Duration::hours(1)));
+    let options = recommend(&state, Task::new(100., Duration::hours(2)), 24);
+    let first = options.iter().find(|r| r.start == start).unwrap();
+    // Half the task lands on the limited hour, the rest is served
+    assert!((first.unserved_task - 50.).abs() < 0.01);
+    assert_eq!(first.added_unmet, 0.)
+}
+
+#[test]
+fn test_shift_to_solar() {
+    use chrono::Timelike;
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 600.;
+    state.battery_capacity = 500.;
+    state.load = 20.;
+    let washer = ShiftableLoad::new(
+        "Washer",
+        Task::new(1000., Duration::hours(2)),
+        NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+    );
+    let report = shift_loads(&state, &[washer]);
+    assert_eq!(report.starts.len(), 3);
+    assert!(report.starts.iter().all(|(_, start)| (9..15).contains(&start.hour())));
+    assert!(report.unmet_reduction() > 0.);
+    assert!(report.self_consumption_gain() > 0.)
+}
+
+#[test]
+fn test_shift_without_room() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(2);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 600.;
+    state.battery_capacity = 500.;
+    state.load = 20.;
+    // Two hours of work in a one-hour window never fits
+    let washer = ShiftableLoad::new(
+        "Washer",
+        Task::new(1000., Duration::hours(2)),
+        NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+    );
+    let report = shift_loads(&state, &[washer]);
+    assert!(report.starts.iter().all(|(_, start)| start.time() == NaiveTime::from_hms_opt(6, 0, 0).unwrap()));
+    let served = |r: &SimState| r.deferred_history.iter().sum::<f32>();
+    assert_eq!(served(&report.baseline), 2000.);
+    assert_eq!(served(&report.shifted), served(&report.baseline));
+    assert_eq!(report.unmet_reduction(), 0.)
+}
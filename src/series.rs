@@ -0,0 +1,112 @@
+use std::io;
+use std::path::Path;
+use chrono::{Datelike, Duration, NaiveDateTime};
+
+const DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+// Reads `datetime,value` rows. A header row and blank lines are skipped.
+pub fn read_csv<P: AsRef<Path>>(path: P) -> io::Result<Vec<(NaiveDateTime, f32)>> {
+    parse_csv(&std::fs::read_to_string(path)?)
+}
+
+pub fn parse_csv(text: &str) -> io::Result<Vec<(NaiveDateTime, f32)>> {
+    let mut series = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(',').map(|f| f.trim());
+        let date = fields.next().and_then(parse_datetime);
+        let value = fields.next().and_then(|v| v.parse::<f32>().ok());
+        match (date, value) {
+            (Some(date), Some(value)) => series.push((date, value)),
+            _ if i == 0 => continue,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: expected datetime,value but found '{}'", i + 1, line))),
+        }
+    }
+    series.sort_by_key(|(date, _)| *date);
+    Ok(series)
+}
+
+pub fn parse_datetime(text: &str) -> Option<NaiveDateTime> {
+    DATE_FORMATS.iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+// Linear interpolation in a sorted series, None outside its range
+pub fn interpolate(series: &[(NaiveDateTime, f32)], time: NaiveDateTime) -> Option<f32> {
+    let after = series.partition_point(|(date, _)| *date < time);
+    if after >= series.len() {
+        return None;
+    }
+    let (t1, v1) = series[after];
+    if t1 == time {
+        return Some(v1);
+    }
+    if after == 0 {
+        return None;
+    }
+    let (t0, v0) = series[after - 1];
+    let fraction = (time - t0).num_seconds() as f32 / (t1 - t0).num_seconds() as f32;
+    Some(v0 + (v1 - v0) * fraction)
+}
+
+// Where to read a time outside the series from, so that a year of data
+// repeats every year. A leap day reads the day before in a common year.
+pub fn replay_time(series: &[(NaiveDateTime, f32)], time: NaiveDateTime) -> NaiveDateTime {
+    let (Some(first), Some(last)) = (series.first(), series.last()) else {
+        return time;
+    };
+    if time >= first.0 && time <= last.0 {
+        return time;
+    }
+    let year = first.0.year();
+    let replayed = time.with_year(year)
+        .unwrap_or_else(|| (time - Duration::days(1)).with_year(year).unwrap());
+    // Data starting mid-year continues into the next
+    if replayed < first.0 {
+        replayed.with_year(year + 1).unwrap_or(replayed)
+    } else {
+        replayed
+    }
+}
+
+// Interpolated as the same time of year when outside the series
+pub fn interpolate_yearly(series: &[(NaiveDateTime, f32)], time: NaiveDateTime) -> Option<f32> {
+    interpolate(series, replay_time(series, time))
+}
+
+#[test]
+fn test_parse_csv() {
+    let series = parse_csv("time,soc\n2023-01-01 01:00,20\n\n2023-01-01T00:00:00,10\n").unwrap();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[0].1, 10.);
+    assert!(parse_csv("time,soc\nyesterday,3\n").is_err())
+}
+
+#[test]
+fn test_interpolate() {
+    let series = parse_csv("2023-01-01 00:00,10\n2023-01-01 01:00,20\n").unwrap();
+    let half_past = parse_datetime("2023-01-01 00:30").unwrap();
+    assert_eq!(interpolate(&series, half_past), Some(15.));
+    assert_eq!(interpolate(&series, series[1].0), Some(20.));
+    assert_eq!(interpolate(&series, parse_datetime("2023-01-01 02:00").unwrap()), None)
+}
+
+#[test]
+fn test_replay_years() {
+    let series = parse_csv("2023-02-28 00:00,10\n2023-03-01 00:00,20\n").unwrap();
+    let later = parse_datetime("2025-02-28 12:00").unwrap();
+    assert_eq!(interpolate_yearly(&series, later), Some(15.));
+    // 2024 is a leap year, and its leap day reads February 28
+    let leap_day = parse_datetime("2024-02-29 00:00").unwrap();
+    assert_eq!(replay_time(&series, leap_day), parse_datetime("2023-02-28 00:00").unwrap())
+}
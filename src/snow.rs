@@ -0,0 +1,93 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::trace;
+
+// Snowfall that buries the array, sliding off over the following days. Steep
+// panels shed snow within a day, flat ones can stay covered for a week.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnowModel {
+    pub snowfall_days: [f32; 12], // expected days with snowfall in each month
+    pub flat_clearing_days: f32, // mean days for snow to clear off a flat panel
+    pub seed: u64,
+}
+
+// One snowfall and how long the array took to clear
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnowCover {
+    pub start: NaiveDateTime,
+    pub clearing: Duration,
+}
+
+impl SnowCover {
+    // Fraction of the array buried, all of it at first and shrinking as the
+    // snow slides off
+    pub fn covered(&self, now: NaiveDateTime) -> f32 {
+        if now < self.start || now >= self.start + self.clearing {
+            return 0.;
+        }
+        let elapsed = (now - self.start).num_seconds() as f32;
+        1. - elapsed / self.clearing.num_seconds() as f32
+    }
+}
+
+impl SnowModel {
+    pub fn new(snowfall_days: [f32; 12], flat_clearing_days: f32, seed: u64) -> SnowModel {
+        SnowModel { snowfall_days, flat_clearing_days, seed }
+    }
+
+    // Snowy northern winters from November to March
+    pub fn northern_winter(seed: u64) -> SnowModel {
+        SnowModel::new([6., 5., 4., 1., 0., 0., 0., 0., 0., 1., 3., 6.], 6., seed)
+    }
+
+    // Mean days covered after a snowfall for a panel at the tilt
+    pub fn clearing_days(&self, tilt: f32) -> f32 {
+        self.flat_clearing_days * tilt.clamp(0., 90.).to_radians().cos()
+    }
+
+    pub fn generate(&self, start: NaiveDate, end: NaiveDate, tilt: f32) -> Vec<SnowCover> {
+        trace::timed!("generate_snow", seed = self.seed, %start, %end);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mean = self.clearing_days(tilt);
+        let mut events = Vec::new();
+        let mut date = start;
+        while date <= end {
+            // Average month length is close enough for a rate
+            let chance = self.snowfall_days[date.month0() as usize] / 30.44;
+            if rng.gen::<f32>() < chance {
+                let days = mean * rng.gen_range(0.5..1.5);
+                events.push(SnowCover {
+                    start: date.and_hms_opt(0, 0, 0).unwrap(),
+                    clearing: Duration::seconds((days * 24. * 3600.) as i64),
+                });
+            }
+            date = date.succ_opt().unwrap();
+        }
+        events
+    }
+}
+
+// Fraction of the array's output lost to snow, from the deepest cover
+pub fn snow_loss(events: &[SnowCover], now: NaiveDateTime) -> f32 {
+    events.iter().map(|e| e.covered(now)).fold(0., f32::max)
+}
+
+#[test]
+fn test_steep_panels_shed_snow() {
+    let model = SnowModel::northern_winter(3);
+    let start = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+    let end = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+    let flat = model.generate(start, end, 0.);
+    let steep = model.generate(start, end, 60.);
+    // The same snowfalls, as the draws don't depend on tilt
+    assert_eq!(flat.len(), steep.len());
+    assert!(flat.len() > 20 && flat.len() < 45);
+    assert!(flat.iter().all(|e| (1..=3).contains(&e.start.month()) || e.start.month() >= 10));
+    let buried_days = |events: &[SnowCover]| (0..365 * 24)
+        .map(|h| snow_loss(events, start.and_hms_opt(0, 0, 0).unwrap() + Duration::hours(h)))
+        .sum::<f32>() / 24.;
+    assert!(buried_days(&flat) > 1.4 * buried_days(&steep));
+    assert_eq!(model.clearing_days(90.).round(), 0.)
+}
@@ -0,0 +1,138 @@
+use crate::{SimState, state_of_health, step_hours};
+
+// Totals over a finished run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub solar_energy: f32, // Wh produced at the array
+    pub wind_energy: f32, // Wh produced by the turbine
+    pub hydro_energy: f32, // Wh
+    pub alternator_energy: f32, // Wh charged while driving
+    pub served_energy: f32, // Wh of load powered
+    pub unmet_energy: f32, // Wh
+    pub unmet_hours: f32,
+    pub wiring_loss: f32, // Wh
+    pub inverter_consumption: f32, // Wh
+    pub parasitic_consumption: f32, // Wh drawn by the equipment itself
+    pub minimum_charge: f32, // Wh
+    pub average_charge: f32, // Wh
+    pub pumped: f32, // liters
+    pub deferred_served: f32, // Wh run by deferrable loads
+    pub standby_per_year: f32, // Wh of phantom draw in a year
+    pub standby_share: f32, // fraction of the load energy that was phantom draw
+    pub array_downtime: f32, // hours, a partly failed array counting its offline share
+    pub grid_import: f32, // Wh
+    pub grid_export: f32, // Wh
+    pub generator_energy: f32, // Wh
+    pub generator_hours: f32,
+    pub generator_starts: f32,
+    pub fuel_used: f32, // liters
+    pub fuel_cost: f32, // at the generator's fuel price
+    pub clipped_energy: f32, // Wh of surplus wasted
+    pub clipped_per_year: f32, // Wh, the clipped energy scaled to a year
+    pub dump_energy: f32, // Wh of surplus diverted to the dump load
+    pub discharge_limited_hours: f32, // hours the load outran the discharge power limit
+    pub load_cuts: f32, // times the loads were cut
+    pub equivalent_cycles: f32,
+    pub state_of_health: f32, // fraction of the rated capacity left at the end
+    pub replacements: f32, // new batteries fitted
+}
+
+impl Summary {
+    pub fn new(state: &SimState) -> Summary {
+        let hours = step_hours(state);
+        let steps = state.charge_history.len().max(1) as f32;
+        Summary {
+            solar_energy: state.solar_history.iter().sum::<f32>() * hours,
+            wind_energy: state.wind_history.iter().sum::<f32>() * hours,
+            hydro_energy: state.hydro_history.iter().sum::<f32>() * hours,
+            alternator_energy: state.alternator_history.iter().sum::<f32>() * hours,
+            served_energy: state.served_load_history.iter().sum::<f32>() * hours
+                - state.unmet_history.iter().sum::<f32>(),
+            unmet_energy: state.unmet_history.iter().sum(),
+            unmet_hours: state.unmet_history.iter().filter(|u| **u > 0.).count() as f32 * hours,
+            wiring_loss: state.wiring_loss_history.iter().sum(),
+            inverter_consumption: state.inverter_history.iter().sum(),
+            parasitic_consumption: state.parasitic_history.iter().sum(),
+            minimum_charge: state.charge_history.iter().cloned().reduce(f32::min).unwrap_or(0.),
+            average_charge: state.charge_history.iter().sum::<f32>() / steps,
+            pumped: state.pumped_history.iter().sum(),
+            deferred_served: state.deferred_history.iter().sum(),
+            standby_per_year: state.standby_load * 8760.,
+            standby_share: {
+                let load_energy = state.served_load_history.iter().sum::<f32>() * hours;
+                if load_energy > 0. { state.standby_load * steps * hours / load_energy } else { 0. }
+            },
+            array_downtime: state.array_online_history.iter().map(|a| 1. - a).sum::<f32>() * hours,
+            grid_import: state.grid_import_history.iter().sum(),
+            grid_export: state.grid_export_history.iter().sum(),
+            generator_energy: state.generator_history.iter().sum(),
+            generator_hours: state.fuel_history.iter().filter(|f| **f > 0.).count() as f32 * hours,
+            generator_starts: state.generator_starts.len() as f32,
+            fuel_used: state.fuel_history.iter().sum(),
+            fuel_cost: state.fuel_history.iter().sum::<f32>() * state.generator.map_or(0., |g| g.fuel_price),
+            clipped_energy: state.clipped_energy_history.iter().sum(),
+            clipped_per_year: if hours > 0. {
+                state.clipped_energy_history.iter().sum::<f32>() * 8760. / (steps * hours)
+            } else {
+                0.
+            },
+            dump_energy: state.dump_history.iter().sum(),
+            discharge_limited_hours: state.discharge_limited_history.iter().filter(|l| **l > 0.).count() as f32 * hours,
+            load_cuts: state.load_cuts.len() as f32,
+            equivalent_cycles: state.cycles,
+            state_of_health: state_of_health(state),
+            replacements: state.replacements.len() as f32,
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<(&'static str, f32)> {
+        vec![
+            ("Solar Energy [Wh]", self.solar_energy),
+            ("Wind Energy [Wh]", self.wind_energy),
+            ("Hydro Energy [Wh]", self.hydro_energy),
+            ("Alternator Energy [Wh]", self.alternator_energy),
+            ("Served Energy [Wh]", self.served_energy),
+            ("Unmet Energy [Wh]", self.unmet_energy),
+            ("Unmet Hours", self.unmet_hours),
+            ("Wiring Loss [Wh]", self.wiring_loss),
+            ("Inverter Consumption [Wh]", self.inverter_consumption),
+            ("Parasitic Consumption [Wh]", self.parasitic_consumption),
+            ("Minimum Charge [Wh]", self.minimum_charge),
+            ("Average Charge [Wh]", self.average_charge),
+            ("Pumped [L]", self.pumped),
+            ("Deferred Served [Wh]", self.deferred_served),
+            ("Standby Energy [Wh/yr]", self.standby_per_year),
+            ("Standby Share", self.standby_share),
+            ("Array Downtime [h]", self.array_downtime),
+            ("Grid Import [Wh]", self.grid_import),
+            ("Grid Export [Wh]", self.grid_export),
+            ("Generator Energy [Wh]", self.generator_energy),
+            ("Generator Runtime [h]", self.generator_hours),
+            ("Generator Starts", self.generator_starts),
+            ("Fuel Used [L]", self.fuel_used),
+            ("Fuel Cost", self.fuel_cost),
+            ("Clipped Energy [Wh]", self.clipped_energy),
+            ("Clipped Energy [Wh/yr]", self.clipped_per_year),
+            ("Dump Load Energy [Wh]", self.dump_energy),
+            ("Discharge Limited [h]", self.discharge_limited_hours),
+            ("Load Cuts", self.load_cuts),
+            ("Equivalent Cycles", self.equivalent_cycles),
+            ("State of Health", self.state_of_health),
+            ("Battery Replacements", self.replacements),
+        ]
+    }
+}
+
+#[test]
+fn test_summary() {
+    let mut state = SimState::new();
+    state.step_size = chrono::Duration::hours(1);
+    state.charge_history = vec![10., 0., 20.];
+    state.served_load_history = vec![5., 5., 5.];
+    state.unmet_history = vec![0., 3., 0.];
+    let summary = Summary::new(&state);
+    assert_eq!(summary.served_energy, 12.);
+    assert_eq!(summary.unmet_hours, 1.);
+    assert_eq!(summary.minimum_charge, 0.);
+    assert_eq!(summary.average_charge, 10.)
+}
@@ -0,0 +1,232 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use std::f64::consts::PI;
+
+use crate::time_hours;
+
+// Fixed panel orientation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    pub tilt: f32, // degrees from horizontal
+    pub azimuth: f32, // degrees, compass bearing the panel faces (180 = south)
+}
+
+impl Orientation {
+    pub fn new(tilt: f32, azimuth: f32) -> Orientation {
+        Orientation { tilt, azimuth }
+    }
+
+    // Tilted toward the equator at the site's latitude
+    pub fn equator_facing(lat: f32) -> Orientation {
+        Orientation::new(lat.abs(), if lat >= 0. { 180. } else { 0. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrackingMode {
+    #[default]
+    Fixed, // the panel keeps its orientation
+    SingleAxis, // turns about an axis along the orientation's tilt and azimuth
+    DualAxis, // always faces the sun
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarPosition {
+    pub declination: f32, // radians
+    pub hour_angle: f32, // radians, negative in the morning
+    pub elevation: f32, // degrees above the horizon, without refraction
+    pub azimuth: f32, // degrees, compass bearing of the sun (180 = south)
+}
+
+impl SolarPosition {
+    pub fn cos_zenith(&self) -> f32 {
+        self.elevation.to_radians().sin()
+    }
+
+    // Unit vector toward the sun as east, north, up
+    pub fn vector(&self) -> [f32; 3] {
+        let (elevation, azimuth) = (self.elevation.to_radians(), self.azimuth.to_radians());
+        [
+            elevation.cos() * azimuth.sin(),
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+        ]
+    }
+}
+
+// The PSA algorithm (Blanco-Muriel et al., 2001), good to about 0.01 degrees
+// this century. Takes the time in UTC and longitude in degrees east.
+pub fn solar_position(utc: NaiveDateTime, lat: f32, lon: f32) -> SolarPosition {
+    let j2000 = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let n = (utc - j2000).num_seconds() as f64 / 86400.;
+    let hours = time_hours(utc.time()) as f64;
+
+    // Ecliptic coordinates
+    let omega = 2.1429 - 0.0010394594 * n;
+    let mean_longitude = 4.8950630 + 0.017202791698 * n;
+    let mean_anomaly = 6.2400600 + 0.0172019699 * n;
+    let ecliptic_longitude = mean_longitude
+        + 0.03341607 * mean_anomaly.sin()
+        + 0.00034894 * (2. * mean_anomaly).sin()
+        - 0.0001134
+        - 0.0000203 * omega.sin();
+    let obliquity = 0.4090928 - 6.2140e-9 * n + 0.0000396 * omega.cos();
+
+    // Celestial coordinates
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin())
+        .atan2(ecliptic_longitude.cos())
+        .rem_euclid(2. * PI);
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    // Local coordinates
+    let sidereal = 6.6974243242 + 0.0657098283 * n + hours;
+    let local_sidereal = (sidereal * 15. + lon as f64).to_radians();
+    let hour_angle = (local_sidereal - right_ascension + PI).rem_euclid(2. * PI) - PI;
+    let phi = (lat as f64).to_radians();
+    let cos_zenith = phi.cos() * hour_angle.cos() * declination.cos() + declination.sin() * phi.sin();
+    let mut zenith = cos_zenith.clamp(-1., 1.).acos();
+    let azimuth = (-hour_angle.sin())
+        .atan2(declination.tan() * phi.cos() - phi.sin() * hour_angle.cos())
+        .rem_euclid(2. * PI);
+    // Parallax of the sun seen from the Earth's surface
+    zenith += 6371.01 / 149597890. * zenith.sin();
+
+    SolarPosition {
+        declination: declination as f32,
+        hour_angle: hour_angle as f32,
+        elevation: 90. - zenith.to_degrees() as f32,
+        azimuth: azimuth.to_degrees() as f32,
+    }
+}
+
+// Hours to add to the clock for apparent solar time, where the sun crosses
+// the meridian at 12:00. Combines the site's distance from its time zone's
+// meridian with the equation of time.
+pub fn solar_time_offset(clock: NaiveDateTime, lon: f32, utc_offset: f32) -> f32 {
+    let utc = clock - Duration::seconds((utc_offset * 3600.) as i64);
+    let solar_hours = 12. + solar_position(utc, 0., lon).hour_angle.to_degrees() / 15.;
+    (solar_hours - time_hours(clock.time()) + 12.).rem_euclid(24.) - 12.
+}
+
+// Cosine of the angle between the sun and the panel normal
+pub fn cos_incidence(position: SolarPosition, orientation: Orientation) -> f32 {
+    let (tilt, azimuth) = (orientation.tilt.to_radians(), orientation.azimuth.to_radians());
+    let normal = [tilt.sin() * azimuth.sin(), tilt.sin() * azimuth.cos(), tilt.cos()];
+    normal.iter().zip(position.vector()).map(|(n, s)| n * s).sum()
+}
+
+// The axis lies in the plane of a panel with the given orientation, rising
+// up its slope, so an equator-facing tilt at the latitude gives a polar axis.
+// Rotating about it the tracker can bring its normal within the angle
+// between the sun and the plane perpendicular to the axis.
+pub fn single_axis_cos_incidence(position: SolarPosition, axis: Orientation) -> f32 {
+    let (tilt, azimuth) = (axis.tilt.to_radians(), axis.azimuth.to_radians());
+    let axis = [-tilt.cos() * azimuth.sin(), -tilt.cos() * azimuth.cos(), tilt.sin()];
+    let along: f32 = axis.iter().zip(position.vector()).map(|(a, s)| a * s).sum();
+    (1. - along * along).max(0.).sqrt()
+}
+
+// ASHRAE incidence angle modifier coefficient for glass-fronted modules
+pub const IAM_B0: f32 = 0.05;
+
+// Share of the light reaching the cells after extra reflection off the
+// glass at oblique angles, 1 at normal incidence
+pub fn incidence_modifier(cos_incidence: f32) -> f32 {
+    if cos_incidence <= 0. {
+        0.
+    } else {
+        (1. - IAM_B0 * (1. / cos_incidence - 1.)).max(0.)
+    }
+}
+
+// Fraction of the sun-facing output a fixed panel receives, including
+// reflection losses
+pub fn orientation_factor(position: SolarPosition, orientation: Orientation) -> f32 {
+    if position.cos_zenith() <= 0. {
+        0.
+    } else {
+        let cos_incidence = cos_incidence(position, orientation).max(0.);
+        cos_incidence * incidence_modifier(cos_incidence)
+    }
+}
+
+pub fn tracking_factor(position: SolarPosition, orientation: Orientation, tracking: TrackingMode) -> f32 {
+    match tracking {
+        TrackingMode::Fixed => orientation_factor(position, orientation),
+        TrackingMode::SingleAxis if position.cos_zenith() > 0. => {
+            let cos_incidence = single_axis_cos_incidence(position, orientation);
+            cos_incidence * incidence_modifier(cos_incidence)
+        },
+        TrackingMode::SingleAxis => 0.,
+        TrackingMode::DualAxis => if position.cos_zenith() > 0. { 1. } else { 0. },
+    }
+}
+
+#[test]
+fn test_single_axis_tracking() {
+    // A horizontal north-south axis faces the sun exactly at the equinox
+    let morning = NaiveDate::from_ymd_opt(2023, 3, 21).unwrap().and_hms_opt(9, 0, 0).unwrap();
+    let sun = solar_position(morning, 0., 0.);
+    let axis = Orientation::new(0., 180.);
+    assert!((single_axis_cos_incidence(sun, axis) - 1.).abs() < 0.02);
+    // and beats a flat panel through the morning
+    assert!(single_axis_cos_incidence(sun, axis) > cos_incidence(sun, axis) + 0.2);
+    let midnight = solar_position(morning.date().and_hms_opt(0, 0, 0).unwrap(), 0., 0.);
+    assert_eq!(tracking_factor(midnight, axis, TrackingMode::DualAxis), 0.)
+}
+
+#[test]
+fn test_incidence_modifier() {
+    assert_eq!(incidence_modifier(1.), 1.);
+    // 60 degrees off normal loses 5% more than the cosine alone
+    assert!((incidence_modifier(0.5) - 0.95).abs() < 0.0001);
+    // Past about 87 degrees the glass reflects everything
+    assert_eq!(incidence_modifier(0.04), 0.)
+}
+
+#[test]
+fn test_flat_panel_matches_zenith() {
+    let noon = NaiveDate::from_ymd_opt(2023, 6, 21).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let sun = solar_position(noon, 40., 0.);
+    let flat = Orientation::new(0., 180.);
+    assert!((cos_incidence(sun, flat) - sun.cos_zenith()).abs() < 0.0001);
+    // Sun is about 73.5 degrees high at the solstice
+    assert!((sun.cos_zenith() - 16.5_f32.to_radians().cos()).abs() < 0.01)
+}
+
+#[test]
+fn test_equator_facing_noon_equinox() {
+    // At the equinox a panel tilted at the latitude faces the noon sun directly
+    let noon = NaiveDate::from_ymd_opt(2023, 3, 21).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    let sun = solar_position(noon, 45., 0.);
+    let factor = orientation_factor(sun, Orientation::equator_facing(45.));
+    assert!((factor - 1.).abs() < 0.01);
+    let north = orientation_factor(sun, Orientation::new(45., 0.));
+    assert!(north < 0.01)
+}
+
+#[test]
+fn test_solar_position() {
+    // Golden, Colorado, from the NREL SPA reference: 2003-10-17 12:30:30
+    // local time, 7 hours behind UTC
+    let utc = NaiveDate::from_ymd_opt(2003, 10, 17).unwrap().and_hms_opt(19, 30, 30).unwrap();
+    let sun = solar_position(utc, 39.742476, -105.1786);
+    assert!((90. - sun.elevation - 50.111).abs() < 0.05);
+    assert!((sun.azimuth - 194.340).abs() < 0.05);
+    assert!((sun.declination.to_degrees() + 9.316).abs() < 0.05);
+    // Southern winter noon sun stands in the north
+    let sydney = solar_position(NaiveDate::from_ymd_opt(2023, 6, 21).unwrap().and_hms_opt(2, 0, 0).unwrap(), -33.9, 151.2);
+    assert!(sydney.azimuth < 10. || sydney.azimuth > 350.);
+    assert!((sydney.elevation - (90. - 33.9 - 23.44)).abs() < 0.5)
+}
+
+#[test]
+fn test_solar_time_offset() {
+    // Early November the sun is about 16 minutes fast, mid February 14 slow
+    let november = NaiveDate::from_ymd_opt(2023, 11, 3).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    assert!((solar_time_offset(november, 0., 0.) * 60. - 16.4).abs() < 0.5);
+    let february = NaiveDate::from_ymd_opt(2023, 2, 14).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    assert!((solar_time_offset(february, 0., 0.) * 60. + 14.2).abs() < 0.5);
+    // Madrid keeps Central European time though it lies west of Greenwich
+    let madrid = solar_time_offset(february, -3.7, 1.);
+    assert!(madrid < -1.4 && madrid > -1.5)
+}
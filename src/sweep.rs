@@ -0,0 +1,111 @@
+use rayon::prelude::*;
+
+use crate::{SimState, run_simulation};
+use crate::summary::Summary;
+use crate::trace;
+
+// A setting a sweep varies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    BatteryCapacity, // Wh
+    SolarPower, // W nominal
+    Load, // W
+    InitialSoc, // fraction
+    MinimumSoc, // fraction
+}
+
+impl Parameter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::BatteryCapacity => "Battery Capacity [Wh]",
+            Parameter::SolarPower => "Solar Power Nominal [W]",
+            Parameter::Load => "Load [W]",
+            Parameter::InitialSoc => "Initial SOC",
+            Parameter::MinimumSoc => "Minimum SOC",
+        }
+    }
+
+    pub fn apply(&self, state: &mut SimState, value: f32) {
+        match self {
+            Parameter::BatteryCapacity => state.battery_capacity = value,
+            Parameter::SolarPower => state.solar_nominal_output = value,
+            Parameter::Load => state.load = value,
+            Parameter::InitialSoc => state.initial_soc = value,
+            Parameter::MinimumSoc => state.minimum_soc = value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    pub values: Vec<f32>, // one for each axis, in order
+    pub summary: Summary,
+}
+
+// Runs every combination of the axes' values, spread across the CPU's
+// cores, e.g. battery capacity by solar wattage for a sizing study. Rows
+// come back in order with the last axis changing fastest.
+pub fn sweep(state: &SimState, axes: &[(Parameter, Vec<f32>)]) -> Vec<SweepRow> {
+    trace::timed!("sweep", axes = axes.len());
+    let combinations = axes.iter().fold(vec![Vec::new()], |combinations, (_, values)| {
+        combinations.iter()
+            .flat_map(|combination| values.iter().map(move |value| {
+                let mut combination: Vec<f32> = combination.clone();
+                combination.push(*value);
+                combination
+            }))
+            .collect()
+    });
+    combinations.into_par_iter()
+        .map(|values| {
+            let mut trial = state.clone();
+            for ((parameter, _), value) in axes.iter().zip(&values) {
+                parameter.apply(&mut trial, *value);
+            }
+            SweepRow { summary: Summary::new(&run_simulation(&trial)), values }
+        })
+        .collect()
+}
+
+// CSV of the sweep, a column for each axis and then each summary metric
+pub fn table(axes: &[(Parameter, Vec<f32>)], rows: &[SweepRow]) -> String {
+    let metrics = rows.first().map_or(Vec::new(), |row| row.summary.metrics());
+    let header: Vec<&str> = axes.iter().map(|(parameter, _)| parameter.name())
+        .chain(metrics.iter().map(|(name, _)| *name))
+        .collect();
+    let mut table = header.join(",") + "\n";
+    for row in rows {
+        let fields: Vec<String> = row.values.iter().cloned()
+            .chain(row.summary.metrics().into_iter().map(|(_, value)| value))
+            .map(|value| value.to_string())
+            .collect();
+        table += &(fields.join(",") + "\n");
+    }
+    table
+}
+
+#[test]
+fn test_sizing_sweep() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + chrono::Duration::days(4);
+    state.step_size = chrono::Duration::hours(1);
+    state.load = 20.;
+    let axes = [
+        (Parameter::BatteryCapacity, vec![200., 2000.]),
+        (Parameter::SolarPower, vec![50., 100., 200.]),
+    ];
+    let rows = sweep(&state, &axes);
+    assert_eq!(rows.len(), 6);
+    assert_eq!(rows[1].values, vec![200., 100.]);
+    // Matches a serial run of the same settings
+    let mut single = state.clone();
+    single.battery_capacity = 2000.;
+    single.solar_nominal_output = 50.;
+    assert_eq!(rows[3].summary, Summary::new(&run_simulation(&single)));
+    // More battery or more solar never leaves more load unmet
+    assert!(rows[5].summary.unmet_energy <= rows[2].summary.unmet_energy);
+    assert!(rows[2].summary.unmet_energy <= rows[0].summary.unmet_energy);
+    let csv = table(&axes, &rows);
+    assert_eq!(csv.lines().count(), 7);
+    assert!(csv.starts_with("Battery Capacity [Wh],Solar Power Nominal [W],Solar Energy [Wh]"))
+}
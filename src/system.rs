@@ -0,0 +1,198 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::{SimState, probe, simulation_end, start_run, step_with};
+
+// Something that puts energy into the system, in Wh over a step
+pub trait PowerSource {
+    fn name(&self) -> &str;
+    fn energy(&mut self, now: NaiveDateTime, step: Duration) -> f32;
+}
+
+// Something that takes energy out of the system, in Wh over a step
+pub trait PowerSink {
+    fn name(&self) -> &str;
+    fn energy(&mut self, now: NaiveDateTime, step: Duration) -> f32;
+}
+
+pub trait Storage {
+    fn name(&self) -> &str;
+    fn stored(&self) -> f32; // Wh
+    fn capacity(&self) -> f32; // Wh
+    // Adds (or with a negative value, removes) energy, returning what didn't
+    // fit: positive for spilled surplus, negative for unmet demand
+    fn apply(&mut self, energy: f32) -> f32;
+}
+
+// An ideal store clamped to [0, capacity], e.g. a hot water tank
+#[derive(Debug, Clone, PartialEq)]
+pub struct Battery {
+    pub capacity: f32, // Wh
+    pub stored: f32, // Wh
+}
+
+impl Battery {
+    pub fn new(capacity: f32) -> Battery {
+        Battery { capacity, stored: 0. }
+    }
+}
+
+impl Storage for Battery {
+    fn name(&self) -> &str {
+        "Battery"
+    }
+
+    fn stored(&self) -> f32 {
+        self.stored
+    }
+
+    fn capacity(&self) -> f32 {
+        self.capacity
+    }
+
+    fn apply(&mut self, energy: f32) -> f32 {
+        let unbounded = self.stored + energy;
+        self.stored = unbounded.clamp(0., self.capacity.max(0.));
+        unbounded - self.stored
+    }
+}
+
+// The configured model, with its array, loads, and battery, as the default
+// components, extended by added sources, sinks, and storage. Added sources
+// and sinks feed the model's own step at the bus. Added storage is offered
+// the surplus the model would waste and asked for the load it would leave
+// unmet, at the bus too, before the step decides on any cuts.
+pub struct System {
+    pub model: SimState, // positioned in the run, with its histories so far
+    pub sources: Vec<Box<dyn PowerSource>>,
+    pub sinks: Vec<Box<dyn PowerSink>>,
+    pub storage: Vec<Box<dyn Storage>>,
+}
+
+impl System {
+    pub fn new(state: &SimState) -> System {
+        System {
+            model: start_run(state),
+            sources: Vec::new(),
+            sinks: Vec::new(),
+            storage: Vec::new(),
+        }
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn PowerSource>) {
+        self.sources.push(source);
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn PowerSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn add_storage(&mut self, storage: Box<dyn Storage>) {
+        self.storage.push(storage);
+    }
+
+    pub fn step(&mut self) {
+        let (now, step) = (self.model.now, self.model.step_size);
+        let mut external = self.sources.iter_mut().map(|s| s.energy(now, step)).sum::<f32>()
+            - self.sinks.iter_mut().map(|s| s.energy(now, step)).sum::<f32>();
+        if !self.storage.is_empty() {
+            let mut trial = probe(&mut self.model);
+            step_with(&mut trial, external);
+            let mut wasted = trial.clipped_energy_history.last().cloned().unwrap_or(0.);
+            let mut unmet = trial.unmet_history.last().cloned().unwrap_or(0.);
+            for storage in self.storage.iter_mut() {
+                if wasted > 0. {
+                    let left = storage.apply(wasted).max(0.);
+                    external -= wasted - left;
+                    wasted = left;
+                }
+                if unmet > 0. {
+                    let left = (-storage.apply(-unmet)).max(0.);
+                    external += unmet - left;
+                    unmet = left;
+                }
+            }
+        }
+        step_with(&mut self.model, external);
+    }
+
+    pub fn run_until(&mut self, end: NaiveDateTime) {
+        while self.model.now < end {
+            self.step();
+        }
+    }
+}
+
+// Runs the configuration's default components to its end day
+pub fn run_system(state: &SimState) -> System {
+    let mut system = System::new(state);
+    system.run_until(simulation_end(state));
+    system
+}
+
+#[test]
+fn test_default_system_matches_simulation() {
+    let mut state = SimState::new();
+    state.end_date = state.start_date + Duration::days(3);
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 100.;
+    state.load = 10.;
+    state.battery_capacity = 300.;
+    state.initial_soc = 0.5;
+    state.charge_efficiency = 0.9;
+    let expected = crate::run_simulation(&state);
+    let system = run_system(&state).model;
+    assert_eq!(system.history_dates, expected.history_dates);
+    assert_eq!(system.charge_history, expected.charge_history);
+    assert_eq!(system.solar_history, expected.solar_history);
+    assert_eq!(system.unmet_history, expected.unmet_history);
+    assert_eq!(system.clipped_energy_history, expected.clipped_energy_history);
+    assert_eq!(system.current_stored_energy, expected.current_stored_energy)
+}
+
+#[test]
+fn test_custom_components() {
+    struct Generator;
+    impl PowerSource for Generator {
+        fn name(&self) -> &str {
+            "Generator"
+        }
+        fn energy(&mut self, _now: NaiveDateTime, step: Duration) -> f32 {
+            50. * step.num_minutes() as f32 / 60.
+        }
+    }
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.battery_capacity = 120.;
+    state.solar_nominal_output = 0.;
+    let mut system = System::new(&state);
+    system.add_source(Box::new(Generator));
+    let start = system.model.now;
+    system.run_until(start + Duration::hours(3));
+    assert_eq!(system.model.current_stored_energy, 120.);
+    assert_eq!(system.model.clipped_energy_history, vec![0., 0., 30.]);
+    // A tank takes the surplus the battery can't
+    let mut system = System::new(&state);
+    system.add_source(Box::new(Generator));
+    system.add_storage(Box::new(Battery::new(1000.)));
+    system.run_until(start + Duration::hours(4));
+    assert_eq!(system.model.clipped_energy_history, vec![0., 0., 0., 0.]);
+    assert_eq!(system.storage[0].stored(), 80.)
+}
+
+#[test]
+fn test_storage_covers_before_cuts() {
+    let mut state = SimState::new();
+    state.step_size = Duration::hours(1);
+    state.solar_nominal_output = 0.;
+    state.battery_capacity = 10.;
+    state.load = 10.;
+    let mut system = System::new(&state);
+    system.add_storage(Box::new(Battery { capacity: 1000., stored: 1000. }));
+    let start = system.model.now;
+    system.run_until(start + Duration::hours(6));
+    // The tank carries the loads once the battery is flat, so nothing is cut
+    assert!(system.model.unmet_history.iter().all(|u| *u == 0.));
+    assert!(system.model.load_cuts.is_empty());
+    assert!(system.model.served_load_history.iter().all(|l| *l == 10.));
+    assert!((system.storage[0].stored() - 940.).abs() < 0.01)
+}
@@ -0,0 +1,122 @@
+use chrono::{Datelike, NaiveDateTime, Timelike, Weekday};
+
+use crate::SimState;
+
+// Hours of the day billed at their own rate, e.g. a 4 pm to 9 pm peak
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TouPeriod {
+    pub start_hour: u32, // 0 to 23
+    pub end_hour: u32, // exclusive, wrapping past midnight when before start_hour
+    pub rate: f32, // per kWh imported
+    pub weekdays_only: bool,
+}
+
+impl TouPeriod {
+    pub fn new(start_hour: u32, end_hour: u32, rate: f32) -> TouPeriod {
+        TouPeriod { start_hour, end_hour, rate, weekdays_only: false }
+    }
+
+    pub fn weekdays(mut self) -> TouPeriod {
+        self.weekdays_only = true;
+        self
+    }
+
+    pub fn covers(&self, now: NaiveDateTime) -> bool {
+        if self.weekdays_only && matches!(now.weekday(), Weekday::Sat | Weekday::Sun) {
+            return false;
+        }
+        let hour = now.hour();
+        if self.start_hour <= self.end_hour {
+            self.start_hour <= hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+// Prices in any currency per kWh: a flat import rate unless a time-of-use
+// period covers the hour, and a feed-in rate paid for exports
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tariff {
+    pub import_rate: f32,
+    pub periods: Vec<TouPeriod>, // the first one covering an hour sets its rate
+    pub feed_in_rate: f32,
+}
+
+impl Tariff {
+    pub fn flat(import_rate: f32, feed_in_rate: f32) -> Tariff {
+        Tariff { import_rate, periods: Vec::new(), feed_in_rate }
+    }
+
+    pub fn with_period(mut self, period: TouPeriod) -> Tariff {
+        self.periods.push(period);
+        self
+    }
+
+    pub fn rate_at(&self, now: NaiveDateTime) -> f32 {
+        self.periods.iter()
+            .find(|p| p.covers(now))
+            .map_or(self.import_rate, |p| p.rate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostReport {
+    pub step_costs: Vec<f32>, // import cost less export revenue for each step
+    pub annual: Vec<(i32, f32, f32)>, // year, import cost, export revenue
+}
+
+impl CostReport {
+    pub fn net(&self) -> f32 {
+        self.step_costs.iter().sum()
+    }
+}
+
+// Prices the grid imports and exports of a finished run
+pub fn energy_costs(result: &SimState, tariff: &Tariff) -> CostReport {
+    let mut step_costs = Vec::new();
+    let mut annual: Vec<(i32, f32, f32)> = Vec::new();
+    for (i, date) in result.history_dates.iter().enumerate() {
+        let imported = result.grid_import_history.get(i).cloned().unwrap_or(0.);
+        let exported = result.grid_export_history.get(i).cloned().unwrap_or(0.);
+        let cost = imported / 1000. * tariff.rate_at(*date);
+        let revenue = exported / 1000. * tariff.feed_in_rate;
+        step_costs.push(cost - revenue);
+        match annual.last_mut() {
+            Some((year, total_cost, total_revenue)) if *year == date.year() => {
+                *total_cost += cost;
+                *total_revenue += revenue;
+            },
+            _ => annual.push((date.year(), cost, revenue)),
+        }
+    }
+    CostReport { step_costs, annual }
+}
+
+#[test]
+fn test_time_of_use() {
+    let tariff = Tariff::flat(0.2, 0.05)
+        .with_period(TouPeriod::new(16, 21, 0.5).weekdays())
+        .with_period(TouPeriod::new(23, 6, 0.1));
+    // 2023-01-02 is a Monday
+    let monday = chrono::NaiveDate::from_ymd_opt(2023, 1, 2).unwrap();
+    assert_eq!(tariff.rate_at(monday.and_hms_opt(17, 0, 0).unwrap()), 0.5);
+    assert_eq!(tariff.rate_at(monday.and_hms_opt(2, 0, 0).unwrap()), 0.1);
+    assert_eq!(tariff.rate_at(monday.and_hms_opt(12, 0, 0).unwrap()), 0.2);
+    let sunday = monday.pred_opt().unwrap();
+    assert_eq!(tariff.rate_at(sunday.and_hms_opt(17, 0, 0).unwrap()), 0.2)
+}
+
+#[test]
+fn test_energy_costs() {
+    let mut state = SimState::new();
+    let start = chrono::NaiveDate::from_ymd_opt(2023, 12, 31).unwrap().and_hms_opt(17, 0, 0).unwrap();
+    state.history_dates = vec![start, start + chrono::Duration::days(1)];
+    state.grid_import_history = vec![2000., 1000.];
+    state.grid_export_history = vec![0., 4000.];
+    let tariff = Tariff::flat(0.2, 0.05).with_period(TouPeriod::new(16, 21, 0.5));
+    let report = energy_costs(&state, &tariff);
+    assert_eq!(report.step_costs, vec![1., 0.3]);
+    assert_eq!(report.annual, vec![(2023, 1., 0.), (2024, 0.5, 0.2)]);
+    assert!((report.net() - 1.3).abs() < 0.0001)
+}
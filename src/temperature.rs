@@ -0,0 +1,162 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use std::f32::consts::PI;
+
+use crate::series;
+
+// Air temperature at the site, shared by every temperature-aware part of the
+// model so they all see the same conditions
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmbientTemperature {
+    Constant(f32), // degrees C
+    Monthly([f32; 12]), // mean degrees C for each month, January first
+    Series(Vec<(NaiveDateTime, f32)>), // degrees C, sorted by time
+    // Yearly sinusoid with a daily swing, coolest at 3 am and warmest at 3 pm
+    Seasonal {
+        mean: f32, // degrees C over the year
+        amplitude: f32, // degrees C from the mean to the warmest day
+        coldest_day: u32, // day of year
+        diurnal_swing: f32, // degrees C from night to afternoon
+    },
+}
+
+impl Default for AmbientTemperature {
+    fn default() -> Self {
+        AmbientTemperature::Constant(25.)
+    }
+}
+
+impl AmbientTemperature {
+    // Rough climate for a latitude: cooler and more seasonal toward the
+    // poles, with winter lagging the solstice by about a month
+    pub fn seasonal(lat: f32) -> AmbientTemperature {
+        AmbientTemperature::Seasonal {
+            mean: 28. - 0.45 * lat.abs(),
+            amplitude: 0.3 * lat.abs(),
+            coldest_day: if lat >= 0. { 20 } else { 202 },
+            diurnal_swing: 10.,
+        }
+    }
+
+    // Reads a `datetime,value` CSV as with measured data
+    pub fn from_csv<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<AmbientTemperature> {
+        series::read_csv(path).map(AmbientTemperature::Series)
+    }
+
+    pub fn at(&self, now: NaiveDateTime) -> f32 {
+        match self {
+            AmbientTemperature::Constant(t) => *t,
+            AmbientTemperature::Monthly(means) => monthly(means, now),
+            AmbientTemperature::Seasonal { mean, amplitude, coldest_day, diurnal_swing } => {
+                let day = now.ordinal0() as f32 - *coldest_day as f32;
+                let hour = crate::time_hours(now.time());
+                mean - amplitude * (2. * PI * day / 365.).cos()
+                    + diurnal_swing / 2. * (2. * PI * (hour - 15.) / 24.).cos()
+            },
+            AmbientTemperature::Series(points) => match series::interpolate(points, now) {
+                Some(t) => t,
+                // Hold the nearest end outside the series
+                None => match (points.first(), points.last()) {
+                    (Some(first), _) if now < first.0 => first.1,
+                    (_, Some(last)) => last.1,
+                    _ => AmbientTemperature::default().at(now),
+                },
+            },
+        }
+    }
+}
+
+// Lumped thermal model of the battery: it settles toward its surroundings
+// with a time constant and warms from the I²R heat of its own current
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryThermal {
+    pub time_constant: f32, // hours, a few for a bare box under a trailer, a day or more insulated
+    pub thermal_mass: f32, // Wh per degree C, about 0.003 per Wh of lithium capacity
+    pub internal_resistance: f32, // ohms at the system voltage
+}
+
+impl BatteryThermal {
+    pub fn new(time_constant: f32, thermal_mass: f32, internal_resistance: f32) -> BatteryThermal {
+        BatteryThermal { time_constant, thermal_mass, internal_resistance }
+    }
+
+    // Battery temperature after some hours, from its current temperature,
+    // the surroundings it settles toward, and the current through it
+    pub fn next(&self, temperature: f32, surroundings: f32, current: f32, hours: f32) -> f32 {
+        let decay = if self.time_constant > 0. { (-hours / self.time_constant).exp() } else { 0. };
+        let heat = current * current * self.internal_resistance * hours;
+        let rise = if self.thermal_mass > 0. { heat / self.thermal_mass } else { 0. };
+        surroundings + (temperature - surroundings) * decay + rise
+    }
+}
+
+// Linear between the middles of neighbouring months, so the table doesn't
+// step at month boundaries
+pub(crate) fn monthly(means: &[f32; 12], now: NaiveDateTime) -> f32 {
+    let month = now.month0() as i32;
+    let (m0, m1) = if now < mid_month(now.year(), month) {
+        (month - 1, month)
+    } else {
+        (month, month + 1)
+    };
+    let (t0, t1) = (mid_month(now.year(), m0), mid_month(now.year(), m1));
+    let (v0, v1) = (means[m0.rem_euclid(12) as usize], means[m1.rem_euclid(12) as usize]);
+    let fraction = (now - t0).num_seconds() as f32 / (t1 - t0).num_seconds() as f32;
+    v0 + (v1 - v0) * fraction
+}
+
+// Month may run past either end of the year
+fn mid_month(year: i32, month0: i32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(year + month0.div_euclid(12), month0.rem_euclid(12) as u32 + 1, 15).unwrap()
+        .and_hms_opt(12, 0, 0).unwrap()
+}
+
+#[test]
+fn test_monthly_interpolation() {
+    let mut means = [0.; 12];
+    means[0] = -10.;
+    means[6] = 30.;
+    let temperature = AmbientTemperature::Monthly(means);
+    let mid_january = NaiveDate::from_ymd_opt(2023, 1, 15).unwrap().and_hms_opt(12, 0, 0).unwrap();
+    assert!((temperature.at(mid_january) + 10.).abs() < 0.001);
+    // Halfway from mid December to mid January
+    let new_year = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    assert!((temperature.at(new_year) + 5.).abs() < 0.5);
+    let july = NaiveDate::from_ymd_opt(2023, 7, 31).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    assert!(temperature.at(july) > 0. && temperature.at(july) < 30.)
+}
+
+#[test]
+fn test_seasonal() {
+    let north = AmbientTemperature::seasonal(45.);
+    let winter = NaiveDate::from_ymd_opt(2023, 1, 21).unwrap().and_hms_opt(15, 0, 0).unwrap();
+    let summer = NaiveDate::from_ymd_opt(2023, 7, 22).unwrap().and_hms_opt(15, 0, 0).unwrap();
+    assert!(north.at(summer) - north.at(winter) > 25.);
+    // Afternoon is warmer than night by the diurnal swing
+    let night = summer.date().and_hms_opt(3, 0, 0).unwrap();
+    assert!((north.at(summer) - north.at(night) - 10.).abs() < 0.1);
+    let south = AmbientTemperature::seasonal(-45.);
+    assert!(south.at(winter) > south.at(summer))
+}
+
+#[test]
+fn test_series_holds_ends() {
+    let start = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let temperature = AmbientTemperature::Series(vec![
+        (start, 10.),
+        (start + chrono::Duration::hours(2), 20.),
+    ]);
+    assert_eq!(temperature.at(start + chrono::Duration::hours(1)), 15.);
+    assert_eq!(temperature.at(start - chrono::Duration::hours(1)), 10.);
+    assert_eq!(temperature.at(start + chrono::Duration::days(1)), 20.)
+}
+
+#[test]
+fn test_battery_thermal() {
+    let insulated = BatteryThermal::new(24., 4., 0.01);
+    let bare = BatteryThermal::new(2., 4., 0.01);
+    // A cold night barely reaches the insulated box
+    assert!(insulated.next(20., -10., 0., 8.) > 10.);
+    assert!(bare.next(20., -10., 0., 8.) < -9.);
+    // 100 A through 10 milliohms for an hour is 100 Wh of heat
+    assert!((bare.next(0., 0., 100., 1.) - 25.).abs() < 0.001)
+}
@@ -0,0 +1,37 @@
+// Span helpers for the optional `tracing` feature. Without the feature the
+// macro expands to nothing, so instrumented code pays no cost.
+
+#[cfg(feature = "tracing")]
+pub struct Timer {
+    _span: tracing::span::EnteredSpan,
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl Timer {
+    pub fn new(span: tracing::Span) -> Timer {
+        Timer {
+            _span: span.entered(),
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Timer {
+    // Runs while the span is still entered, so the event lands inside it
+    fn drop(&mut self) {
+        tracing::debug!(elapsed_ms = self.start.elapsed().as_secs_f64() * 1000., "finished");
+    }
+}
+
+// Enters an info span for the rest of the enclosing block and records how
+// long it took, e.g. `timed!("run_simulation", start_date = %state.start_date)`
+macro_rules! timed {
+    ($($span:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _timer = $crate::trace::Timer::new(tracing::info_span!($($span)*));
+    };
+}
+
+pub(crate) use timed;
@@ -0,0 +1,243 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::trace;
+
+// Hour to hour correlation of passing cloud
+const HOURLY_CORRELATION: f32 = 0.7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sky {
+    Clear,
+    PartlyCloudy,
+    Overcast,
+}
+
+impl Sky {
+    const ALL: [Sky; 3] = [Sky::Clear, Sky::PartlyCloudy, Sky::Overcast];
+
+    // Mean and spread of the daily fraction of clear-sky output
+    fn clearness(&self) -> (f32, f32) {
+        match self {
+            Sky::Clear => (0.95, 0.03),
+            Sky::PartlyCloudy => (0.6, 0.12),
+            Sky::Overcast => (0.25, 0.08),
+        }
+    }
+
+    // Relative spread of the hourly clearness around the day's
+    fn hourly_spread(&self) -> f32 {
+        match self {
+            Sky::Clear => 0.05,
+            Sky::PartlyCloudy => 0.35,
+            Sky::Overcast => 0.15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonthlyWeather {
+    pub clear_fraction: f32, // long run fraction of clear days
+    pub overcast_fraction: f32, // long run fraction of overcast days
+    pub persistence: f32, // chance that tomorrow simply repeats today
+}
+
+impl MonthlyWeather {
+    pub fn new(clear_fraction: f32, overcast_fraction: f32, persistence: f32) -> MonthlyWeather {
+        MonthlyWeather {
+            clear_fraction,
+            overcast_fraction,
+            persistence,
+        }
+    }
+
+    fn stationary(&self, sky: Sky) -> f32 {
+        match sky {
+            Sky::Clear => self.clear_fraction,
+            Sky::PartlyCloudy => (1. - self.clear_fraction - self.overcast_fraction).max(0.),
+            Sky::Overcast => self.overcast_fraction,
+        }
+    }
+
+    // Either repeat today, or draw from the long run mix. This keeps the
+    // monthly fractions while making runs of cloudy days more likely.
+    pub fn transition(&self, from: Sky, to: Sky) -> f32 {
+        let repeat = if from == to { self.persistence } else { 0. };
+        repeat + (1. - self.persistence) * self.stationary(to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DailyWeather {
+    pub date: NaiveDate,
+    pub sky: Sky,
+    pub clearness: f32, // fraction of clear-sky solar output
+    pub hourly: [f32; 24], // clearness for each hour of the day
+}
+
+// Step to step flicker in solar output, from clouds and haze passing faster
+// than the hourly weather
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarNoise {
+    pub std_dev: f32, // fraction of the step's output
+    pub seed: u64,
+}
+
+impl SolarNoise {
+    pub fn new(std_dev: f32, seed: u64) -> SolarNoise {
+        SolarNoise { std_dev, seed }
+    }
+
+    // Multiplier on the output of the step starting at `now`. Drawn from the
+    // seed and the time, so a rerun or a resumed run sees the same noise.
+    pub fn factor(&self, now: NaiveDateTime) -> f32 {
+        let key = self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ now.timestamp() as u64;
+        let mut rng = StdRng::seed_from_u64(key);
+        (1. + self.std_dev * standard_normal(&mut rng)).max(0.)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherGenerator {
+    pub months: [MonthlyWeather; 12],
+    pub seed: u64,
+}
+
+impl WeatherGenerator {
+    pub fn new(months: [MonthlyWeather; 12], seed: u64) -> WeatherGenerator {
+        WeatherGenerator { months, seed }
+    }
+
+    // Mid-latitude climate with grey winters and mostly clear summers
+    pub fn temperate(seed: u64) -> WeatherGenerator {
+        let clear = [0.2, 0.25, 0.3, 0.35, 0.4, 0.5, 0.6, 0.6, 0.5, 0.35, 0.2, 0.15];
+        let overcast = [0.5, 0.45, 0.35, 0.3, 0.25, 0.2, 0.1, 0.1, 0.2, 0.35, 0.5, 0.55];
+        let mut months = [MonthlyWeather::new(0., 0., 0.); 12];
+        for (i, month) in months.iter_mut().enumerate() {
+            *month = MonthlyWeather::new(clear[i], overcast[i], 0.5);
+        }
+        WeatherGenerator::new(months, seed)
+    }
+
+    pub fn generate(&self, start: NaiveDate, end: NaiveDate) -> Vec<DailyWeather> {
+        trace::timed!("generate_weather", seed = self.seed, %start, %end);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        // Separate stream so the hours don't change the sequence of days
+        let mut hour_rng = StdRng::seed_from_u64(self.seed ^ 0x9e37_79b9_7f4a_7c15);
+        let mut cloud = 0.;
+        let mut days = Vec::new();
+        let mut date = start;
+        let first = self.months[start.month0() as usize];
+        let mut sky = choose(&mut rng, |s| first.stationary(s));
+        while date <= end {
+            let (mean, spread) = sky.clearness();
+            let clearness = (mean + spread * standard_normal(&mut rng)).clamp(0.05, 1.);
+            let mut hourly = [0.; 24];
+            for hour in hourly.iter_mut() {
+                // AR(1) so cloudy spells last a few hours
+                cloud = HOURLY_CORRELATION * cloud
+                    + (1. - HOURLY_CORRELATION * HOURLY_CORRELATION).sqrt() * standard_normal(&mut hour_rng);
+                *hour = (clearness * (1. + sky.hourly_spread() * cloud)).clamp(0.05, 1.);
+            }
+            days.push(DailyWeather { date, sky, clearness, hourly });
+
+            date = date.succ_opt().unwrap();
+            let month = self.months[date.month0() as usize];
+            let today = sky;
+            sky = choose(&mut rng, |s| month.transition(today, s));
+        }
+        days
+    }
+}
+
+fn choose(rng: &mut StdRng, weight: impl Fn(Sky) -> f32) -> Sky {
+    let total: f32 = Sky::ALL.iter().map(|s| weight(*s)).sum();
+    let mut draw = rng.gen::<f32>() * total;
+    for sky in Sky::ALL {
+        draw -= weight(sky);
+        if draw < 0. {
+            return sky;
+        }
+    }
+    Sky::Overcast
+}
+
+// Box-Muller transform
+pub fn standard_normal<R: Rng>(rng: &mut R) -> f32 {
+    let u1 = rng.gen::<f32>().max(f32::MIN_POSITIVE);
+    let u2 = rng.gen::<f32>();
+    (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+}
+
+// Clearness on a date, clear sky if the date wasn't generated
+pub fn clearness_on(days: &[DailyWeather], date: NaiveDate) -> f32 {
+    let i = days.partition_point(|d| d.date < date);
+    match days.get(i) {
+        Some(day) if day.date == date => day.clearness,
+        _ => 1.,
+    }
+}
+
+// Clearness for the hour containing now, clear sky if the date wasn't generated
+pub fn clearness_at(days: &[DailyWeather], now: NaiveDateTime) -> f32 {
+    let i = days.partition_point(|d| d.date < now.date());
+    match days.get(i) {
+        Some(day) if day.date == now.date() => day.hourly[now.hour() as usize],
+        _ => 1.,
+    }
+}
+
+#[test]
+fn test_transition_rows_sum_to_one() {
+    let month = MonthlyWeather::new(0.3, 0.4, 0.6);
+    for from in Sky::ALL {
+        let total: f32 = Sky::ALL.iter().map(|to| month.transition(from, *to)).sum();
+        assert!((total - 1.).abs() < 0.0001)
+    }
+}
+
+#[test]
+fn test_generated_fractions_and_seed() {
+    let generator = WeatherGenerator::temperate(7);
+    let start = NaiveDate::from_ymd_opt(2023, 7, 1).unwrap();
+    let end = NaiveDate::from_ymd_opt(2033, 7, 31).unwrap();
+    let days = generator.generate(start, end);
+    let july: Vec<&DailyWeather> = days.iter().filter(|d| d.date.month() == 7).collect();
+    let clear = july.iter().filter(|d| d.sky == Sky::Clear).count() as f32 / july.len() as f32;
+    assert!((clear - 0.6).abs() < 0.1);
+    assert_eq!(days, generator.generate(start, end));
+    assert_ne!(days, WeatherGenerator::temperate(8).generate(start, end))
+}
+
+#[test]
+fn test_hourly_clearness() {
+    let start = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+    let days = WeatherGenerator::temperate(4).generate(start, start + chrono::Duration::days(400));
+    let partly: Vec<&DailyWeather> = days.iter().filter(|d| d.sky == Sky::PartlyCloudy).collect();
+    // Hours vary around the day's clearness
+    let mean = |d: &DailyWeather| d.hourly.iter().sum::<f32>() / 24.;
+    let offset = partly.iter().map(|d| mean(d) - d.clearness).sum::<f32>() / partly.len() as f32;
+    assert!(offset.abs() < 0.05);
+    assert!(partly.iter().all(|d| d.hourly.iter().any(|h| (h - d.clearness).abs() > 0.05)));
+    // and neighbouring hours are alike
+    let steps: Vec<f32> = partly.iter().flat_map(|d| d.hourly.windows(2).map(|w| (w[1] - w[0]).abs())).collect();
+    let spread: Vec<f32> = partly.iter().flat_map(|d| d.hourly.iter().map(|h| (h - d.clearness).abs())).collect();
+    let average = |v: &[f32]| v.iter().sum::<f32>() / v.len() as f32;
+    assert!(average(&steps) < average(&spread));
+    let noon = days[0].date.and_hms_opt(12, 30, 0).unwrap();
+    assert_eq!(clearness_at(&days, noon), days[0].hourly[12])
+}
+
+#[test]
+fn test_solar_noise() {
+    let noise = SolarNoise::new(0.2, 11);
+    let start = NaiveDate::from_ymd_opt(2023, 4, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let factors: Vec<f32> = (0..2000).map(|i| noise.factor(start + chrono::Duration::minutes(15 * i))).collect();
+    let mean = factors.iter().sum::<f32>() / factors.len() as f32;
+    let spread = (factors.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / factors.len() as f32).sqrt();
+    assert!((mean - 1.).abs() < 0.02 && (spread - 0.2).abs() < 0.02);
+    // Reproducible, and different for another seed
+    assert_eq!(noise.factor(start), SolarNoise::new(0.2, 11).factor(start));
+    assert_ne!(noise.factor(start), SolarNoise::new(0.2, 12).factor(start))
+}
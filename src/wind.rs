@@ -0,0 +1,131 @@
+use chrono::{Datelike, Duration, NaiveDateTime};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::f32::consts::PI;
+
+use crate::series;
+use crate::weather::standard_normal;
+
+// Wind speeds are taken as measured at the usual 10 m mast height
+const MEASUREMENT_HEIGHT: f32 = 10.;
+// Hour to hour correlation of the wind
+const HOURLY_CORRELATION: f32 = 0.9;
+
+// Turbine described by its power curve: nothing below the cut-in speed,
+// rising with the cube of the speed to the rated power, and shut down for
+// protection above the cut-out speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindTurbine {
+    pub rated_power: f32, // watts
+    pub cut_in: f32, // m/s
+    pub rated_speed: f32, // m/s
+    pub cut_out: f32, // m/s
+    pub hub_height: f32, // m
+}
+
+impl WindTurbine {
+    pub fn new(rated_power: f32, cut_in: f32, rated_speed: f32, cut_out: f32, hub_height: f32) -> WindTurbine {
+        WindTurbine { rated_power, cut_in, rated_speed, cut_out, hub_height }
+    }
+
+    // Typical small battery-charging turbine on a short tower
+    pub fn small(rated_power: f32) -> WindTurbine {
+        WindTurbine::new(rated_power, 3., 12., 25., 10.)
+    }
+
+    // Speed at the hub from the speed at the mast, by the one-seventh power law
+    pub fn hub_speed(&self, speed: f32) -> f32 {
+        if self.hub_height <= 0. {
+            return speed;
+        }
+        speed * (self.hub_height / MEASUREMENT_HEIGHT).powf(1. / 7.)
+    }
+
+    // Watts at a wind speed at the hub
+    pub fn power(&self, speed: f32) -> f32 {
+        if speed < self.cut_in || speed >= self.cut_out {
+            0.
+        } else if speed >= self.rated_speed {
+            self.rated_power
+        } else {
+            let (low, high) = (self.cut_in.powi(3), self.rated_speed.powi(3));
+            self.rated_power * (speed.powi(3) - low) / (high - low)
+        }
+    }
+}
+
+// Synthetic hourly wind: gusty, persistent from hour to hour, and windier
+// in one season, as winter is at most sites
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindGenerator {
+    pub mean_speed: f32, // m/s over the year at the mast
+    pub seasonal: f32, // fraction the monthly mean swings above and below the yearly
+    pub windiest_day: u32, // day of year
+    pub seed: u64,
+}
+
+impl WindGenerator {
+    pub fn new(mean_speed: f32, seed: u64) -> WindGenerator {
+        WindGenerator { mean_speed, seasonal: 0.25, windiest_day: 15, seed }
+    }
+
+    // The mean for the southern hemisphere, windiest in July instead
+    pub fn for_latitude(mean_speed: f32, lat: f32, seed: u64) -> WindGenerator {
+        let mut generator = WindGenerator::new(mean_speed, seed);
+        if lat < 0. {
+            generator.windiest_day = 197;
+        }
+        generator
+    }
+
+    // Hourly speeds in m/s. The speed is the length of a vector whose two
+    // components wander as correlated normals, giving the Rayleigh spread
+    // (a Weibull shape of 2) usual for wind.
+    pub fn generate(&self, start: NaiveDateTime, end: NaiveDateTime) -> Vec<(NaiveDateTime, f32)> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let innovation = (1. - HOURLY_CORRELATION * HOURLY_CORRELATION).sqrt();
+        let (mut x, mut y) = (standard_normal(&mut rng), standard_normal(&mut rng));
+        let mut speeds = Vec::new();
+        let mut time = start;
+        while time <= end {
+            let day = time.ordinal0() as f32 - self.windiest_day as f32;
+            let mean = self.mean_speed * (1. + self.seasonal * (2. * PI * day / 365.).cos());
+            // A Rayleigh variable of unit scale averages the square root of pi/2
+            speeds.push((time, mean / (PI / 2.).sqrt() * (x * x + y * y).sqrt()));
+            x = HOURLY_CORRELATION * x + innovation * standard_normal(&mut rng);
+            y = HOURLY_CORRELATION * y + innovation * standard_normal(&mut rng);
+            time += Duration::hours(1);
+        }
+        speeds
+    }
+}
+
+// Speed at the mast at a time, calm outside the series
+pub fn speed_at(speeds: &[(NaiveDateTime, f32)], now: NaiveDateTime) -> f32 {
+    series::interpolate(speeds, now).unwrap_or(0.)
+}
+
+#[test]
+fn test_power_curve() {
+    let turbine = WindTurbine::small(400.);
+    assert_eq!(turbine.power(2.), 0.);
+    assert_eq!(turbine.power(15.), 400.);
+    assert_eq!(turbine.power(30.), 0.);
+    // The cube law makes half the rated speed worth far less than half the power
+    assert!(turbine.power(6.) < 0.15 * 400.);
+    assert!((turbine.hub_speed(5.) - 5.).abs() < 0.0001);
+    assert!(WindTurbine::new(400., 3., 12., 25., 30.).hub_speed(5.) > 5.5)
+}
+
+#[test]
+fn test_generated_wind() {
+    let start = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let generator = WindGenerator::new(5., 7);
+    let speeds = generator.generate(start, start + Duration::days(365));
+    let mean = |from: usize, to: usize| speeds[from..to].iter().map(|(_, s)| s).sum::<f32>() / (to - from) as f32;
+    assert!((mean(0, speeds.len()) - 5.).abs() < 0.5);
+    // Windier in winter than summer
+    assert!(mean(0, 24 * 60) > mean(24 * 180, 24 * 240));
+    assert!(speeds.iter().all(|(_, s)| *s >= 0.));
+    assert_eq!(speeds, generator.generate(start, start + Duration::days(365)))
+}
@@ -0,0 +1,192 @@
+pub const COPPER_RESISTIVITY: f32 = 1.72e-8; // ohm m
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CableRun {
+    pub length: f32, // m, one way
+    pub resistance: f32, // ohm/m, per conductor
+}
+
+impl CableRun {
+    pub fn new(length: f32, resistance: f32) -> CableRun {
+        CableRun {
+            length,
+            resistance,
+        }
+    }
+
+    pub fn from_awg(length: f32, gauge: u32) -> CableRun {
+        CableRun::new(length, COPPER_RESISTIVITY / (awg_area(gauge) * 1e-6))
+    }
+
+    pub fn from_cross_section(length: f32, area: f32) -> CableRun {
+        CableRun::new(length, COPPER_RESISTIVITY / (area * 1e-6))
+    }
+
+    // Out and back, so both conductors count
+    pub fn loop_resistance(&self) -> f32 {
+        2. * self.length * self.resistance
+    }
+
+    pub fn voltage_drop(&self, current: f32) -> f32 {
+        current * self.loop_resistance()
+    }
+
+    pub fn power_loss(&self, current: f32) -> f32 {
+        current * current * self.loop_resistance()
+    }
+}
+
+// Copper cross section in mm^2
+pub fn awg_area(gauge: u32) -> f32 {
+    let diameter = 0.127 * 92_f32.powf((36. - gauge as f32) / 39.);
+    std::f32::consts::PI / 4. * diameter * diameter
+}
+
+#[test]
+fn test_awg_area() {
+    assert!((awg_area(10) - 5.26).abs() < 0.01);
+    assert!((awg_area(4) - 21.15).abs() < 0.05)
+}
+
+// Cable runs between the components. A missing run is treated as lossless.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Wiring {
+    pub array: Option<CableRun>, // array to charge controller
+    pub controller: Option<CableRun>, // charge controller to battery
+    pub load: Option<CableRun>, // battery to loads
+    // Fraction lost in connections and cabling not given as runs, e.g. 0.03,
+    // taken on the way to the battery and again on the way to the loads
+    pub connection_loss: f32,
+}
+
+impl Wiring {
+    // Watts lost on the way from the array to the battery
+    pub fn charging_loss(&self, solar_power: f32, voltage: f32) -> f32 {
+        if voltage <= 0. {
+            return 0.;
+        }
+        let current = solar_power / voltage;
+        let loss = [self.array, self.controller].iter()
+            .flatten()
+            .map(|run| run.power_loss(current))
+            .sum::<f32>();
+        (loss + solar_power * self.connection_loss).min(solar_power)
+    }
+
+    // Extra watts the battery has to supply to deliver load_power at the loads
+    pub fn load_loss(&self, load_power: f32, voltage: f32) -> f32 {
+        let connections = load_power * self.connection_loss;
+        match self.load {
+            Some(run) if voltage > 0. => run.power_loss(load_power / voltage) + connections,
+            _ => connections,
+        }
+    }
+}
+
+#[test]
+fn test_twelve_volt_home_run() {
+    // 400 W at 12 V down 15 m of 10 AWG loses over a quarter of the energy
+    let wiring = Wiring {
+        array: Some(CableRun::from_awg(15., 10)),
+        ..Wiring::default()
+    };
+    let loss = wiring.charging_loss(400., 12.);
+    assert!((loss / 400. - 0.27).abs() < 0.01);
+    assert_eq!(wiring.load_loss(100., 12.), 0.)
+}
+
+#[test]
+fn test_connection_loss() {
+    let wiring = Wiring {
+        connection_loss: 0.05,
+        ..Wiring::default()
+    };
+    assert_eq!(wiring.charging_loss(400., 12.), 20.);
+    assert_eq!(wiring.load_loss(100., 12.), 5.);
+    assert_eq!(wiring.load_loss(100., 0.), 5.)
+}
+
+// Copper ampacity at 75 C, 14 AWG down to 1/0 (gauge 0)
+const AMPACITY: [(u32, f32); 8] = [
+    (14, 20.), (12, 25.), (10, 35.), (8, 50.), (6, 65.), (4, 85.), (2, 115.), (0, 150.),
+];
+const FUSE_RATINGS: [f32; 20] = [
+    5., 10., 15., 20., 25., 30., 40., 50., 60., 70., 80., 100., 125., 150., 175., 200., 225., 250., 300., 400.,
+];
+pub const MAX_VOLTAGE_DROP: f32 = 0.03; // fraction of system voltage
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireSize {
+    pub run: &'static str,
+    pub peak_current: f32, // amps
+    pub gauge: Option<u32>, // AWG, 0 for 1/0, None when nothing up to 1/0 is enough
+    pub fuse: Option<f32>, // amps
+}
+
+// Sizes each cable run from the peak currents of a finished run. Wire must
+// carry the fuse rating (125% of the peak current, rounded up to a standard
+// size) and, where the run's length is known, keep the drop under 3%. The
+// array runs are sized for its rating, with the rear of bifacial panels
+// adding up to their bifaciality, as step averages understate its peak.
+pub fn size_wiring(result: &crate::SimState) -> Vec<WireSize> {
+    let voltage = result.system_voltage;
+    let hours = crate::step_hours(result);
+    let peak = |watts: &mut dyn Iterator<Item = f32>| {
+        if voltage > 0. { watts.fold(0., f32::max) / voltage } else { 0. }
+    };
+    let rated = result.solar_nominal_output * (1. + result.bifaciality);
+    let array_current = peak(&mut result.solar_history.iter().cloned().chain([rated]));
+    let load_current = peak(&mut result.served_load_history.iter().zip(&result.inverter_history)
+        .map(|(load, inverter)| load + if hours > 0. { inverter / hours } else { 0. }));
+    vec![
+        size_run("Array", array_current, result.wiring.array, voltage),
+        size_run("Controller", array_current, result.wiring.controller, voltage),
+        size_run("Load", load_current, result.wiring.load, voltage),
+    ]
+}
+
+fn size_run(run: &'static str, peak_current: f32, cable: Option<CableRun>, voltage: f32) -> WireSize {
+    let fuse = FUSE_RATINGS.iter().cloned().find(|f| *f >= 1.25 * peak_current);
+    let gauge = fuse.and_then(|fuse| {
+        AMPACITY.iter()
+            .find(|(gauge, ampacity)| {
+                let drop = match cable {
+                    Some(cable) => CableRun::from_awg(cable.length, *gauge).voltage_drop(peak_current),
+                    None => 0.,
+                };
+                *ampacity >= fuse && drop <= MAX_VOLTAGE_DROP * voltage
+            })
+            .map(|(gauge, _)| *gauge)
+    });
+    WireSize { run, peak_current, gauge, fuse }
+}
+
+#[test]
+fn test_size_run() {
+    // 20 A needs a 25 A fuse and 12 AWG when the run is short
+    let short = size_run("Load", 20., Some(CableRun::new(1., 0.)), 12.);
+    assert_eq!((short.fuse, short.gauge), (Some(25.), Some(12)));
+    // Over 5 m at 12 V the drop forces much heavier cable
+    let long = size_run("Load", 20., Some(CableRun::new(5., 0.)), 12.);
+    assert_eq!(long.gauge, Some(6));
+    // The same power at 48 V is a quarter of the current
+    let high_voltage = size_run("Load", 5., Some(CableRun::new(5., 0.)), 48.);
+    assert_eq!((high_voltage.fuse, high_voltage.gauge), (Some(10.), Some(14)));
+    assert_eq!(size_run("Array", 500., None, 12.).fuse, None)
+}
+
+#[test]
+fn test_size_wiring_for_daily_steps() {
+    let mut state = crate::SimState::new();
+    state.end_date = state.start_date + chrono::Duration::days(3);
+    state.step_size = chrono::Duration::days(1);
+    state.solar_nominal_output = 400.;
+    let result = crate::run_simulation(&state);
+    // Daily averages are a fraction of the 33 A the array delivers at noon
+    let array = size_wiring(&result)[0];
+    assert!(result.solar_history.iter().all(|p| *p / state.system_voltage < 15.));
+    assert!((array.peak_current - 400. / state.system_voltage).abs() < 0.01);
+    assert_eq!(array.fuse, Some(50.));
+    state.bifaciality = 0.5;
+    assert_eq!(size_wiring(&crate::run_simulation(&state))[0].fuse, Some(70.))
+}